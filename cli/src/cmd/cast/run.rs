@@ -1,4 +1,8 @@
-use crate::{cmd::Cmd, init_progress, update_progress, utils::try_consume_config_rpc_url};
+use crate::{
+    cmd::Cmd,
+    init_progress, update_progress,
+    utils::{self, try_consume_config_rpc_url},
+};
 use cast::trace::{identifier::SignaturesIdentifier, CallTraceDecoder};
 use clap::Parser;
 use ethers::{
@@ -14,7 +18,10 @@ use forge::{
         inspector::cheatcodes::util::configure_tx_env, opts::EvmOpts, Backend, DeployResult,
         ExecutorBuilder, RawCallResult,
     },
-    trace::{identifier::EtherscanIdentifier, CallTraceArena, CallTraceDecoderBuilder, TraceKind},
+    trace::{
+        identifier::{EtherscanIdentifier, KnownContractsIdentifier},
+        CallTraceArena, CallTraceDecoderBuilder, TraceKind,
+    },
 };
 use foundry_common::try_get_http_provider;
 use foundry_config::{find_project_root_path, Config};
@@ -92,7 +99,7 @@ impl RunArgs {
         // tracing will be enabled only for the targeted transaction
         let builder = ExecutorBuilder::default()
             .with_config(env)
-            .with_spec(crate::utils::evm_spec(&config.evm_version));
+            .with_spec(utils::evm_spec(&config.evm_version));
 
         let mut executor = builder.build(db);
 
@@ -177,8 +184,10 @@ impl RunArgs {
             }
         };
 
-        let mut etherscan_identifier =
-            EtherscanIdentifier::new(&config, evm_opts.get_remote_chain_id())?;
+        let remote_chain_id = evm_opts.get_remote_chain_id();
+        let mut etherscan_identifier = EtherscanIdentifier::new(&config, remote_chain_id)?;
+        let mut known_identifier =
+            remote_chain_id.map(KnownContractsIdentifier::new).unwrap_or_default();
 
         let labeled_addresses: BTreeMap<Address, String> = self
             .label
@@ -203,8 +212,15 @@ impl RunArgs {
         )?);
 
         for (_, trace) in &mut result.traces {
+            decoder.identify(trace, &mut known_identifier);
             decoder.identify(trace, &mut etherscan_identifier);
         }
+        utils::fill_ens_labels(
+            evm_opts.fork_url.as_deref(),
+            remote_chain_id,
+            result.traces.iter().map(|(_, trace)| trace),
+            &mut decoder.labels,
+        )?;
 
         if self.debug {
             let (sources, bytecode) = etherscan_identifier.get_compiled_contracts().await?;
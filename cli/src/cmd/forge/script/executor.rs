@@ -18,7 +18,6 @@ use ethers::{
 use forge::{
     executor::{inspector::CheatsConfig, Backend, ExecutorBuilder},
     trace::CallTraceDecoder,
-    CallKind,
 };
 use std::collections::VecDeque;
 use tracing::trace;
@@ -157,7 +156,7 @@ impl ScriptArgs {
                         .iter()
                         .flat_map(|(_, traces)| {
                             traces.arena.iter().filter_map(|node| {
-                                if matches!(node.kind(), CallKind::Create | CallKind::Create2) {
+                                if node.kind().is_create() {
                                     return Some(AdditionalContract {
                                         opcode: node.kind(),
                                         address: node.trace.address,
@@ -1,5 +1,9 @@
 //! script command
-use crate::{cmd::forge::build::BuildArgs, opts::MultiWallet, utils::parse_ether_value};
+use crate::{
+    cmd::forge::build::BuildArgs,
+    opts::MultiWallet,
+    utils::{self, parse_ether_value},
+};
 use cast::{decode, executor::inspector::DEFAULT_CREATE2_DEPLOYER};
 use clap::{Parser, ValueHint};
 use dialoguer::Confirm;
@@ -22,11 +26,13 @@ use forge::{
     decode::decode_console_logs,
     executor::{opts::EvmOpts, Backend},
     trace::{
-        identifier::{EtherscanIdentifier, LocalTraceIdentifier, SignaturesIdentifier},
+        identifier::{
+            EtherscanIdentifier, KnownContractsIdentifier, LocalTraceIdentifier,
+            SignaturesIdentifier,
+        },
         CallTraceArena, CallTraceDecoder, CallTraceDecoderBuilder, RawOrDecodedCall,
         RawOrDecodedReturnData, TraceKind,
     },
-    CallKind,
 };
 use foundry_common::{
     abi::format_token, evm::EvmArgs, ContractsByArtifact, CONTRACT_MAX_SIZE, SELECTOR_LEN,
@@ -179,10 +185,11 @@ impl ScriptArgs {
         result: &mut ScriptResult,
         known_contracts: &ContractsByArtifact,
     ) -> eyre::Result<CallTraceDecoder> {
-        let mut etherscan_identifier = EtherscanIdentifier::new(
-            &script_config.config,
-            script_config.evm_opts.get_remote_chain_id(),
-        )?;
+        let remote_chain_id = script_config.evm_opts.get_remote_chain_id();
+        let mut etherscan_identifier =
+            EtherscanIdentifier::new(&script_config.config, remote_chain_id)?;
+        let mut known_identifier =
+            remote_chain_id.map(KnownContractsIdentifier::new).unwrap_or_default();
 
         let mut local_identifier = LocalTraceIdentifier::new(known_contracts);
         let mut decoder =
@@ -195,8 +202,15 @@ impl ScriptArgs {
 
         for (_, trace) in &mut result.traces {
             decoder.identify(trace, &mut local_identifier);
+            decoder.identify(trace, &mut known_identifier);
             decoder.identify(trace, &mut etherscan_identifier);
         }
+        utils::fill_ens_labels(
+            script_config.evm_opts.fork_url.as_deref(),
+            remote_chain_id,
+            result.traces.iter().map(|(_, trace)| trace),
+            &mut decoder.labels,
+        )?;
         Ok(decoder)
     }
 
@@ -492,12 +506,10 @@ impl ScriptArgs {
         }
 
         // From traces
-        let create_nodes = result.traces.iter().flat_map(|(_, traces)| {
-            traces
-                .arena
-                .iter()
-                .filter(|node| matches!(node.kind(), CallKind::Create | CallKind::Create2))
-        });
+        let create_nodes = result
+            .traces
+            .iter()
+            .flat_map(|(_, traces)| traces.arena.iter().filter(|node| node.kind().is_create()));
         let mut unknown_c = 0usize;
         for node in create_nodes {
             // Calldata == init code
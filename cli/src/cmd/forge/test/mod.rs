@@ -15,7 +15,10 @@ use forge::{
     gas_report::GasReport,
     result::{SuiteResult, TestKind, TestResult},
     trace::{
-        identifier::{EtherscanIdentifier, LocalTraceIdentifier, SignaturesIdentifier},
+        identifier::{
+            EtherscanIdentifier, KnownContractsIdentifier, LocalTraceIdentifier,
+            SignaturesIdentifier,
+        },
         CallTraceDecoderBuilder, TraceKind,
     },
     MultiContractRunner, MultiContractRunnerBuilder, TestOptions,
@@ -501,6 +504,12 @@ fn test(
         let remote_chain_id = runner.evm_opts.get_remote_chain_id();
         // Do not re-query etherscan for contracts that you've already queried today.
         let mut etherscan_identifier = EtherscanIdentifier::new(&config, remote_chain_id)?;
+        // Label well-known contracts of the forked chain
+        let mut known_identifier =
+            remote_chain_id.map(KnownContractsIdentifier::new).unwrap_or_default();
+        // Label addresses with their ENS names when forking mainnet
+        let mut ens_labeler =
+            utils::ens_labeler(runner.evm_opts.fork_url.as_deref(), remote_chain_id)?;
 
         // Set up test reporter channel
         let (tx, rx) = channel::<(String, SuiteResult)>();
@@ -556,6 +565,7 @@ fn test(
                     let rt = RuntimeOrHandle::new();
                     for (kind, trace) in &mut result.traces {
                         decoder.identify(trace, &mut local_identifier);
+                        decoder.identify(trace, &mut known_identifier);
                         decoder.identify(trace, &mut etherscan_identifier);
 
                         let should_include = match kind {
@@ -572,6 +582,12 @@ fn test(
                             _ => false,
                         };
 
+                        if let (true, Some(labeler)) = (should_include, &mut ens_labeler) {
+                            let addresses =
+                                trace.addresses().into_iter().map(|(address, _)| *address);
+                            labeler.fill_labels(addresses, &mut decoder.labels);
+                        }
+
                         // We decode the trace if we either need to build a gas report or we need
                         // to print it
                         if should_include || gas_reporting {
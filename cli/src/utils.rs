@@ -3,12 +3,17 @@ use ethers::{
     abi::token::{LenientTokenizer, Tokenizer},
     prelude::TransactionReceipt,
     solc::EvmVersion,
-    types::U256,
+    types::{Address, Chain, U256},
     utils::format_units,
 };
-use forge::executor::SpecId;
+use forge::{
+    executor::{fork::BlockingProvider, SpecId},
+    trace::{identifier::EnsLabeler, CallTraceArena},
+};
+use foundry_common::{try_get_http_provider, RetryProvider};
 use foundry_config::Config;
 use std::{
+    collections::{HashMap, HashSet},
     future::Future,
     ops::Mul,
     path::Path,
@@ -146,6 +151,49 @@ pub fn block_on<F: Future>(future: F) -> F::Output {
     rt.block_on(future)
 }
 
+/// Returns a labeler of the ENS names of addresses if forking mainnet, where ENS is deployed
+pub fn ens_labeler(
+    fork_url: Option<&str>,
+    chain: Option<Chain>,
+) -> eyre::Result<Option<EnsLabeler<RetryProvider>>> {
+    match (fork_url, chain) {
+        (Some(url), Some(Chain::Mainnet)) => {
+            let provider = BlockingProvider::try_new(try_get_http_provider(url)?)?;
+            Ok(Some(EnsLabeler::new(provider)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Adds the ENS names of the addresses called in the `traces` to `labels` if forking mainnet, see
+/// [ens_labeler]. Addresses that are already labeled keep their label.
+///
+/// Unlike [EnsLabeler::fill_labels], this can be called within a runtime, since the labeler is
+/// created, used and dropped in place.
+pub fn fill_ens_labels<'a>(
+    fork_url: Option<&str>,
+    chain: Option<Chain>,
+    traces: impl IntoIterator<Item = &'a CallTraceArena>,
+    labels: &mut HashMap<Address, String>,
+) -> eyre::Result<()> {
+    let fill = || -> eyre::Result<()> {
+        if let Some(mut labeler) = ens_labeler(fork_url, chain)? {
+            let addresses = traces
+                .into_iter()
+                .flat_map(|trace| trace.addresses())
+                .map(|(address, _)| *address)
+                .collect::<HashSet<_>>();
+            labeler.fill_labels(addresses, labels);
+        }
+        Ok(())
+    };
+    // The labeler blocks on its own runtime, which panics within another runtime
+    match tokio::runtime::Handle::try_current() {
+        Ok(_) => tokio::task::block_in_place(fill),
+        Err(_) => fill(),
+    }
+}
+
 /// Conditionally print a message
 ///
 /// This macro accepts a predicate and the message to print if the predicate is tru
@@ -272,6 +320,20 @@ mod tests {
     use foundry_common::fs;
     use std::{env, fs::File, io::Write};
 
+    #[test]
+    fn only_labels_ens_names_on_mainnet_forks() {
+        assert!(ens_labeler(None, Some(Chain::Mainnet)).unwrap().is_none());
+        assert!(ens_labeler(Some("http://localhost:8545"), Some(Chain::Goerli)).unwrap().is_none());
+        assert!(ens_labeler(Some("http://localhost:8545"), Some(Chain::Mainnet))
+            .unwrap()
+            .is_some());
+
+        // nothing is requested without a mainnet fork
+        let mut labels = HashMap::new();
+        fill_ens_labels(None, None, [&CallTraceArena::default()], &mut labels).unwrap();
+        assert!(labels.is_empty());
+    }
+
     #[test]
     fn foundry_path_ext_works() {
         let p = Path::new("contracts/MyTest.t.sol");
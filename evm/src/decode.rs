@@ -4,7 +4,7 @@ use crate::{
     error::ERROR_PREFIX,
 };
 use ethers::{
-    abi::{decode, AbiDecode, Contract as Abi, ParamType, RawLog, Token},
+    abi::{decode, ethabi::AbiError, AbiDecode, Contract as Abi, ParamType, RawLog, Token},
     contract::EthLogDecode,
     prelude::U256,
     types::Log,
@@ -13,6 +13,10 @@ use foundry_common::{abi::format_token, SELECTOR_LEN};
 use itertools::Itertools;
 use once_cell::sync::Lazy;
 use revm::Return;
+use std::{collections::HashMap, fmt};
+
+/// Custom errors indexed by their selector, see [errors_by_selector]
+pub type ErrorsBySelector = HashMap<[u8; SELECTOR_LEN], Vec<AbiError>>;
 
 /// Decode a set of logs, only returning logs from DSTest logging events and Hardhat's `console.log`
 pub fn decode_console_logs(logs: &[Log]) -> Vec<String> {
@@ -65,12 +69,148 @@ pub fn decode_console_log(log: &Log) -> Option<String> {
     Some(decoded)
 }
 
+/// Indexes the custom errors of the ABI by their selector, so that reverts can be decoded without
+/// scanning all errors of the ABI.
+///
+/// If multiple distinct errors share a selector, all of them are kept in the order of the ABI, so
+/// the revert data is decoded with the first one that can decode it, see [decode_abi_error].
+pub fn errors_by_selector(abi: &Abi) -> ErrorsBySelector {
+    let mut errors = ErrorsBySelector::new();
+    for error in abi.errors() {
+        let mut selector = [0u8; SELECTOR_LEN];
+        selector.copy_from_slice(&error.signature()[..SELECTOR_LEN]);
+        let same_selector = errors.entry(selector).or_default();
+        if !same_selector.contains(error) {
+            same_selector.push(error.clone());
+        }
+    }
+    errors
+}
+
+/// Decodes the revert data `err` with the first of the custom `errors` with its selector that can
+/// decode it, returning the error and its parameters
+pub fn decode_abi_error<'a>(
+    err: &[u8],
+    errors: &'a ErrorsBySelector,
+) -> Option<(&'a AbiError, Vec<Token>)> {
+    let (selector, data) = (err.get(..SELECTOR_LEN)?, &err[SELECTOR_LEN..]);
+    errors.get(selector)?.iter().find_map(|error| Some((error, error.decode(data).ok()?)))
+}
+
+/// Returns the reason of a solidity builtin `Panic(uint256)` with the given code
+///
+/// ref: <https://docs.soliditylang.org/en/latest/control-structures.html#panic-via-assert-and-error-via-require>
+pub fn panic_reason(code: U256) -> Option<&'static str> {
+    if code > U256::from(u8::MAX) {
+        return None
+    }
+    let reason = match code.low_u32() {
+        0x00 => "generic compiler inserted panic",
+        0x01 => "assertion failed",
+        0x11 => "arithmetic underflow or overflow",
+        0x12 => "division or modulo by zero",
+        0x21 => "conversion into non-existent enum type",
+        0x22 => "incorrectly encoded storage byte array",
+        0x31 => "`pop()` on empty array",
+        0x32 => "array out-of-bounds access",
+        0x41 => "memory allocation overflow",
+        0x51 => "call to a zero-initialized variable of internal function type",
+        _ => return None,
+    };
+    Some(reason)
+}
+
+/// The data of a revert, told apart by its selector, see [RevertData::new]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RevertData {
+    /// A revert without data, e.g. `revert()` or a `require` without a reason
+    Empty,
+    /// A revert reason string, i.e. `Error(string)` or the error of a failed cheatcode, which may
+    /// be empty as in `revert("")`
+    Reason(String),
+    /// A builtin `Panic(uint256)` with its code
+    Panic(U256),
+    /// A custom error with its selector and ABI encoded arguments
+    Custom { selector: [u8; SELECTOR_LEN], args: Vec<u8> },
+    /// Data that is too short for a selector, or an `Error(string)` or `Panic(uint256)` whose
+    /// arguments can't be decoded
+    Raw(Vec<u8>),
+}
+
+impl RevertData {
+    /// Tells apart the revert data by its selector, without trying to decode custom errors
+    pub fn new(err: &[u8]) -> Self {
+        if err.is_empty() {
+            return RevertData::Empty
+        }
+        // a failed cheatcode, see [crate::error::encode_error]
+        if let Some(reason) =
+            err.strip_prefix(ERROR_PREFIX.as_slice()).and_then(|data| String::decode(data).ok())
+        {
+            return RevertData::Reason(reason)
+        }
+        if err.len() < SELECTOR_LEN {
+            return RevertData::Raw(err.to_vec())
+        }
+        let (selector, args) = err.split_at(SELECTOR_LEN);
+        match selector {
+            // keccak(Error(string))
+            [8, 195, 121, 160] => String::decode(args)
+                .map(RevertData::Reason)
+                .unwrap_or_else(|_| RevertData::Raw(err.to_vec())),
+            // keccak(Panic(uint256))
+            [78, 72, 123, 113] => U256::decode(args)
+                .map(RevertData::Panic)
+                .unwrap_or_else(|_| RevertData::Raw(err.to_vec())),
+            _ => RevertData::Custom {
+                selector: selector.try_into().expect("selector has 4 bytes"),
+                args: args.to_vec(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for RevertData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RevertData::Empty => write!(f, "<empty revert data>"),
+            RevertData::Reason(reason) => write!(f, r#""{}""#, reason),
+            RevertData::Panic(code) => match panic_reason(*code) {
+                Some(reason) => write!(f, "{} ({:#x})", reason, code),
+                None => write!(f, "unknown panic code ({:#x})", code),
+            },
+            RevertData::Custom { selector, args } => {
+                write!(f, "custom error 0x{}", hex::encode(selector))?;
+                if !args.is_empty() {
+                    write!(f, ":0x{}", hex::encode(args))?;
+                }
+                Ok(())
+            }
+            RevertData::Raw(data) => write!(f, "0x{}", hex::encode(data)),
+        }
+    }
+}
+
 /// Given an ABI encoded error string with the function signature `Error(string)`, it decodes
 /// it and returns the revert error message.
+///
+/// Reason strings, also of failed cheatcodes, and panics are told apart with [RevertData].
+///
+/// When decoding many reverts with the same ABI, prefer [decode_revert_with] with an index
+/// of the errors built once with [errors_by_selector].
 pub fn decode_revert(
     err: &[u8],
     maybe_abi: Option<&Abi>,
     status: Option<Return>,
+) -> eyre::Result<String> {
+    decode_revert_with(err, maybe_abi.map(errors_by_selector).as_ref(), status)
+}
+
+/// Same as [decode_revert], but custom errors are looked up by their selector in `maybe_errors`
+pub fn decode_revert_with(
+    err: &[u8],
+    maybe_errors: Option<&ErrorsBySelector>,
+    status: Option<Return>,
 ) -> eyre::Result<String> {
     if err.len() < SELECTOR_LEN {
         if let Some(status) = status {
@@ -80,56 +220,14 @@ pub fn decode_revert(
         }
         eyre::bail!("Not enough error data to decode")
     }
+    match RevertData::new(err) {
+        RevertData::Reason(reason) => return Ok(reason),
+        revert @ RevertData::Panic(_) => return Ok(revert.to_string()),
+        // an `Error(string)` or `Panic(uint256)` whose arguments can't be decoded
+        RevertData::Raw(_) => eyre::bail!("Bad revert data decode"),
+        RevertData::Empty | RevertData::Custom { .. } => {}
+    }
     match err[..SELECTOR_LEN] {
-        // keccak(Panic(uint256))
-        [78, 72, 123, 113] => {
-            // ref: https://soliditydeveloper.com/solidity-0.8
-            match err[err.len() - 1] {
-                1 => {
-                    // assert
-                    Ok("Assertion violated".to_string())
-                }
-                17 => {
-                    // safemath over/underflow
-                    Ok("Arithmetic over/underflow".to_string())
-                }
-                18 => {
-                    // divide by 0
-                    Ok("Division or modulo by 0".to_string())
-                }
-                33 => {
-                    // conversion into non-existent enum type
-                    Ok("Conversion into non-existent enum type".to_string())
-                }
-                34 => {
-                    // incorrectly encoded storage byte array
-                    Ok("Incorrectly encoded storage byte array".to_string())
-                }
-                49 => {
-                    // pop() on empty array
-                    Ok("`pop()` on empty array".to_string())
-                }
-                50 => {
-                    // index out of bounds
-                    Ok("Index out of bounds".to_string())
-                }
-                65 => {
-                    // allocating too much memory or creating too large array
-                    Ok("Memory allocation overflow".to_string())
-                }
-                81 => {
-                    // calling a zero initialized variable of internal function type
-                    Ok("Calling a zero initialized variable of internal function type".to_string())
-                }
-                _ => {
-                    eyre::bail!("Unsupported solidity builtin panic")
-                }
-            }
-        }
-        // keccak(Error(string))
-        [8, 195, 121, 160] => {
-            String::decode(&err[SELECTOR_LEN..]).map_err(|_| eyre::eyre!("Bad string decode"))
-        }
         // keccak(expectRevert(bytes))
         [242, 141, 206, 179] => {
             let err_data = &err[SELECTOR_LEN..];
@@ -137,7 +235,7 @@ pub fn decode_revert(
                 let len = U256::from(&err_data[32..64]).as_usize();
                 if err_data.len() > 64 + len {
                     let actual_err = &err_data[64..64 + len];
-                    if let Ok(decoded) = decode_revert(actual_err, maybe_abi, None) {
+                    if let Ok(decoded) = decode_revert_with(actual_err, maybe_errors, None) {
                         // check if it's a builtin
                         return Ok(decoded)
                     } else if let Ok(as_str) = String::from_utf8(actual_err.to_vec()) {
@@ -153,7 +251,7 @@ pub fn decode_revert(
             let err_data = &err[SELECTOR_LEN..];
             if err_data.len() == 32 {
                 let actual_err = &err_data[..SELECTOR_LEN];
-                if let Ok(decoded) = decode_revert(actual_err, maybe_abi, None) {
+                if let Ok(decoded) = decode_revert_with(actual_err, maybe_errors, None) {
                     // it's a known selector
                     return Ok(decoded)
                 }
@@ -161,35 +259,22 @@ pub fn decode_revert(
             eyre::bail!("Unknown error selector")
         }
         _ => {
-            // try to decode a custom error if provided an abi
-            if let Some(abi) = maybe_abi {
-                for abi_error in abi.errors() {
-                    if abi_error.signature()[..SELECTOR_LEN] == err[..SELECTOR_LEN] {
-                        // if we don't decode, don't return an error, try to decode as a
-                        // string later
-                        if let Ok(decoded) = abi_error.decode(&err[SELECTOR_LEN..]) {
-                            let inputs = decoded
-                                .iter()
-                                .map(foundry_common::abi::format_token)
-                                .collect::<Vec<_>>()
-                                .join(", ");
-                            return Ok(format!("{}({})", abi_error.name, inputs))
-                        }
-                    }
-                }
+            // try to decode a custom error if provided an abi, if we don't decode, don't return
+            // an error, try to decode as a string later
+            if let Some((abi_error, decoded)) =
+                maybe_errors.and_then(|errors| decode_abi_error(err, errors))
+            {
+                let inputs = decoded
+                    .iter()
+                    .map(foundry_common::abi::format_token)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                return Ok(format!("{}({})", abi_error.name, inputs))
             }
 
-            // optimistically try to decode as string, unkown selector or `CheatcodeError`
+            // optimistically try to decode as string, also with an unknown selector
             String::decode(err)
                 .ok()
-                .or_else(|| {
-                    // try decoding as cheatcode error
-                    if err.starts_with(ERROR_PREFIX.as_slice()) {
-                        String::decode(&err[ERROR_PREFIX.len()..]).ok()
-                    } else {
-                        None
-                    }
-                })
                 .or_else(|| {
                     // try decoding as unknown err
                     String::decode(&err[SELECTOR_LEN..])
@@ -281,6 +366,45 @@ mod tests {
         contract::EthError,
     };
 
+    #[test]
+    fn test_revert_data() {
+        let reason = |reason: &str| {
+            [[8, 195, 121, 160].as_slice(), &ethers::abi::encode(&[Token::String(reason.into())])]
+                .concat()
+        };
+        assert_eq!(RevertData::new(&[]), RevertData::Empty);
+        assert_eq!(RevertData::new(&reason("")), RevertData::Reason(String::new()));
+        assert_eq!(RevertData::new(&reason("")).to_string(), r#""""#);
+        assert_eq!(RevertData::new(&reason("not owner")).to_string(), r#""not owner""#);
+        assert_eq!(
+            RevertData::new(&crate::error::encode_error("not owner")),
+            RevertData::Reason("not owner".to_string())
+        );
+        assert_eq!(decode_revert(&reason("not owner"), None, None).unwrap(), "not owner");
+        assert_eq!(
+            decode_revert(&crate::error::encode_error("not owner"), None, None).unwrap(),
+            "not owner"
+        );
+
+        let panic =
+            [[78, 72, 123, 113].as_slice(), &ethers::abi::encode(&[Token::Uint(1u64.into())])]
+                .concat();
+        assert_eq!(RevertData::new(&panic).to_string(), "assertion failed (0x1)");
+        assert_eq!(decode_revert(&panic, None, None).unwrap(), "assertion failed (0x1)");
+
+        let custom = [[1, 2, 3, 4].as_slice(), &[0; 32]].concat();
+        assert_eq!(
+            RevertData::new(&custom),
+            RevertData::Custom { selector: [1, 2, 3, 4], args: vec![0; 32] }
+        );
+        assert_eq!(RevertData::new(&[1, 2, 3, 4]).to_string(), "custom error 0x01020304");
+
+        // a reason selector without a string is kept raw
+        assert_eq!(RevertData::new(&[8, 195, 121, 160]), RevertData::Raw(vec![8, 195, 121, 160]));
+        assert!(decode_revert(&[8, 195, 121, 160], None, None).is_err());
+        assert_eq!(RevertData::new(&[1, 2]).to_string(), "0x0102");
+    }
+
     #[test]
     fn test_decode_custom_error_address() {
         #[derive(Debug, Clone, EthError)]
@@ -309,4 +433,58 @@ mod tests {
             ])
         );
     }
+
+    #[test]
+    fn test_decode_revert_with_indexed_custom_error() {
+        #[derive(Debug, Clone, EthError)]
+        struct MyError(U256);
+
+        let abi: Abi = serde_json::from_str(
+            r#"[{"type":"error","name":"MyError","inputs":[{"name":"value","type":"uint256"}]}]"#,
+        )
+        .unwrap();
+        let errors = errors_by_selector(&abi);
+        assert_eq!(errors.len(), 1);
+
+        let encoded = MyError(100u64.into()).encode();
+        assert_eq!(decode_revert_with(&encoded, Some(&errors), None).unwrap(), "MyError(100)");
+        assert_eq!(decode_revert(&encoded, Some(&abi), None).unwrap(), "MyError(100)");
+    }
+
+    #[test]
+    fn test_decode_revert_with_colliding_custom_errors() {
+        // both have the selector 0x42966c68
+        let abi: Abi = serde_json::from_str(
+            r#"[{"type":"error","name":"burn","inputs":[{"name":"","type":"uint256"}]},{"type":"error","name":"collate_propagate_storage","inputs":[{"name":"","type":"bytes16"}]}]"#,
+        )
+        .unwrap();
+        let errors = errors_by_selector(&abi);
+        assert_eq!(errors[&[0x42, 0x96, 0x6c, 0x68]].len(), 2);
+
+        // the first error that decodes the data is used
+        let not_owner: Abi = serde_json::from_str(
+            r#"[{"type":"error","name":"NotOwner","inputs":[{"name":"","type":"string"}]}]"#,
+        )
+        .unwrap();
+        let mut errors = errors_by_selector(&abi);
+        errors
+            .get_mut(&[0x42, 0x96, 0x6c, 0x68])
+            .unwrap()
+            .insert(0, not_owner.error("NotOwner").unwrap().clone());
+        let encoded = [[0x42, 0x96, 0x6c, 0x68].as_slice(), &U256::from(100u64).encode()].concat();
+        assert_eq!(decode_revert_with(&encoded, Some(&errors), None).unwrap(), "burn(100)");
+    }
+
+    #[test]
+    fn test_decode_panic() {
+        let panic = |code: u64| {
+            let mut err = vec![78, 72, 123, 113];
+            err.extend(U256::from(code).encode());
+            decode_revert(&err, None, None).unwrap()
+        };
+        assert_eq!(panic(0x11), "arithmetic underflow or overflow (0x11)");
+        assert_eq!(panic(0x32), "array out-of-bounds access (0x32)");
+        assert_eq!(panic(0x99), "unknown panic code (0x99)");
+        assert_eq!(panic(0x111), "unknown panic code (0x111)");
+    }
 }
@@ -1,4 +1,7 @@
-use ethers::types::{Address, Selector, H160};
+use ethers::{
+    abi::Function,
+    types::{Address, Selector, H160},
+};
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
 
@@ -133,6 +136,11 @@ ethers::contract::abigen!(
 );
 pub use hevm::{HEVMCalls, HEVM_ABI};
 
+/// The cheatcodes of [HEVM_ABI] by their selector, to decode calls to the [CHEATCODE_ADDRESS]
+/// without searching the ABI
+pub static HEVM_FUNCTIONS_BY_SELECTOR: Lazy<HashMap<Selector, Function>> =
+    Lazy::new(|| HEVM_ABI.functions().map(|func| (func.short_signature(), func.clone())).collect());
+
 /// The Hardhat console address (0x000000000000000000636F6e736F6c652e6c6f67).
 ///
 /// See: https://github.com/nomiclabs/hardhat/blob/master/packages/hardhat-core/console.sol
@@ -40,6 +40,14 @@ impl ExecutorBuilder {
         self
     }
 
+    /// Enables or disables recording the state of the accounts touched by the traced calls, which
+    /// requires tracing to be enabled
+    #[must_use]
+    pub fn set_state_diff(mut self, enable: bool) -> Self {
+        self.inspector_config.state_diff = enable;
+        self
+    }
+
     /// Enables or disables the debugger
     #[must_use]
     pub fn set_debugger(mut self, enable: bool) -> Self {
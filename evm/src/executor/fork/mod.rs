@@ -16,6 +16,12 @@ pub mod database;
 mod multi;
 pub use multi::{ForkId, MultiFork, MultiForkHandler};
 
+mod provider;
+pub use provider::{
+    AccountOverride, AsyncProvider, BlockingProvider, BlockingProviderError,
+    BlockingProviderResult, PinnedBlockingProvider, RetryPolicy, StateOverride,
+};
+
 /// Represents a _fork_ of a remote chain whose data is available only via the `url` endpoint.
 #[derive(Debug, Clone)]
 pub struct CreateFork {
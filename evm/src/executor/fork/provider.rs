@@ -0,0 +1,932 @@
+//! Synchronous and asynchronous wrappers around a [Middleware]
+
+use ethers::{
+    providers::{Http, HttpClientError, JsonRpcError, Middleware, ProviderError, RetryClientError},
+    types::{
+        transaction::eip2718::TypedTransaction, Address, Block, BlockId, BlockNumber, Bytes,
+        FeeHistory, Transaction, TransactionReceipt, H256, U256, U64,
+    },
+};
+use futures::future::{join_all, try_join};
+use serde::Serialize;
+use std::{collections::BTreeMap, future::Future, sync::Arc, time::Duration};
+use tokio::runtime::Runtime;
+use tracing::trace;
+
+/// The result of a [BlockingProvider] or [AsyncProvider] request
+pub type BlockingProviderResult<T, M> = Result<T, BlockingProviderError<<M as Middleware>::Error>>;
+
+/// An error of a [BlockingProvider] or [AsyncProvider] request
+#[derive(Debug, thiserror::Error)]
+pub enum BlockingProviderError<E> {
+    /// The request failed
+    #[error(transparent)]
+    Provider(E),
+    /// A request that was sent to the transport of the middleware directly failed, see
+    /// [AsyncProvider::call_with_overrides]
+    #[error(transparent)]
+    Rpc(ProviderError),
+    /// The request did not complete within the timeout of the provider
+    #[error("request timed out after {0:?}")]
+    Timeout(Duration),
+}
+
+impl<E: std::error::Error + 'static> BlockingProviderError<E> {
+    /// Whether the request may succeed if it's retried, see [is_transient]. Timeouts are not
+    /// retried.
+    fn is_transient(&self) -> bool {
+        match self {
+            BlockingProviderError::Provider(err) => is_transient(err),
+            BlockingProviderError::Rpc(err) => is_transient(err),
+            BlockingProviderError::Timeout(_) => false,
+        }
+    }
+}
+
+/// The accounts whose state is replaced for a call, see [AsyncProvider::call_with_overrides]
+pub type StateOverride = BTreeMap<Address, AccountOverride>;
+
+/// The state of an account that is replaced for a call, the state that is not set is kept.
+///
+/// Only one of `state` and `state_diff` may be set.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountOverride {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balance: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<U64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<Bytes>,
+    /// Replaces the whole storage of the account
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<BTreeMap<H256, H256>>,
+    /// Replaces the given storage slots of the account
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_diff: Option<BTreeMap<H256, H256>>,
+}
+
+/// How an [AsyncProvider] retries requests that failed with a transient error, like rate limits
+/// or server errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The maximum number of retries of a request
+    pub max_retries: u32,
+    /// The delay before the first retry, which doubles with every retry
+    pub initial_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Returns the delay before the given retry, starting at 0
+    fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff.saturating_mul(2u32.saturating_pow(retry))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { max_retries: 5, initial_backoff: Duration::from_millis(500) }
+    }
+}
+
+/// Wrapper around a [Middleware] that applies a timeout and a [RetryPolicy] to every request.
+///
+/// This exposes the same requests as [BlockingProvider], but returns futures instead of blocking
+/// on them, so it can be used from code that is already `async` without nesting runtimes. Requests
+/// must be awaited on a [tokio] runtime if a timeout or retry policy is set.
+#[derive(Debug, Clone)]
+pub struct AsyncProvider<M> {
+    provider: M,
+    /// The timeout of each request, requests may take forever if not set
+    timeout: Option<Duration>,
+    /// How to retry failed requests, they are not retried if not set
+    retry_policy: Option<RetryPolicy>,
+}
+
+impl<M> AsyncProvider<M>
+where
+    M: Middleware,
+    M::Error: 'static,
+{
+    /// Creates a new provider for the given middleware
+    pub fn new(provider: M) -> Self {
+        Self { provider, timeout: None, retry_policy: None }
+    }
+
+    /// Sets the timeout of each request, after which it fails with
+    /// [BlockingProviderError::Timeout]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Retries requests that failed with a transient error according to the `policy`.
+    ///
+    /// Every attempt of a request has its own timeout, and timed out attempts are not retried.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// Returns the wrapped middleware
+    pub fn provider(&self) -> &M {
+        &self.provider
+    }
+
+    /// Executes the request created by `request`, retrying it on transient errors, see
+    /// [RetryPolicy]
+    async fn request<T, F, Fut>(&self, request: F) -> BlockingProviderResult<T, M>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, M::Error>>,
+    {
+        self.retry(|| async {
+            self.request_once(request()).await?.map_err(BlockingProviderError::Provider)
+        })
+        .await
+    }
+
+    /// Executes the request created by `request` until it succeeds, fails with an error that is not
+    /// transient or runs out of retries, see [RetryPolicy]
+    async fn retry<T, F, Fut>(&self, request: F) -> BlockingProviderResult<T, M>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = BlockingProviderResult<T, M>>,
+    {
+        let mut retry = 0;
+        loop {
+            match (request().await, self.retry_policy) {
+                (Err(err), Some(policy)) if retry < policy.max_retries && err.is_transient() => {
+                    let backoff = policy.backoff(retry);
+                    trace!(target: "blocking_provider", %err, ?backoff, retry, "retrying request");
+                    tokio::time::sleep(backoff).await;
+                    retry += 1;
+                }
+                (result, _) => return result,
+            }
+        }
+    }
+
+    /// Executes the given request until it completes or the timeout expires
+    async fn request_once<F: Future>(&self, request: F) -> BlockingProviderResult<F::Output, M> {
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, request)
+                .await
+                .map_err(|_| BlockingProviderError::Timeout(timeout)),
+            None => Ok(request.await),
+        }
+    }
+
+    /// Returns the chain id of the network
+    pub async fn get_chainid(&self) -> BlockingProviderResult<U256, M> {
+        self.request(|| self.provider.get_chainid()).await
+    }
+
+    /// Returns the current gas price
+    pub async fn get_gas_price(&self) -> BlockingProviderResult<U256, M> {
+        self.request(|| self.provider.get_gas_price()).await
+    }
+
+    /// Returns the base fees and the effective priority fees at the given percentiles of the
+    /// `block_count` blocks up to and including `last_block`, see EIP-1559
+    pub async fn get_fee_history(
+        &self,
+        block_count: u64,
+        last_block: BlockNumber,
+        reward_percentiles: &[f64],
+    ) -> BlockingProviderResult<FeeHistory, M> {
+        self.request(|| self.provider.fee_history(block_count, last_block, reward_percentiles))
+            .await
+    }
+
+    /// Returns the balance of the account at the given block
+    pub async fn get_balance(
+        &self,
+        address: Address,
+        block: Option<BlockId>,
+    ) -> BlockingProviderResult<U256, M> {
+        self.request(|| self.provider.get_balance(address, block)).await
+    }
+
+    /// Returns the nonce of the account at the given block
+    pub async fn get_transaction_count(
+        &self,
+        address: Address,
+        block: Option<BlockId>,
+    ) -> BlockingProviderResult<U256, M> {
+        self.request(|| self.provider.get_transaction_count(address, block)).await
+    }
+
+    /// Returns the balance and the nonce of the account at the given block.
+    ///
+    /// Both are requested concurrently, which saves a round-trip compared to requesting them one
+    /// after the other.
+    pub async fn get_account(
+        &self,
+        address: Address,
+        block: Option<BlockId>,
+    ) -> BlockingProviderResult<(U256, U256), M> {
+        self.request(|| {
+            try_join(
+                self.provider.get_balance(address, block),
+                self.provider.get_transaction_count(address, block),
+            )
+        })
+        .await
+    }
+
+    /// Returns the code of the account at the given block
+    pub async fn get_code(
+        &self,
+        address: Address,
+        block: Option<BlockId>,
+    ) -> BlockingProviderResult<Bytes, M> {
+        self.request(|| self.provider.get_code(address, block)).await
+    }
+
+    /// Returns the value of the storage slot of the account at the given block
+    pub async fn get_storage_at(
+        &self,
+        address: Address,
+        slot: H256,
+        block: Option<BlockId>,
+    ) -> BlockingProviderResult<H256, M> {
+        self.request(|| self.provider.get_storage_at(address, slot, block)).await
+    }
+
+    /// Returns the values of multiple storage slots of the account at the given block.
+    ///
+    /// All slots are requested concurrently, the values are returned in the order of `slots`.
+    pub async fn get_storage_at_multi(
+        &self,
+        address: Address,
+        slots: &[H256],
+        block: Option<BlockId>,
+    ) -> BlockingProviderResult<Vec<H256>, M> {
+        self.request(move || async move {
+            join_all(slots.iter().map(|slot| self.provider.get_storage_at(address, *slot, block)))
+                .await
+                .into_iter()
+                .collect()
+        })
+        .await
+    }
+
+    /// Executes the call at the given block without creating a transaction, i.e. `eth_call`, and
+    /// returns its output
+    pub async fn call(
+        &self,
+        tx: &TypedTransaction,
+        block: Option<BlockId>,
+    ) -> BlockingProviderResult<Bytes, M> {
+        self.request(|| self.provider.call(tx, block)).await
+    }
+
+    /// Executes the call at the given block like [Self::call], with the state of the accounts in
+    /// `overrides` replaced for the call, e.g. to give the caller more balance or to run other
+    /// code.
+    ///
+    /// The overrides are the third parameter of `eth_call`, which geth and anvil support but not
+    /// every node does. Since [Middleware] has no request for them, the call is sent to the
+    /// transport of the middleware directly, so it fails with [BlockingProviderError::Rpc].
+    pub async fn call_with_overrides(
+        &self,
+        tx: &TypedTransaction,
+        block: Option<BlockId>,
+        overrides: &StateOverride,
+    ) -> BlockingProviderResult<Bytes, M> {
+        let block = block.unwrap_or_else(|| BlockNumber::Latest.into());
+        self.retry(|| async {
+            self.request_once(
+                self.provider.provider().request::<_, Bytes>("eth_call", (tx, block, overrides)),
+            )
+            .await?
+            .map_err(BlockingProviderError::Rpc)
+        })
+        .await
+    }
+
+    /// Returns the block with the hashes of its transactions, `None` if the block is unknown
+    pub async fn get_block(
+        &self,
+        block: BlockId,
+    ) -> BlockingProviderResult<Option<Block<H256>>, M> {
+        self.request(|| self.provider.get_block(block)).await
+    }
+
+    /// Returns the block with all of its transactions, `None` if the block is unknown
+    pub async fn get_block_with_txs(
+        &self,
+        block: BlockId,
+    ) -> BlockingProviderResult<Option<Block<Transaction>>, M> {
+        self.request(|| self.provider.get_block_with_txs(block)).await
+    }
+
+    /// Returns the mined transaction, `None` if the transaction is unknown or still pending
+    pub async fn get_transaction(
+        &self,
+        hash: H256,
+    ) -> BlockingProviderResult<Option<Transaction>, M> {
+        Ok(self
+            .request(|| self.provider.get_transaction(hash))
+            .await?
+            .filter(|tx| tx.block_hash.is_some()))
+    }
+
+    /// Returns the receipt of the transaction, `None` if the transaction is unknown or still
+    /// pending
+    pub async fn get_transaction_receipt(
+        &self,
+        hash: H256,
+    ) -> BlockingProviderResult<Option<TransactionReceipt>, M> {
+        self.request(|| self.provider.get_transaction_receipt(hash)).await
+    }
+
+    /// Returns the ENS name of the address by its reverse record, `None` if it has no reverse
+    /// record or the name does not resolve back to the address
+    pub async fn lookup_address(
+        &self,
+        address: Address,
+    ) -> BlockingProviderResult<Option<String>, M> {
+        match self.request(|| self.provider.lookup_address(address)).await {
+            Ok(name) => Ok(Some(name)),
+            Err(BlockingProviderError::Provider(err)) if is_missing_ens_name(&err) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Blocking wrapper around an [AsyncProvider] that executes every request on a
+/// [tokio::runtime::Runtime].
+///
+/// This is useful for code that needs to fetch remote state, but can't be `async` itself, like
+/// [revm::Database] implementations. Code that is already `async` should use the
+/// [AsyncProvider] instead, see [Self::as_async], since blocking within a runtime panics.
+///
+/// Clones share the same runtime, which can be used to block on requests from multiple threads at
+/// once.
+#[derive(Debug, Clone)]
+pub struct BlockingProvider<M> {
+    provider: AsyncProvider<M>,
+    runtime: Arc<Runtime>,
+}
+
+impl<M> BlockingProvider<M>
+where
+    M: Middleware,
+    M::Error: 'static,
+{
+    /// Creates a new blocking provider for the given middleware
+    ///
+    /// # Panics
+    ///
+    /// If the runtime can't be created, see [Self::try_new]
+    pub fn new(provider: M) -> Self {
+        Self::try_new(provider).expect("failed to create tokio runtime")
+    }
+
+    /// Creates a new blocking provider for the given middleware, failing if the runtime can't be
+    /// created
+    pub fn try_new(provider: M) -> std::io::Result<Self> {
+        Ok(Self { provider: AsyncProvider::new(provider), runtime: Arc::new(Runtime::new()?) })
+    }
+
+    /// Sets the timeout of each request, after which it fails with
+    /// [BlockingProviderError::Timeout]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.provider = self.provider.with_timeout(timeout);
+        self
+    }
+
+    /// Retries requests that failed with a transient error according to the `policy`.
+    ///
+    /// Every attempt of a request has its own timeout, and timed out attempts are not retried.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.provider = self.provider.with_retry_policy(policy);
+        self
+    }
+
+    /// Returns a provider that reads all state at the given block, see [PinnedBlockingProvider]
+    pub fn pin_block(self, block: BlockId) -> PinnedBlockingProvider<M> {
+        PinnedBlockingProvider { provider: self, block }
+    }
+
+    /// Returns the wrapped middleware
+    pub fn provider(&self) -> &M {
+        self.provider.provider()
+    }
+
+    /// Returns the non-blocking provider the requests are executed with
+    pub fn as_async(&self) -> &AsyncProvider<M> {
+        &self.provider
+    }
+
+    /// Blocks on the given future until it completes
+    ///
+    /// Note that the timeout of the provider does not apply here
+    pub fn block_on<F: Future>(&self, f: F) -> F::Output {
+        self.runtime.block_on(f)
+    }
+
+    /// Returns the chain id of the network
+    pub fn get_chainid(&self) -> BlockingProviderResult<U256, M> {
+        self.block_on(self.provider.get_chainid())
+    }
+
+    /// Returns the current gas price
+    pub fn get_gas_price(&self) -> BlockingProviderResult<U256, M> {
+        self.block_on(self.provider.get_gas_price())
+    }
+
+    /// Returns the base fees and the effective priority fees at the given percentiles of the
+    /// `block_count` blocks up to and including `last_block`, see EIP-1559
+    pub fn get_fee_history(
+        &self,
+        block_count: u64,
+        last_block: BlockNumber,
+        reward_percentiles: &[f64],
+    ) -> BlockingProviderResult<FeeHistory, M> {
+        self.block_on(self.provider.get_fee_history(block_count, last_block, reward_percentiles))
+    }
+
+    /// Returns the balance of the account at the given block
+    pub fn get_balance(
+        &self,
+        address: Address,
+        block: Option<BlockId>,
+    ) -> BlockingProviderResult<U256, M> {
+        self.block_on(self.provider.get_balance(address, block))
+    }
+
+    /// Returns the nonce of the account at the given block
+    pub fn get_transaction_count(
+        &self,
+        address: Address,
+        block: Option<BlockId>,
+    ) -> BlockingProviderResult<U256, M> {
+        self.block_on(self.provider.get_transaction_count(address, block))
+    }
+
+    /// Returns the balance and the nonce of the account at the given block, see
+    /// [AsyncProvider::get_account]
+    pub fn get_account(
+        &self,
+        address: Address,
+        block: Option<BlockId>,
+    ) -> BlockingProviderResult<(U256, U256), M> {
+        self.block_on(self.provider.get_account(address, block))
+    }
+
+    /// Returns the code of the account at the given block
+    pub fn get_code(
+        &self,
+        address: Address,
+        block: Option<BlockId>,
+    ) -> BlockingProviderResult<Bytes, M> {
+        self.block_on(self.provider.get_code(address, block))
+    }
+
+    /// Returns the value of the storage slot of the account at the given block
+    pub fn get_storage_at(
+        &self,
+        address: Address,
+        slot: H256,
+        block: Option<BlockId>,
+    ) -> BlockingProviderResult<H256, M> {
+        self.block_on(self.provider.get_storage_at(address, slot, block))
+    }
+
+    /// Returns the values of multiple storage slots of the account at the given block, see
+    /// [AsyncProvider::get_storage_at_multi]
+    pub fn get_storage_at_multi(
+        &self,
+        address: Address,
+        slots: &[H256],
+        block: Option<BlockId>,
+    ) -> BlockingProviderResult<Vec<H256>, M> {
+        self.block_on(self.provider.get_storage_at_multi(address, slots, block))
+    }
+
+    /// Executes the call at the given block without creating a transaction, i.e. `eth_call`, and
+    /// returns its output
+    pub fn call(
+        &self,
+        tx: &TypedTransaction,
+        block: Option<BlockId>,
+    ) -> BlockingProviderResult<Bytes, M> {
+        self.block_on(self.provider.call(tx, block))
+    }
+
+    /// Executes the call at the given block with the state of the accounts in `overrides`
+    /// replaced, see [AsyncProvider::call_with_overrides]
+    pub fn call_with_overrides(
+        &self,
+        tx: &TypedTransaction,
+        block: Option<BlockId>,
+        overrides: &StateOverride,
+    ) -> BlockingProviderResult<Bytes, M> {
+        self.block_on(self.provider.call_with_overrides(tx, block, overrides))
+    }
+
+    /// Returns the block with the hashes of its transactions, `None` if the block is unknown
+    pub fn get_block(&self, block: BlockId) -> BlockingProviderResult<Option<Block<H256>>, M> {
+        self.block_on(self.provider.get_block(block))
+    }
+
+    /// Returns the block with all of its transactions, `None` if the block is unknown
+    pub fn get_block_with_txs(
+        &self,
+        block: BlockId,
+    ) -> BlockingProviderResult<Option<Block<Transaction>>, M> {
+        self.block_on(self.provider.get_block_with_txs(block))
+    }
+
+    /// Returns the mined transaction, `None` if the transaction is unknown or still pending
+    pub fn get_transaction(&self, hash: H256) -> BlockingProviderResult<Option<Transaction>, M> {
+        self.block_on(self.provider.get_transaction(hash))
+    }
+
+    /// Returns the receipt of the transaction, `None` if the transaction is unknown or still
+    /// pending
+    pub fn get_transaction_receipt(
+        &self,
+        hash: H256,
+    ) -> BlockingProviderResult<Option<TransactionReceipt>, M> {
+        self.block_on(self.provider.get_transaction_receipt(hash))
+    }
+
+    /// Returns the ENS name of the address by its reverse record, see
+    /// [AsyncProvider::lookup_address]
+    pub fn lookup_address(&self, address: Address) -> BlockingProviderResult<Option<String>, M> {
+        self.block_on(self.provider.lookup_address(address))
+    }
+}
+
+/// A [BlockingProvider] that reads all state at the same block, e.g. the block of a fork.
+///
+/// This avoids accidentally reading the latest state when the block of a request is omitted, the
+/// underlying provider can still be used for requests at other blocks.
+#[derive(Debug, Clone)]
+pub struct PinnedBlockingProvider<M> {
+    provider: BlockingProvider<M>,
+    block: BlockId,
+}
+
+impl<M> PinnedBlockingProvider<M>
+where
+    M: Middleware,
+    M::Error: 'static,
+{
+    /// Returns the block all state is read at
+    pub fn block(&self) -> BlockId {
+        self.block
+    }
+
+    /// Returns the underlying provider
+    pub fn provider(&self) -> &BlockingProvider<M> {
+        &self.provider
+    }
+
+    /// Returns the balance of the account at the pinned block
+    pub fn get_balance(&self, address: Address) -> BlockingProviderResult<U256, M> {
+        self.provider.get_balance(address, Some(self.block))
+    }
+
+    /// Returns the nonce of the account at the pinned block
+    pub fn get_transaction_count(&self, address: Address) -> BlockingProviderResult<U256, M> {
+        self.provider.get_transaction_count(address, Some(self.block))
+    }
+
+    /// Returns the balance and the nonce of the account at the pinned block, see
+    /// [BlockingProvider::get_account]
+    pub fn get_account(&self, address: Address) -> BlockingProviderResult<(U256, U256), M> {
+        self.provider.get_account(address, Some(self.block))
+    }
+
+    /// Returns the code of the account at the pinned block
+    pub fn get_code(&self, address: Address) -> BlockingProviderResult<Bytes, M> {
+        self.provider.get_code(address, Some(self.block))
+    }
+
+    /// Returns the value of the storage slot of the account at the pinned block
+    pub fn get_storage_at(&self, address: Address, slot: H256) -> BlockingProviderResult<H256, M> {
+        self.provider.get_storage_at(address, slot, Some(self.block))
+    }
+
+    /// Returns the values of multiple storage slots of the account at the pinned block, see
+    /// [BlockingProvider::get_storage_at_multi]
+    pub fn get_storage_at_multi(
+        &self,
+        address: Address,
+        slots: &[H256],
+    ) -> BlockingProviderResult<Vec<H256>, M> {
+        self.provider.get_storage_at_multi(address, slots, Some(self.block))
+    }
+
+    /// Returns the pinned block with the hashes of its transactions, `None` if the block is unknown
+    pub fn get_block(&self) -> BlockingProviderResult<Option<Block<H256>>, M> {
+        self.provider.get_block(self.block)
+    }
+}
+
+/// Whether the error of a request is transient, i.e. it may succeed if retried, like rate limits,
+/// server errors or dropped connections.
+///
+/// This is decided by the HTTP status and the JSON-RPC error code of the response, so e.g. a
+/// revert whose data happens to contain `429` is not transient. Errors of other transports are
+/// never transient.
+fn is_transient(err: &(dyn std::error::Error + 'static)) -> bool {
+    if let Some(err) = err.downcast_ref::<ProviderError>() {
+        return match err {
+            ProviderError::JsonRpcClientError(err) => is_transient(err.as_ref()),
+            _ => false,
+        }
+    }
+    if let Some(RetryClientError::ProviderError(err)) = err.downcast_ref::<RetryClientError<Http>>()
+    {
+        return is_transient_http_error(err)
+    }
+    if let Some(err) = err.downcast_ref::<HttpClientError>() {
+        return is_transient_http_error(err)
+    }
+    err.downcast_ref::<JsonRpcError>().map_or(false, is_transient_rpc_error)
+}
+
+/// Whether the request failed because of a rate limit, a server error or a dropped connection
+fn is_transient_http_error(err: &HttpClientError) -> bool {
+    match err {
+        HttpClientError::ReqwestError(err) => {
+            err.is_timeout() ||
+                err.is_connect() ||
+                err.status()
+                    .map_or(false, |status| status.as_u16() == 429 || status.is_server_error())
+        }
+        HttpClientError::JsonRpcError(err) => is_transient_rpc_error(err),
+        _ => false,
+    }
+}
+
+/// Whether the ENS lookup failed because there is no such name, rather than because of the request,
+/// i.e. the name has no resolver or resolves to another address
+fn is_missing_ens_name(err: &(dyn std::error::Error + 'static)) -> bool {
+    match err.downcast_ref::<ProviderError>() {
+        Some(err) => matches!(err, ProviderError::EnsError(_) | ProviderError::EnsNotOwned(_)),
+        // the error of a middleware wraps the error of the provider
+        None => err.source().map_or(false, is_missing_ens_name),
+    }
+}
+
+/// Whether the JSON-RPC error is a rate limit: providers respond with `429` like the HTTP status,
+/// `-32005` (limit exceeded, EIP-1474) or `-32016`
+fn is_transient_rpc_error(err: &JsonRpcError) -> bool {
+    matches!(err.code, 429 | -32005 | -32016)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::{Http, Provider};
+    use std::{net::TcpListener, time::Instant};
+
+    #[test]
+    fn can_block_on_shared_runtime_from_multiple_threads() {
+        let (provider, _mock) = Provider::mocked();
+        let provider = BlockingProvider::new(provider);
+
+        let handles = (0..8u64)
+            .map(|i| {
+                let provider = provider.clone();
+                std::thread::spawn(move || {
+                    provider.block_on(async move {
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                        i
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let results = handles.into_iter().map(|handle| handle.join().unwrap()).collect::<Vec<_>>();
+        assert_eq!(results, (0..8).collect::<Vec<_>>());
+        assert_eq!(Arc::strong_count(&provider.runtime), 1);
+    }
+
+    #[test]
+    fn returns_storage_slots_in_order() {
+        let (provider, mock) = Provider::mocked();
+        let provider = BlockingProvider::new(provider);
+        let slots = [1u64, 2, 3].map(H256::from_low_u64_be);
+
+        // the slots are requested in order, and mocked responses are returned last to first
+        for value in [30u64, 20, 10] {
+            mock.push(H256::from_low_u64_be(value)).unwrap();
+        }
+        assert_eq!(
+            provider.get_storage_at_multi(Address::zero(), &slots, None).unwrap(),
+            [10u64, 20, 30].map(H256::from_low_u64_be).to_vec()
+        );
+        for slot in 1u64..=3 {
+            mock.assert_request(
+                "eth_getStorageAt",
+                [format!("{:?}", Address::zero()), format!("{slot:#x}"), "latest".to_string()],
+            )
+            .unwrap();
+        }
+
+        assert!(provider.get_storage_at_multi(Address::zero(), &[], None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn can_get_blocks() {
+        let (provider, mock) = Provider::mocked();
+        let provider = BlockingProvider::new(provider);
+        let number = BlockId::Number(BlockNumber::Number(1u64.into()));
+
+        mock.push(Block::<H256> {
+            number: Some(1u64.into()),
+            transactions: vec![H256::from_low_u64_be(1)],
+            ..Default::default()
+        })
+        .unwrap();
+        let block = provider.get_block(number).unwrap().unwrap();
+        assert_eq!(block.number, Some(1u64.into()));
+        assert_eq!(block.transactions, vec![H256::from_low_u64_be(1)]);
+        mock.assert_request("eth_getBlockByNumber", serde_json::json!(["0x1", false])).unwrap();
+
+        mock.push(Block::<Transaction> {
+            transactions: vec![Transaction { nonce: 2u64.into(), ..Default::default() }],
+            ..Default::default()
+        })
+        .unwrap();
+        let block = provider.get_block_with_txs(number).unwrap().unwrap();
+        assert_eq!(block.transactions[0].nonce, 2u64.into());
+        mock.assert_request("eth_getBlockByNumber", serde_json::json!(["0x1", true])).unwrap();
+
+        mock.push(Option::<Block<H256>>::None).unwrap();
+        assert_eq!(provider.get_block(BlockNumber::Latest.into()).unwrap(), None);
+    }
+
+    #[test]
+    fn can_try_to_create_provider() {
+        let (provider, mock) = Provider::mocked();
+        let provider = BlockingProvider::try_new(provider).unwrap();
+
+        mock.push(U256::from(1u64)).unwrap();
+        assert_eq!(provider.get_transaction_count(Address::zero(), None).unwrap(), 1u64.into());
+    }
+
+    #[test]
+    fn only_returns_mined_transactions() {
+        let (provider, mock) = Provider::mocked();
+        let provider = BlockingProvider::new(provider);
+        let hash = H256::from_low_u64_be(1);
+
+        mock.push(Transaction {
+            hash,
+            block_hash: Some(H256::from_low_u64_be(2)),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(provider.get_transaction(hash).unwrap().map(|tx| tx.hash), Some(hash));
+        mock.assert_request("eth_getTransactionByHash", [hash]).unwrap();
+
+        // still pending
+        mock.push(Transaction { hash, ..Default::default() }).unwrap();
+        assert_eq!(provider.get_transaction(hash).unwrap(), None);
+
+        mock.push(TransactionReceipt { transaction_hash: hash, ..Default::default() }).unwrap();
+        let receipt = provider.get_transaction_receipt(hash).unwrap().unwrap();
+        assert_eq!(receipt.transaction_hash, hash);
+
+        mock.push(Option::<TransactionReceipt>::None).unwrap();
+        assert_eq!(provider.get_transaction_receipt(hash).unwrap(), None);
+    }
+
+    #[test]
+    fn can_get_chain_id_and_fees() {
+        let (provider, mock) = Provider::mocked();
+        let provider = BlockingProvider::new(provider);
+
+        mock.push(U256::from(1u64)).unwrap();
+        assert_eq!(provider.get_chainid().unwrap(), 1u64.into());
+        mock.assert_request("eth_chainId", ()).unwrap();
+        mock.push(U256::from(30_000_000_000u64)).unwrap();
+        assert_eq!(provider.get_gas_price().unwrap(), 30_000_000_000u64.into());
+        mock.assert_request("eth_gasPrice", ()).unwrap();
+
+        mock.push(FeeHistory {
+            base_fee_per_gas: vec![10u64.into(), 11u64.into()],
+            gas_used_ratio: vec![0.5],
+            oldest_block: 100u64.into(),
+            reward: vec![vec![1u64.into(), 2u64.into()]],
+        })
+        .unwrap();
+        let history = provider.get_fee_history(1, BlockNumber::Latest, &[25.0, 75.0]).unwrap();
+        mock.assert_request("eth_feeHistory", serde_json::json!(["0x1", "latest", [25.0, 75.0]]))
+            .unwrap();
+        assert_eq!(history.base_fee_per_gas, vec![10u64.into(), 11u64.into()]);
+        assert_eq!(history.oldest_block, 100u64.into());
+        assert_eq!(history.reward, vec![vec![1u64.into(), 2u64.into()]]);
+    }
+
+    #[test]
+    fn serializes_state_overrides_like_geth() {
+        let overrides: StateOverride = [(
+            Address::zero(),
+            AccountOverride {
+                balance: Some(U256::from(1u64)),
+                nonce: Some(2u64.into()),
+                state_diff: Some([(H256::zero(), H256::from_low_u64_be(3))].into_iter().collect()),
+                ..Default::default()
+            },
+        )]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            serde_json::to_value(&overrides).unwrap(),
+            serde_json::json!({
+                "0x0000000000000000000000000000000000000000": {
+                    "balance": "0x1",
+                    "nonce": "0x2",
+                    "stateDiff": {
+                        "0x0000000000000000000000000000000000000000000000000000000000000000":
+                            "0x0000000000000000000000000000000000000000000000000000000000000003"
+                    }
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn can_read_state_at_pinned_block() {
+        let (provider, mock) = Provider::mocked();
+        let block = BlockId::Number(BlockNumber::Number(15_000_000u64.into()));
+        let provider = BlockingProvider::new(provider).pin_block(block);
+
+        mock.push(U256::from(100u64)).unwrap();
+        assert_eq!(provider.get_balance(Address::zero()).unwrap(), U256::from(100u64));
+        mock.assert_request(
+            "eth_getBalance",
+            [format!("{:?}", Address::zero()), "0xe4e1c0".to_string()],
+        )
+        .unwrap();
+        assert_eq!(provider.block(), block);
+    }
+
+    #[tokio::test]
+    async fn can_request_from_async_context() {
+        let (provider, mock) = Provider::mocked();
+        let provider = AsyncProvider::new(provider).with_timeout(Duration::from_secs(5));
+
+        mock.push(U256::from(100u64)).unwrap();
+        assert_eq!(provider.get_balance(Address::zero(), None).await.unwrap(), U256::from(100u64));
+    }
+
+    #[test]
+    fn times_out_on_unresponsive_endpoint() {
+        // accepts connections, but never responds
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let provider =
+            Provider::<Http>::try_from(format!("http://{}", listener.local_addr().unwrap()))
+                .unwrap();
+        let timeout = Duration::from_millis(100);
+        let provider = BlockingProvider::new(provider).with_timeout(timeout);
+
+        let start = Instant::now();
+        let err = provider.get_chainid().unwrap_err();
+        assert!(matches!(err, BlockingProviderError::Timeout(t) if t == timeout));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn only_retries_transient_errors() {
+        let rpc_error = |code, message: &str, data| -> BlockingProviderError<ProviderError> {
+            let err = HttpClientError::JsonRpcError(JsonRpcError {
+                code,
+                message: message.to_string(),
+                data,
+            });
+            BlockingProviderError::Provider(ProviderError::JsonRpcClientError(Box::new(err)))
+        };
+        assert!(rpc_error(429, "Too Many Requests", None).is_transient());
+        assert!(rpc_error(-32005, "limit exceeded", None).is_transient());
+        assert!(!rpc_error(-32601, "the method foo does not exist", None).is_transient());
+        // only the code of the error is relevant, not the revert data
+        let revert = rpc_error(
+            3,
+            "execution reverted: 429",
+            Some(serde_json::json!("0x08c379a0000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000033432390000000000000000000000000000000000000000000000000000000000")),
+        );
+        assert!(revert.to_string().contains("429"));
+        assert!(!revert.is_transient());
+
+        let err = BlockingProviderError::<ProviderError>::Rpc(ProviderError::CustomError(
+            "429 Too Many Requests".to_string(),
+        ));
+        assert!(!err.is_transient());
+        assert!(!BlockingProviderError::<ProviderError>::Timeout(Duration::ZERO).is_transient());
+
+        let policy = RetryPolicy { max_retries: 3, initial_backoff: Duration::from_millis(100) };
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+    }
+}
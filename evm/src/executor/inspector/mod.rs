@@ -48,6 +48,9 @@ pub struct InspectorStackConfig {
     pub gas_price: U256,
     /// Whether tracing is enabled
     pub tracing: bool,
+    /// Whether the tracer records the state of the accounts touched by every call, see
+    /// [Tracer::with_state_diff_recording]
+    pub state_diff: bool,
     /// Whether the debugger is enabled
     pub debugger: bool,
     /// The fuzzer inspector and its state, if it exists.
@@ -71,7 +74,9 @@ impl InspectorStackConfig {
         }
 
         if self.tracing {
-            stack.tracer = Some(Tracer::default());
+            let tracer = Tracer::default();
+            stack.tracer =
+                Some(if self.state_diff { tracer.with_state_diff_recording() } else { tracer });
         }
         if self.debugger {
             let gas_inspector = Rc::new(RefCell::new(GasInspector::default()));
@@ -2,30 +2,36 @@ use crate::{
     debug::Instruction::OpCode,
     executor::inspector::utils::{gas_used, get_create_address},
     trace::{
-        CallTrace, CallTraceArena, CallTraceStep, LogCallOrder, RawOrDecodedCall, RawOrDecodedLog,
-        RawOrDecodedReturnData,
+        utils::geth_error, AccountState, CallTrace, CallTraceArena, CallTraceStep, LogCallOrder,
+        RawOrDecodedCall, RawOrDecodedLog, RawOrDecodedReturnData, Warmth,
     },
     CallKind,
 };
 use bytes::Bytes;
 use ethers::{
-    abi::RawLog,
+    abi::{ethereum_types::BigEndianHash, RawLog},
     types::{Address, H256, U256},
 };
 use revm::{
-    opcode, return_ok, CallInputs, CallScheme, CreateInputs, Database, EVMData, Gas, GasInspector,
-    Inspector, Interpreter, JournalEntry, Return,
+    opcode, return_ok, return_revert, CallInputs, CallScheme, CreateInputs, Database, EVMData, Gas,
+    GasInspector, Inspector, Interpreter, JournalEntry, Return,
 };
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+
+/// The prefix of EVM Object Format (EOF) code, see EIP-3540
+const EOF_MAGIC: [u8; 2] = [0xef, 0x00];
 
 /// An inspector that collects call traces.
 #[derive(Default, Debug, Clone)]
 pub struct Tracer {
     record_steps: bool,
+    record_state_diff: bool,
 
     pub traces: CallTraceArena,
     trace_stack: Vec<usize>,
     step_stack: Vec<(usize, usize)>, // (trace_idx, step_idx)
+    // Gas refunded by the parent frames of each ongoing trace
+    refund_stack: Vec<i64>,
 
     gas_inspector: Rc<RefCell<GasInspector>>,
 }
@@ -41,32 +47,75 @@ impl Tracer {
         self
     }
 
-    fn start_trace(
+    /// Enables recording the state of the caller and the destination of every call before and
+    /// after it, see [CallTrace::pre_state].
+    ///
+    /// This clones the code and the changed storage of both accounts twice per call, so it's only
+    /// enabled if a state diff is needed, e.g. for [CallTraceArena::parity_state_diff].
+    pub fn with_state_diff_recording(mut self) -> Self {
+        self.record_state_diff = true;
+        self
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn start_trace<DB: Database>(
         &mut self,
-        depth: usize,
+        data: &EVMData<'_, DB>,
         address: Address,
-        data: Vec<u8>,
+        input: Vec<u8>,
         value: U256,
+        gas_limit: u64,
         kind: CallKind,
         caller: Address,
     ) {
+        // The refund counter in revm is per frame, so we track the refunds of all parent frames at
+        // the point of the call to report the refund counter of the whole transaction, like geth
+        let refund = self
+            .trace_stack
+            .last()
+            .and_then(|idx| self.traces.arena[*idx].trace.steps.last())
+            .map_or(0, |step| step.gas_refund_counter as i64);
+        self.refund_stack.push(refund);
+
+        // The step of the parent that is being executed is the one making the call
+        let parent_step = self
+            .step_stack
+            .last()
+            .filter(|(trace_idx, _)| self.trace_stack.last() == Some(trace_idx))
+            .map(|(_, step_idx)| *step_idx);
+
         self.trace_stack.push(self.traces.push_trace(
             0,
             CallTrace {
-                depth,
+                depth: data.journaled_state.depth() as usize,
                 address,
                 kind,
-                data: RawOrDecodedCall::Raw(data),
+                data: RawOrDecodedCall::Raw(input),
                 value,
+                gas_limit,
                 status: Return::Continue,
                 caller,
+                parent_step,
+                pre_state: if self.record_state_diff {
+                    account_states(data, &[caller, address], false)
+                } else {
+                    Default::default()
+                },
                 ..Default::default()
             },
         ));
     }
 
-    fn fill_trace(&mut self, status: Return, cost: u64, output: Vec<u8>, address: Option<Address>) {
+    fn fill_trace<DB: Database>(
+        &mut self,
+        data: &EVMData<'_, DB>,
+        status: Return,
+        cost: u64,
+        output: Vec<u8>,
+        address: Option<Address>,
+    ) {
         let success = matches!(status, return_ok!());
+        self.refund_stack.pop();
         let trace = &mut self.traces.arena
             [self.trace_stack.pop().expect("more traces were filled than started")]
         .trace;
@@ -78,6 +127,25 @@ impl Tracer {
         if let Some(address) = address {
             trace.address = address;
         }
+
+        if !self.record_state_diff {
+            return
+        }
+
+        let accounts = [trace.caller, trace.address];
+        trace.post_state = account_states(data, &accounts, false);
+        // The pre state has the values of the slots that were changed so far at the start of the
+        // call, the slots that were first changed during the call had their original values then
+        for (address, state) in account_states(data, &accounts, true) {
+            if let (Some(Some(pre)), Some(state)) = (trace.pre_state.get_mut(&address), state) {
+                let start = std::mem::take(&mut pre.storage);
+                pre.storage = state
+                    .storage
+                    .into_iter()
+                    .map(|(slot, original)| (slot, start.get(&slot).copied().unwrap_or(original)))
+                    .collect();
+            }
+        }
     }
 
     fn start_step<DB: Database>(&mut self, interp: &mut Interpreter, data: &mut EVMData<'_, DB>) {
@@ -88,19 +156,24 @@ impl Tracer {
         self.step_stack.push((trace_idx, trace.trace.steps.len()));
 
         let pc = interp.program_counter();
+        let op = interp.contract.bytecode.bytecode()[pc];
+        let refund = self.refund_stack.last().copied().unwrap_or_default() + interp.gas.refunded();
 
         trace.trace.steps.push(CallTraceStep {
             depth: data.journaled_state.depth(),
             pc,
-            op: OpCode(interp.contract.bytecode.bytecode()[pc]),
+            op: OpCode(op),
             contract: interp.contract.address,
             stack: interp.stack.clone(),
             memory: interp.memory.clone(),
             gas: self.gas_inspector.borrow().gas_remaining(),
-            gas_refund_counter: interp.gas.refunded() as u64,
+            gas_refund_counter: refund.max(0) as u64,
             gas_cost: 0,
             state_diff: None,
             error: None,
+            source_location: None,
+            access: access_warmth(interp, data, op),
+            eof: trace.trace.eof,
         });
     }
 
@@ -135,13 +208,20 @@ impl Tracer {
                 }
                 _ => None,
             };
-
-            step.gas_cost = step.gas - self.gas_inspector.borrow().gas_remaining();
         }
 
-        // Error codes only
-        if status as u8 > Return::OutOfGas as u8 {
-            step.error = Some(format!("{:?}", status));
+        step.gas_cost = if matches!(status, return_ok!() | return_revert!()) {
+            step.gas.saturating_sub(self.gas_inspector.borrow().gas_remaining())
+        } else {
+            // An exceptional halt (e.g. out of gas) consumes all gas that is left in the frame,
+            // but the interpreter does not record the cost of the failing op
+            step.gas
+        };
+
+        // Error codes only. A failed sub-call does not end up here, since the status of the
+        // calling step is still `Continue`, so only the step that actually failed gets the error
+        if !matches!(status, return_ok!()) {
+            step.error = Some(geth_error(status, Some(step.op)));
         }
     }
 }
@@ -150,6 +230,23 @@ impl<DB> Inspector<DB> for Tracer
 where
     DB: Database,
 {
+    fn initialize_interp(
+        &mut self,
+        interp: &mut Interpreter,
+        _: &mut EVMData<'_, DB>,
+        _: bool,
+    ) -> Return {
+        // The interpreter of a call is initialized once its trace is started, with the code that is
+        // executed, which is the code of the code address for `DELEGATECALL`s and the init code for
+        // creations
+        if let Some(idx) = self.trace_stack.last() {
+            self.traces.arena[*idx].trace.eof =
+                interp.contract.bytecode.bytecode().starts_with(&EOF_MAGIC);
+        }
+
+        Return::Continue
+    }
+
     fn step(
         &mut self,
         interp: &mut Interpreter,
@@ -168,6 +265,7 @@ where
     fn log(&mut self, _: &mut EVMData<'_, DB>, _: &Address, topics: &[H256], data: &Bytes) {
         let node = &mut self.traces.arena[*self.trace_stack.last().expect("no ongoing trace")];
         node.ordering.push(LogCallOrder::Log(node.logs.len()));
+        node.first_log_topics.push(topics.first().copied());
         node.logs
             .push(RawOrDecodedLog::Raw(RawLog { topics: topics.to_vec(), data: data.to_vec() }));
     }
@@ -201,11 +299,19 @@ where
             _ => (inputs.context.caller, inputs.context.address),
         };
 
+        if self.record_state_diff {
+            // Make sure the accounts are loaded to record their state. They are loaded by revm
+            // anyway, so this does not change the gas cost
+            let _ = data.journaled_state.load_account(from, data.db);
+            let _ = data.journaled_state.load_account(to, data.db);
+        }
+
         self.start_trace(
-            data.journaled_state.depth() as usize,
+            data,
             to,
             inputs.input.to_vec(),
             inputs.transfer.value,
+            inputs.gas_limit,
             inputs.context.scheme.into(),
             from,
         );
@@ -223,6 +329,7 @@ where
         _is_static: bool,
     ) -> (Return, Gas, Bytes) {
         self.fill_trace(
+            data,
             status,
             gas_used(data.env.cfg.spec_id, gas.spend(), gas.refunded() as u64),
             retdata.to_vec(),
@@ -241,10 +348,11 @@ where
         let _ = data.journaled_state.load_account(inputs.caller, data.db);
         let nonce = data.journaled_state.account(inputs.caller).info.nonce;
         self.start_trace(
-            data.journaled_state.depth() as usize,
+            data,
             get_create_address(inputs, nonce),
             inputs.init_code.to_vec(),
             inputs.value,
+            inputs.gas_limit,
             inputs.scheme.into(),
             inputs.caller,
         );
@@ -272,6 +380,7 @@ where
             None => vec![],
         };
         self.fill_trace(
+            data,
             status,
             gas_used(data.env.cfg.spec_id, gas.spend(), gas.refunded() as u64),
             code,
@@ -281,3 +390,71 @@ where
         (status, address, gas, retdata)
     }
 }
+
+/// Returns whether the account or storage slot the `op` is about to access is warm or cold.
+///
+/// Accounts and slots are warm once they are loaded into the journaled state, which revm does for
+/// the access list, the sender, the recipient and the precompiles, and resets after every
+/// transaction.
+fn access_warmth<DB: Database>(
+    interp: &Interpreter,
+    data: &EVMData<'_, DB>,
+    op: u8,
+) -> Option<Warmth> {
+    let state = &data.journaled_state.state;
+    let address = |n| interp.stack.peek(n).ok().map(|value| Address::from(H256::from_uint(&value)));
+    let warm = match op {
+        opcode::SLOAD | opcode::SSTORE => {
+            let slot = interp.stack.peek(0).ok()?;
+            state
+                .get(&interp.contract.address)
+                .map_or(false, |account| account.storage.contains_key(&slot))
+        }
+        opcode::EXTCODECOPY |
+        opcode::EXTCODEHASH |
+        opcode::EXTCODESIZE |
+        opcode::BALANCE |
+        opcode::SELFDESTRUCT => state.contains_key(&address(0)?),
+        opcode::DELEGATECALL | opcode::CALL | opcode::STATICCALL | opcode::CALLCODE => {
+            state.contains_key(&address(1)?)
+        }
+        _ => return None,
+    };
+    Some(if warm { Warmth::Warm } else { Warmth::Cold })
+}
+
+/// Returns the state of the given accounts if they are loaded, `None` if an account does not exist.
+///
+/// The storage contains the slots that were changed in the transaction, with the values they had
+/// before the transaction if `original` is set, otherwise with their present values.
+fn account_states<DB: Database>(
+    data: &EVMData<'_, DB>,
+    addresses: &[Address],
+    original: bool,
+) -> BTreeMap<Address, Option<AccountState>> {
+    addresses
+        .iter()
+        .filter_map(|address| {
+            let account = data.journaled_state.state.get(address)?;
+            let state = (!account.is_destroyed && !account.info.is_empty()).then(|| AccountState {
+                balance: account.info.balance,
+                nonce: account.info.nonce,
+                code: account
+                    .info
+                    .code
+                    .as_ref()
+                    .map_or(vec![], |code| code.bytes()[..code.len()].to_vec())
+                    .into(),
+                storage: account
+                    .storage
+                    .iter()
+                    .filter(|(_, slot)| slot.original_value() != slot.present_value())
+                    .map(|(key, slot)| {
+                        (*key, if original { slot.original_value() } else { slot.present_value() })
+                    })
+                    .collect(),
+            });
+            Some((*address, state))
+        })
+        .collect()
+}
@@ -55,10 +55,30 @@ pub enum CallKind {
     StaticCall,
     CallCode,
     DelegateCall,
+    /// `AUTHCALL` of [EIP-3074](https://eips.ethereum.org/EIPS/eip-3074), a call on behalf of the
+    /// authorized account, which is the caller of the call
+    AuthCall,
     Create,
     Create2,
 }
 
+impl CallKind {
+    /// Whether this is a contract creation, i.e. `CREATE` or `CREATE2`
+    pub fn is_create(&self) -> bool {
+        matches!(self, CallKind::Create | CallKind::Create2)
+    }
+
+    /// Whether this is a `STATICCALL`
+    pub fn is_static(&self) -> bool {
+        matches!(self, CallKind::StaticCall)
+    }
+
+    /// Whether this is a `DELEGATECALL`
+    pub fn is_delegate(&self) -> bool {
+        matches!(self, CallKind::DelegateCall)
+    }
+}
+
 impl Default for CallKind {
     fn default() -> Self {
         CallKind::Call
@@ -88,9 +108,11 @@ impl From<CreateScheme> for CallKind {
 impl From<CallKind> for ActionType {
     fn from(kind: CallKind) -> Self {
         match kind {
-            CallKind::Call | CallKind::StaticCall | CallKind::DelegateCall | CallKind::CallCode => {
-                ActionType::Call
-            }
+            CallKind::Call |
+            CallKind::StaticCall |
+            CallKind::DelegateCall |
+            CallKind::CallCode |
+            CallKind::AuthCall => ActionType::Call,
             CallKind::Create => ActionType::Create,
             CallKind::Create2 => ActionType::Create,
         }
@@ -104,8 +126,45 @@ impl From<CallKind> for CallType {
             CallKind::StaticCall => CallType::StaticCall,
             CallKind::CallCode => CallType::CallCode,
             CallKind::DelegateCall => CallType::DelegateCall,
+            // parity traces have no call type for `AUTHCALL`, which is a regular call made on
+            // behalf of the authorized account
+            CallKind::AuthCall => CallType::Call,
             CallKind::Create => CallType::None,
             CallKind::Create2 => CallType::None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn call_kind_helpers() {
+        let kinds = [
+            CallKind::Call,
+            CallKind::StaticCall,
+            CallKind::CallCode,
+            CallKind::DelegateCall,
+            CallKind::AuthCall,
+            CallKind::Create,
+            CallKind::Create2,
+        ];
+        let helpers = kinds
+            .iter()
+            .map(|kind| (kind.is_create(), kind.is_static(), kind.is_delegate()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            helpers,
+            vec![
+                (false, false, false),
+                (false, true, false),
+                (false, false, false),
+                (false, false, true),
+                (false, false, false),
+                (true, false, false),
+                (true, false, false),
+            ]
+        );
+    }
+}
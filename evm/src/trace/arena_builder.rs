@@ -0,0 +1,92 @@
+use super::{
+    node::CallTraceNode, CallTrace, CallTraceArena, LogCallOrder, RawOrDecodedLog,
+    RawOrDecodedReturnData,
+};
+use ethers::abi::RawLog;
+use revm::{return_ok, Return};
+
+/// Builds a [CallTraceArena] incrementally from the events of an execution, e.g. while executing
+/// it instead of after.
+///
+/// Every call is started with [Self::push_call] and ended with [Self::end_call], and the logs are
+/// added to the ongoing call with [Self::push_log], which keeps the `parent`, `children`, `idx` and
+/// `ordering` of the nodes consistent.
+#[derive(Debug, Default)]
+pub struct ArenaBuilder {
+    arena: Vec<CallTraceNode>,
+    /// The indices of the ongoing calls, the innermost call last
+    stack: Vec<usize>,
+}
+
+impl ArenaBuilder {
+    /// Creates an empty builder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a call that is made by the ongoing call, or the top-level call if there is none,
+    /// returning its index in the arena.
+    ///
+    /// The depth of the call is set according to the ongoing calls.
+    ///
+    /// # Panics
+    ///
+    /// If the top-level call already ended
+    pub fn push_call(&mut self, mut trace: CallTrace) -> usize {
+        let idx = self.arena.len();
+        let parent = self.stack.last().copied();
+        assert!(parent.is_some() || idx == 0, "the top-level call already ended");
+
+        trace.depth = self.stack.len();
+        if let Some(parent) = parent {
+            let parent = &mut self.arena[parent];
+            parent.ordering.push(LogCallOrder::Call(parent.children.len()));
+            parent.children.push(idx);
+        }
+        self.arena.push(CallTraceNode { parent, idx, trace, ..Default::default() });
+        self.stack.push(idx);
+        idx
+    }
+
+    /// Ends the ongoing call with the given status, gas cost and output
+    ///
+    /// # Panics
+    ///
+    /// If there is no ongoing call
+    pub fn end_call(&mut self, status: Return, gas_cost: u64, output: Vec<u8>) {
+        let idx = self.stack.pop().expect("more calls were ended than started");
+        let trace = &mut self.arena[idx].trace;
+        trace.status = status;
+        trace.success = matches!(status, return_ok!());
+        trace.gas_cost = gas_cost;
+        trace.output = RawOrDecodedReturnData::Raw(output);
+    }
+
+    /// Adds a log emitted by the ongoing call
+    ///
+    /// # Panics
+    ///
+    /// If there is no ongoing call
+    pub fn push_log(&mut self, log: RawLog) {
+        let node = &mut self.arena[*self.stack.last().expect("no ongoing call")];
+        node.ordering.push(LogCallOrder::Log(node.logs.len()));
+        node.first_log_topics.push(log.topics.first().copied());
+        node.logs.push(RawOrDecodedLog::Raw(log));
+    }
+
+    /// Returns the index of the ongoing call, `None` if there is none
+    pub fn current(&self) -> Option<usize> {
+        self.stack.last().copied()
+    }
+
+    /// Returns the arena of all calls.
+    ///
+    /// Calls that were not ended are kept as they were started. The arena of a builder without any
+    /// calls has an empty top-level call, like [CallTraceArena::default].
+    pub fn finish(self) -> CallTraceArena {
+        if self.arena.is_empty() {
+            return CallTraceArena::default()
+        }
+        CallTraceArena { arena: self.arena }
+    }
+}
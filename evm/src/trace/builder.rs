@@ -0,0 +1,107 @@
+use crate::{
+    trace::{
+        node::CallTraceNode, CallTrace, CallTraceArena, RawOrDecodedCall, RawOrDecodedReturnData,
+    },
+    CallKind,
+};
+use ethers::types::{Address, U256};
+use revm::Return;
+
+/// Builds [CallTraceNode]s and [CallTraceArena]s for tests.
+///
+/// The built calls are successful unless [CallTraceNodeBuilder::success] is unset, and the depths
+/// of the calls follow from how their builders are nested with [CallTraceNodeBuilder::child].
+#[derive(Debug, Clone)]
+pub struct CallTraceNodeBuilder {
+    trace: CallTrace,
+    children: Vec<CallTraceNodeBuilder>,
+}
+
+impl Default for CallTraceNodeBuilder {
+    fn default() -> Self {
+        Self {
+            trace: CallTrace { success: true, status: Return::Stop, ..Default::default() },
+            children: Vec::new(),
+        }
+    }
+}
+
+impl CallTraceNodeBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the destination address of the call or the address of the created contract
+    pub fn address(mut self, address: Address) -> Self {
+        self.trace.address = address;
+        self
+    }
+
+    /// Sets the caller of the call
+    pub fn caller(mut self, caller: Address) -> Self {
+        self.trace.caller = caller;
+        self
+    }
+
+    pub fn kind(mut self, kind: CallKind) -> Self {
+        self.trace.kind = kind;
+        self
+    }
+
+    /// Sets the value transferred in the call
+    pub fn value(mut self, value: U256) -> Self {
+        self.trace.value = value;
+        self
+    }
+
+    /// Sets the raw calldata of the call, or the init code of a contract creation
+    pub fn calldata(mut self, calldata: impl Into<Vec<u8>>) -> Self {
+        self.trace.data = RawOrDecodedCall::Raw(calldata.into());
+        self
+    }
+
+    /// Sets the raw return data of the call, or the code of the created contract
+    pub fn output(mut self, output: impl Into<Vec<u8>>) -> Self {
+        self.trace.output = RawOrDecodedReturnData::Raw(output.into());
+        self
+    }
+
+    /// Sets whether the call succeeded, failed calls are reverted
+    pub fn success(mut self, success: bool) -> Self {
+        self.trace.success = success;
+        self.trace.status = if success { Return::Stop } else { Return::Revert };
+        self
+    }
+
+    pub fn gas_cost(mut self, gas_cost: u64) -> Self {
+        self.trace.gas_cost = gas_cost;
+        self
+    }
+
+    /// Adds a sub-call, after the ones added before
+    pub fn child(mut self, child: CallTraceNodeBuilder) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    /// Builds the node of the call, without any sub-calls
+    pub fn build(self) -> CallTraceNode {
+        CallTraceNode { trace: self.trace, ..Default::default() }
+    }
+
+    /// Builds an arena with the call as its root and the sub-calls, recursively, as its nodes
+    pub fn build_arena(self) -> CallTraceArena {
+        let mut arena = CallTraceArena::default();
+        self.push_into(&mut arena, 0);
+        arena
+    }
+
+    fn push_into(self, arena: &mut CallTraceArena, depth: usize) {
+        // The traces are pushed in the order they are executed, so every trace is added to the
+        // last node one level above it, like the tracer does
+        arena.push_trace(0, CallTrace { depth, ..self.trace });
+        for child in self.children {
+            child.push_into(arena, depth + 1);
+        }
+    }
+}
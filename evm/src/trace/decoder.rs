@@ -1,19 +1,21 @@
 use super::{
-    identifier::{SingleSignaturesIdentifier, TraceIdentifier},
+    identifier::{SignatureLookup, SingleSignaturesIdentifier, TraceIdentifier},
     CallTraceArena, RawOrDecodedCall, RawOrDecodedLog, RawOrDecodedReturnData,
 };
 use crate::{
     abi::{CHEATCODE_ADDRESS, CONSOLE_ABI, HARDHAT_CONSOLE_ABI, HARDHAT_CONSOLE_ADDRESS, HEVM_ABI},
     decode,
     executor::inspector::DEFAULT_CREATE2_DEPLOYER,
-    trace::{node::CallTraceNode, utils},
+    trace::{
+        node::{CallTraceNode, DecodeWarning},
+        utils,
+    },
 };
 use ethers::{
     abi::{Abi, Address, Event, Function, Param, ParamType, Token},
     types::H256,
 };
 use foundry_common::{abi::get_indexed_event, SELECTOR_LEN};
-use hashbrown::HashSet;
 use std::collections::{BTreeMap, HashMap};
 
 /// Build a new [CallTraceDecoder].
@@ -46,6 +48,26 @@ impl CallTraceDecoderBuilder {
         self
     }
 
+    /// Show amounts of wei in function inputs and outputs in ether, see [utils::label_param]
+    pub fn with_formatted_amounts(mut self, format_amounts: bool) -> Self {
+        self.decoder.format_amounts = format_amounts;
+        self
+    }
+
+    /// Keep the raw calldata and return data of decoded calls, so they can be decoded again, see
+    /// [CallTraceNode::retain_raw]
+    pub fn with_raw_data(mut self, retain_raw: bool) -> Self {
+        self.decoder.retain_raw = retain_raw;
+        self
+    }
+
+    /// Resolve the functions of calls to contracts without a known ABI with the `lookup`, see
+    /// [SignatureLookup]
+    pub fn with_signature_lookup(mut self, lookup: impl SignatureLookup + 'static) -> Self {
+        self.decoder.signature_lookup = Some(Box::new(lookup));
+        self
+    }
+
     /// Build the decoder.
     pub fn build(self) -> CallTraceDecoder {
         self.decoder
@@ -77,6 +99,12 @@ pub struct CallTraceDecoder {
     pub errors: Abi,
     /// A signature identifier for events and functions.
     pub signature_identifier: Option<SingleSignaturesIdentifier>,
+    /// Whether to show amounts of wei in function inputs and outputs in ether
+    pub format_amounts: bool,
+    /// Whether to keep the raw calldata and return data of decoded calls
+    pub retain_raw: bool,
+    /// A fallback source of functions for calls that could not be decoded otherwise
+    pub signature_lookup: Option<Box<dyn SignatureLookup>>,
 }
 
 impl CallTraceDecoder {
@@ -106,7 +134,7 @@ impl CallTraceDecoder {
                     ],
                     [ParamType::Address],
                 ),
-                precompile(2, "keccak", [ParamType::Bytes], [ParamType::FixedBytes(32)]),
+                precompile(2, "sha256", [ParamType::Bytes], [ParamType::FixedBytes(32)]),
                 precompile(3, "ripemd", [ParamType::Bytes], [ParamType::FixedBytes(32)]),
                 precompile(4, "identity", [ParamType::Bytes], [ParamType::Bytes]),
                 precompile(
@@ -178,6 +206,9 @@ impl CallTraceDecoder {
                 .collect::<BTreeMap<(H256, usize), Vec<Event>>>(),
             errors: Abi::default(),
             signature_identifier: None,
+            format_amounts: false,
+            retain_raw: false,
+            signature_lookup: None,
         }
     }
 
@@ -234,7 +265,14 @@ impl CallTraceDecoder {
         });
     }
 
-    pub async fn decode(&self, traces: &mut CallTraceArena) {
+    /// Decodes the calls and events of the arena and labels its addresses, returning the problems
+    /// encountered along with the index of the node they occurred in, e.g. calls whose selector
+    /// is shared by multiple known functions.
+    pub async fn decode(&self, traces: &mut CallTraceArena) -> Vec<(usize, DecodeWarning)> {
+        // index the errors once, so reverts can be decoded by their selector
+        let errors = decode::errors_by_selector(&self.errors);
+        let mut warnings = Vec::new();
+
         for node in traces.arena.iter_mut() {
             // Set contract name
             if let Some(contract) = self.contracts.get(&node.trace.address).cloned() {
@@ -247,20 +285,63 @@ impl CallTraceDecoder {
             }
 
             // Decode call
-            if let Some(precompile_fn) = self.precompiles.get(&node.trace.address) {
-                node.decode_precompile(precompile_fn, &self.labels);
+            if self.retain_raw {
+                node.retain_raw();
+            }
+            let decoded = match node.decode(
+                &self.precompiles,
+                &self.functions,
+                &self.labels,
+                &errors,
+                self.format_amounts,
+            ) {
+                Some(node_warnings) => {
+                    warnings.extend(node_warnings.into_iter().map(|warning| (node.idx, warning)));
+                    true
+                }
+                None => false,
+            };
+            if decoded || node.trace.is_precompile() {
+                // Nothing else to do, the inputs of unknown precompiles are not function calls
             } else if let RawOrDecodedCall::Raw(ref bytes) = node.trace.data {
                 if bytes.len() >= 4 {
-                    if let Some(funcs) = self.functions.get(&bytes[..SELECTOR_LEN]) {
-                        node.decode_function(funcs, &self.labels, &self.errors);
-                    } else if node.trace.address == DEFAULT_CREATE2_DEPLOYER {
+                    if node.trace.address == DEFAULT_CREATE2_DEPLOYER {
                         node.trace.data =
                             RawOrDecodedCall::Decoded("create2".to_string(), String::new(), vec![]);
-                    } else if let Some(identifier) = &self.signature_identifier {
-                        if let Some(function) =
-                            identifier.write().await.identify_function(&bytes[..SELECTOR_LEN]).await
+                    } else {
+                        let selector: [u8; SELECTOR_LEN] =
+                            bytes[..SELECTOR_LEN].try_into().unwrap();
+                        let identified = match &self.signature_identifier {
+                            Some(identifier) => {
+                                identifier.write().await.identify_function(&selector).await
+                            }
+                            None => None,
+                        };
+                        if let Some(function) = identified {
+                            let node_warnings = node.decode_function_with(
+                                &[function],
+                                &self.labels,
+                                &errors,
+                                self.format_amounts,
+                            );
+                            warnings.extend(
+                                node_warnings.into_iter().map(|warning| (node.idx, warning)),
+                            );
+                        } else if let Some(function) = self
+                            .signature_lookup
+                            .as_ref()
+                            .and_then(|lookup| lookup.resolve(selector))
                         {
-                            node.decode_function(&[function], &self.labels, &self.errors);
+                            let node_warnings = node.decode_function_with(
+                                &[function],
+                                &self.labels,
+                                &errors,
+                                self.format_amounts,
+                            );
+                            warnings.extend(
+                                node_warnings.into_iter().map(|warning| (node.idx, warning)),
+                            );
+                            node.trace.best_effort_signature = true;
                         }
                     }
                 } else {
@@ -272,94 +353,62 @@ impl CallTraceDecoder {
 
                     if let RawOrDecodedReturnData::Raw(bytes) = &node.trace.output {
                         if !node.trace.success {
-                            if let Ok(decoded_error) = decode::decode_revert(
+                            if let Some(decoded_error) = utils::decode_revert(
                                 &bytes[..],
-                                Some(&self.errors),
+                                &[],
+                                &errors,
+                                &self.labels,
+                                self.format_amounts,
                                 Some(node.trace.status),
                             ) {
-                                node.trace.output = RawOrDecodedReturnData::Decoded(format!(
-                                    r#""{}""#,
-                                    decoded_error
-                                ));
+                                node.trace.output = RawOrDecodedReturnData::Decoded(decoded_error);
                             }
                         }
                     }
                 }
             }
 
+            // Decode the sub-calls of multicalls
+            node.decode_multicall(&self.functions, &self.labels, self.format_amounts);
+
             // Decode events
             self.decode_events(node).await;
         }
+
+        traces.label_eip1967_proxies();
+        warnings
     }
 
     async fn decode_events(&self, node: &mut CallTraceNode) {
-        for log in node.logs.iter_mut() {
-            self.decode_event(log).await;
+        for idx in 0..node.logs.len() {
+            if let Some((log, tokens)) = self.decode_event(&node.logs[idx]).await {
+                node.set_decoded_log(idx, log, tokens);
+            }
         }
     }
 
-    async fn decode_event(&self, log: &mut RawOrDecodedLog) {
-        if let RawOrDecodedLog::Raw(raw_log) = log {
-            // do not attempt decoding if no topics
-            if raw_log.topics.is_empty() {
-                return
-            }
-
-            let mut events = vec![];
-            if let Some(evs) = self.events.get(&(raw_log.topics[0], raw_log.topics.len() - 1)) {
-                events = evs.clone();
-            } else if let Some(identifier) = &self.signature_identifier {
-                if let Some(event) =
-                    identifier.write().await.identify_event(&raw_log.topics[0].0).await
-                {
-                    events.push(get_indexed_event(event, raw_log));
-                }
-            }
+    async fn decode_event(&self, log: &RawOrDecodedLog) -> Option<(RawOrDecodedLog, Vec<Token>)> {
+        let raw_log = match log {
+            RawOrDecodedLog::Raw(raw_log) => raw_log,
+            RawOrDecodedLog::Decoded(_, _) => return None,
+        };
+        // do not attempt decoding if no topics
+        if raw_log.topics.is_empty() {
+            return None
+        }
 
-            for mut event in events {
-                // ensure all params are named, otherwise this will cause issues with decoding: See also <https://github.com/rust-ethereum/ethabi/issues/206>
-                let empty_params = patch_nameless_params(&mut event);
-                if let Ok(decoded) = event.parse_log(raw_log.clone()) {
-                    *log = RawOrDecodedLog::Decoded(
-                        event.name,
-                        decoded
-                            .params
-                            .into_iter()
-                            .map(|param| {
-                                // undo patched names
-                                let name = if empty_params.contains(&param.name) {
-                                    "".to_string()
-                                } else {
-                                    param.name
-                                };
-                                (name, self.apply_label(&param.value))
-                            })
-                            .collect(),
-                    );
-                    break
-                }
+        let mut events = vec![];
+        if let Some(evs) = self.events.get(&(raw_log.topics[0], raw_log.topics.len() - 1)) {
+            events = evs.clone();
+        } else if let Some(identifier) = &self.signature_identifier {
+            if let Some(event) = identifier.write().await.identify_event(&raw_log.topics[0].0).await
+            {
+                events.push(get_indexed_event(event, raw_log));
             }
         }
-    }
 
-    fn apply_label(&self, token: &Token) -> String {
-        utils::label(token, &self.labels)
-    }
-}
-
-/// This is a bit horrible but due to <https://github.com/rust-ethereum/ethabi/issues/206> we need to patch nameless (valid) params before decoding a logs, otherwise [`Event::parse_log()`] will result in wrong results since they're identified by name.
-///
-/// Returns a set of patched param names, that originally were empty.
-fn patch_nameless_params(event: &mut Event) -> HashSet<String> {
-    let mut patches = HashSet::new();
-    if event.inputs.iter().filter(|input| input.name.is_empty()).count() > 1 {
-        for (idx, param) in event.inputs.iter_mut().enumerate() {
-            // this is an illegal arg name, which ensures patched identifiers are unique
-            param.name = format!("<patched {}>", idx);
-            patches.insert(param.name.clone());
-        }
+        events.iter().find_map(|event| utils::decode_event(event, raw_log, &self.labels))
     }
-    patches
 }
 
 fn precompile<I, O>(number: u8, name: impl ToString, inputs: I, outputs: O) -> (Address, Function)
@@ -0,0 +1,91 @@
+use ethers::types::{GethTrace, H256, U256};
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+
+/// Serializes a [GethTrace] to the exact JSON returned by geth's `debug_traceTransaction`, e.g.
+/// with `serde_json::to_string(&GethTraceJson(&trace))`, so outputs can be compared byte-for-byte.
+///
+/// Unlike the [Serialize] implementation of [GethTrace], the fields are in geth's order, the return
+/// value, stack items and storage slots are hex encoded without `0x` and stack items are padded to
+/// 32 bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct GethTraceJson<'a>(pub &'a GethTrace);
+
+/// Geth's `ExecutionResult`
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExecutionResult<'a> {
+    gas: u64,
+    failed: bool,
+    return_value: String,
+    struct_logs: Vec<StructLogRes<'a>>,
+}
+
+/// Geth's `StructLogRes`
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StructLogRes<'a> {
+    pc: u64,
+    op: &'a str,
+    gas: u64,
+    gas_cost: u64,
+    depth: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stack: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory: Option<&'a [String]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    storage: Option<BTreeMap<String, String>>,
+    #[serde(rename = "refund", skip_serializing_if = "is_zero")]
+    refund_counter: u64,
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+/// Encodes the stack item as 32 bytes of hex without `0x`
+fn encode_word(word: &U256) -> String {
+    let mut bytes = [0u8; 32];
+    word.to_big_endian(&mut bytes);
+    hex::encode(bytes)
+}
+
+fn encode_slot(slot: &H256) -> String {
+    hex::encode(slot.as_bytes())
+}
+
+impl Serialize for GethTraceJson<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let trace = self.0;
+        ExecutionResult {
+            gas: trace.gas,
+            failed: trace.failed,
+            return_value: hex::encode(&trace.return_value),
+            struct_logs: trace
+                .struct_logs
+                .iter()
+                .map(|log| StructLogRes {
+                    pc: log.pc,
+                    op: &log.op,
+                    gas: log.gas,
+                    gas_cost: log.gas_cost,
+                    depth: log.depth,
+                    error: log.error.as_deref(),
+                    stack: log.stack.as_ref().map(|stack| stack.iter().map(encode_word).collect()),
+                    memory: log.memory.as_deref(),
+                    storage: log.storage.as_ref().map(|storage| {
+                        storage
+                            .iter()
+                            .map(|(key, value)| (encode_slot(key), encode_slot(value)))
+                            .collect()
+                    }),
+                    refund_counter: log.refund_counter.unwrap_or_default(),
+                })
+                .collect(),
+        }
+        .serialize(serializer)
+    }
+}
@@ -0,0 +1,182 @@
+use crate::executor::fork::{BlockingProvider, BlockingProviderError};
+use ethers::{abi::Address, providers::Middleware};
+use futures::future::{join_all, Future};
+use std::{collections::HashMap, pin::Pin};
+use tracing::trace;
+
+/// The future of a [LabelProvider::label] request
+pub type LabelFuture<'a, E> = Pin<Box<dyn Future<Output = Result<Option<String>, E>> + Send + 'a>>;
+
+/// A source of labels for addresses, e.g. ENS names
+pub trait LabelProvider {
+    /// The error of a request that failed, so the label is unknown
+    type Error: std::error::Error;
+
+    /// Returns the label of the address, `None` if it has none
+    fn label(&self, address: Address) -> LabelFuture<'_, Self::Error>;
+}
+
+/// Labels addresses with the name of their ENS reverse record, with the timeout and retry policy
+/// of the provider, see
+/// [AsyncProvider::lookup_address](crate::executor::fork::AsyncProvider::lookup_address)
+impl<M> LabelProvider for BlockingProvider<M>
+where
+    M: Middleware,
+    M::Error: 'static,
+{
+    type Error = BlockingProviderError<M::Error>;
+
+    fn label(&self, address: Address) -> LabelFuture<'_, Self::Error> {
+        Box::pin(self.as_async().lookup_address(address))
+    }
+}
+
+/// Fills label maps with the ENS names of addresses, blocking on the requests.
+///
+/// Names are cached, including the absence of a name, so every address is only looked up once.
+/// Failed lookups are not cached, so they are retried the next time the address is labeled.
+#[derive(Debug)]
+pub struct EnsLabeler<M> {
+    provider: BlockingProvider<M>,
+    cache: HashMap<Address, Option<String>>,
+}
+
+impl<M> EnsLabeler<M>
+where
+    M: Middleware,
+    M::Error: 'static,
+{
+    pub fn new(provider: BlockingProvider<M>) -> Self {
+        Self { provider, cache: HashMap::new() }
+    }
+
+    /// Returns the ENS name of the address, `None` if it has none or the lookup failed
+    pub fn label(&mut self, address: Address) -> Option<String> {
+        if let Some(label) = self.cache.get(&address) {
+            return label.clone()
+        }
+        let label = self.provider.block_on(self.provider.label(address));
+        self.cache_label(address, label)
+    }
+
+    /// Adds the ENS names of the `addresses` that are not labeled yet to `labels`.
+    ///
+    /// Uncached names are looked up concurrently, addresses without a name are left unlabeled so
+    /// they are shown as is.
+    pub fn fill_labels(
+        &mut self,
+        addresses: impl IntoIterator<Item = Address>,
+        labels: &mut HashMap<Address, String>,
+    ) {
+        let addresses = addresses
+            .into_iter()
+            .filter(|address| !labels.contains_key(address))
+            .collect::<Vec<_>>();
+
+        let uncached = addresses
+            .iter()
+            .filter(|address| !self.cache.contains_key(address))
+            .copied()
+            .collect::<Vec<_>>();
+        let names = self
+            .provider
+            .block_on(join_all(uncached.iter().map(|address| self.provider.label(*address))));
+        for (address, name) in uncached.into_iter().zip(names) {
+            self.cache_label(address, name);
+        }
+
+        for address in addresses {
+            if let Some(Some(name)) = self.cache.get(&address) {
+                labels.insert(address, name.clone());
+            }
+        }
+    }
+
+    /// Caches the result of a lookup unless it failed, returning the name
+    fn cache_label(
+        &mut self,
+        address: Address,
+        label: Result<Option<String>, BlockingProviderError<M::Error>>,
+    ) -> Option<String> {
+        match label {
+            Ok(label) => {
+                self.cache.insert(address, label.clone());
+                label
+            }
+            Err(err) => {
+                trace!(target: "ens", ?address, %err, "failed to look up ENS name");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::{
+        abi::{encode, Token},
+        providers::{MockProvider, Provider},
+        types::Bytes,
+    };
+
+    /// Mocks the responses of the `eth_call`s of an ENS lookup: the reverse resolver, the name,
+    /// the forward resolver and the address of the name
+    fn mock_lookup(mock: &MockProvider, resolver: Address, name: &str, address: Address) {
+        // mocked responses are returned last to first
+        for response in [
+            encode(&[Token::Address(address)]),
+            encode(&[Token::Address(resolver)]),
+            encode(&[Token::String(name.to_string())]),
+            encode(&[Token::Address(resolver)]),
+        ] {
+            mock.push(Bytes::from(response)).unwrap();
+        }
+    }
+
+    #[test]
+    fn labels_addresses_with_their_ens_name() {
+        let (provider, mock) = Provider::mocked();
+        let mut labeler = EnsLabeler::new(BlockingProvider::new(provider));
+        let named = Address::random();
+
+        mock_lookup(&mock, Address::random(), "vitalik.eth", named);
+        let mut labels = HashMap::new();
+        labeler.fill_labels([named], &mut labels);
+        assert_eq!(labels, HashMap::from([(named, "vitalik.eth".to_string())]));
+
+        // the name is cached
+        assert_eq!(labeler.label(named), Some("vitalik.eth".to_string()));
+    }
+
+    #[test]
+    fn leaves_unresolved_addresses_unlabeled() {
+        let (provider, mock) = Provider::mocked();
+        let mut labeler = EnsLabeler::new(BlockingProvider::new(provider));
+        let unnamed = Address::random();
+        let labeled = Address::random();
+
+        // the reverse record has no resolver
+        mock.push(Bytes::from(encode(&[Token::Address(Address::zero())]))).unwrap();
+        let mut labels = HashMap::from([(labeled, "Labeled".to_string())]);
+        labeler.fill_labels([unnamed, labeled], &mut labels);
+
+        assert_eq!(labels, HashMap::from([(labeled, "Labeled".to_string())]));
+        assert_eq!(labeler.cache, HashMap::from([(unnamed, None)]));
+        assert_eq!(labeler.label(unnamed), None);
+    }
+
+    #[test]
+    fn does_not_cache_failed_lookups() {
+        let (provider, mock) = Provider::mocked();
+        let mut labeler = EnsLabeler::new(BlockingProvider::new(provider));
+        let named = Address::random();
+
+        // there is no response to the request
+        assert_eq!(labeler.label(named), None);
+        assert!(labeler.cache.is_empty());
+
+        mock_lookup(&mock, Address::random(), "vitalik.eth", named);
+        assert_eq!(labeler.label(named), Some("vitalik.eth".to_string()));
+    }
+}
@@ -0,0 +1,141 @@
+use super::{AddressIdentity, TraceIdentifier};
+use ethers::abi::Address;
+use foundry_config::Chain;
+use std::collections::HashMap;
+
+/// Contracts deployed at the same address on every chain
+const ALL_CHAINS: &[(&str, &str)] = &[
+    ("0xcA11bde05977b3631167028862bE2a173976CA11", "Multicall3"),
+    ("0x000000000022D473030F116dDEE9F6B43aC78BA3", "Permit2"),
+];
+
+/// Contracts on Ethereum mainnet
+const MAINNET: &[(&str, &str)] = &[
+    ("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2", "WETH"),
+    ("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48", "USDC"),
+    ("0xdAC17F958D2ee523a2206206994597C13D831ec7", "USDT"),
+    ("0x6B175474E89094C44Da98b954EedeAC495271d0F", "DAI"),
+    ("0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D", "UniswapV2Router02"),
+    ("0xE592427A0AEce92De3Edee1F18E0157C05861564", "UniswapV3SwapRouter"),
+    ("0x68b3465833fb72A70ecDF485E0e4C7bD8665Fc45", "UniswapV3SwapRouter02"),
+];
+
+/// Contracts on Goerli
+const GOERLI: &[(&str, &str)] = &[
+    ("0xB4FBF271143F4FBf7B91A5ded31805e42b2208d6", "WETH"),
+    ("0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D", "UniswapV2Router02"),
+    ("0xE592427A0AEce92De3Edee1F18E0157C05861564", "UniswapV3SwapRouter"),
+];
+
+/// A trace identifier that labels well-known contracts of a chain, like WETH, USDC, the Uniswap
+/// routers, Multicall3 and Permit2.
+///
+/// This makes traces of forked chains readable without labeling these contracts manually, which is
+/// why `forge test`, `forge script` and `cast run` use it for the chain they fork. The
+/// [CallTraceDecoder](crate::trace::CallTraceDecoder) keeps the labels it already has, so user
+/// supplied labels take precedence, and the known contracts can be changed with
+/// [Self::with_label] and [Self::without].
+#[derive(Debug, Clone, Default)]
+pub struct KnownContractsIdentifier {
+    labels: HashMap<Address, String>,
+}
+
+impl KnownContractsIdentifier {
+    /// Creates an identifier for the known contracts of the given chain
+    pub fn new(chain: impl Into<Chain>) -> Self {
+        let contracts = match chain.into().id() {
+            1 => MAINNET,
+            5 => GOERLI,
+            _ => &[],
+        };
+        let labels = ALL_CHAINS
+            .iter()
+            .chain(contracts)
+            .map(|(address, label)| {
+                (address.parse().expect("valid known address"), label.to_string())
+            })
+            .collect();
+        Self { labels }
+    }
+
+    /// Labels the contract at `address` with `label`, replacing its known label if any
+    pub fn with_label(mut self, address: Address, label: impl Into<String>) -> Self {
+        self.labels.insert(address, label.into());
+        self
+    }
+
+    /// Does not label the contract at `address`
+    pub fn without(mut self, address: Address) -> Self {
+        self.labels.remove(&address);
+        self
+    }
+
+    /// Returns the label of the contract at `address`, if known
+    pub fn label(&self, address: &Address) -> Option<&str> {
+        self.labels.get(address).map(String::as_str)
+    }
+}
+
+impl TraceIdentifier for KnownContractsIdentifier {
+    fn identify_addresses(
+        &mut self,
+        addresses: Vec<(&Address, Option<&Vec<u8>>)>,
+    ) -> Vec<AddressIdentity> {
+        addresses
+            .into_iter()
+            .filter_map(|(address, _)| {
+                Some(AddressIdentity {
+                    address: *address,
+                    label: Some(self.labels.get(address)?.clone()),
+                    contract: None,
+                    abi: None,
+                    artifact_id: None,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::Chain as NamedChain;
+
+    #[test]
+    fn labels_known_contracts_per_chain() {
+        let weth = "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2".parse().unwrap();
+        let multicall3 = "0xcA11bde05977b3631167028862bE2a173976CA11".parse().unwrap();
+
+        let mainnet = KnownContractsIdentifier::new(NamedChain::Mainnet);
+        assert_eq!(mainnet.label(&weth), Some("WETH"));
+        assert_eq!(mainnet.label(&multicall3), Some("Multicall3"));
+
+        let other = KnownContractsIdentifier::new(Chain::Id(1337));
+        assert_eq!(other.label(&weth), None);
+        assert_eq!(other.label(&multicall3), Some("Multicall3"));
+
+        let mut overridden = mainnet.with_label(weth, "Wrapped Ether").without(multicall3);
+        let identities = overridden.identify_addresses(vec![(&weth, None), (&multicall3, None)]);
+        assert_eq!(identities.len(), 1);
+        assert_eq!(identities[0].label.as_deref(), Some("Wrapped Ether"));
+    }
+
+    #[test]
+    fn keeps_user_labels() {
+        use crate::trace::{CallTraceDecoderBuilder, CallTraceNodeBuilder};
+
+        let weth = "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2".parse().unwrap();
+        let usdc = "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48".parse().unwrap();
+        let arena = CallTraceNodeBuilder::new()
+            .address(weth)
+            .child(CallTraceNodeBuilder::new().address(usdc))
+            .build_arena();
+
+        let mut decoder = CallTraceDecoderBuilder::new()
+            .with_labels([(weth, "MyWETH".to_string())].into_iter().collect())
+            .build();
+        decoder.identify(&arena, &mut KnownContractsIdentifier::new(NamedChain::Mainnet));
+        assert_eq!(decoder.labels.get(&weth).map(String::as_str), Some("MyWETH"));
+        assert_eq!(decoder.labels.get(&usdc).map(String::as_str), Some("USDC"));
+    }
+}
@@ -4,8 +4,16 @@ pub use local::LocalTraceIdentifier;
 mod etherscan;
 pub use etherscan::EtherscanIdentifier;
 
+mod ens;
+pub use ens::{EnsLabeler, LabelFuture, LabelProvider};
+
+mod known;
+pub use known::KnownContractsIdentifier;
+
 mod signatures;
-pub use signatures::{SignaturesIdentifier, SingleSignaturesIdentifier};
+pub use signatures::{
+    InMemorySignatureLookup, SignatureLookup, SignaturesIdentifier, SingleSignaturesIdentifier,
+};
 
 use ethers::{
     abi::{Abi, Address},
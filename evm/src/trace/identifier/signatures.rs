@@ -6,7 +6,12 @@ use foundry_common::{
 };
 use hashbrown::HashSet;
 use serde::{Deserialize, Serialize};
-use std::{collections::BTreeMap, path::PathBuf, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::PathBuf,
+    sync::Arc,
+};
 use tokio::sync::RwLock;
 use tracing::{trace, warn};
 
@@ -158,6 +163,47 @@ pub struct CachedSignatures {
     pub functions: BTreeMap<String, String>,
 }
 
+/// A source of function signatures by selector, which the [CallTraceDecoder] consults for calls
+/// to contracts without a known ABI.
+///
+/// Functions resolved this way are best-effort, since different functions can have the same
+/// selector, and calls decoded with them are marked as such, see
+/// [CallTrace::best_effort_signature].
+///
+/// [CallTraceDecoder]: crate::trace::CallTraceDecoder
+/// [CallTrace::best_effort_signature]: crate::trace::CallTrace::best_effort_signature
+pub trait SignatureLookup: fmt::Debug + Send + Sync {
+    /// Returns the function with the given selector, if known
+    fn resolve(&self, selector: [u8; 4]) -> Option<Function>;
+}
+
+/// A [SignatureLookup] backed by a map of functions, e.g. loaded from a cache of 4byte.directory
+#[derive(Debug, Clone, Default)]
+pub struct InMemorySignatureLookup {
+    functions: HashMap<[u8; 4], Function>,
+}
+
+impl InMemorySignatureLookup {
+    /// Adds the function, replacing a function with the same selector
+    pub fn insert(&mut self, func: Function) {
+        self.functions.insert(func.short_signature(), func);
+    }
+}
+
+impl FromIterator<Function> for InMemorySignatureLookup {
+    fn from_iter<T: IntoIterator<Item = Function>>(iter: T) -> Self {
+        let mut lookup = Self::default();
+        iter.into_iter().for_each(|func| lookup.insert(func));
+        lookup
+    }
+}
+
+impl SignatureLookup for InMemorySignatureLookup {
+    fn resolve(&self, selector: [u8; 4]) -> Option<Function> {
+        self.functions.get(&selector).cloned()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
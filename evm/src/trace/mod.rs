@@ -3,26 +3,42 @@
 /// Identifiers figure out what ABIs and labels belong to all the addresses of the trace.
 pub mod identifier;
 
+mod arena_builder;
+#[cfg(any(test, feature = "test-util"))]
+mod builder;
 mod decoder;
+mod geth;
 pub mod node;
-mod utils;
+pub(crate) mod utils;
 
 use crate::{
     abi::CHEATCODE_ADDRESS, debug::Instruction, trace::identifier::LocalTraceIdentifier, CallKind,
 };
+pub use arena_builder::ArenaBuilder;
+#[cfg(any(test, feature = "test-util"))]
+pub use builder::CallTraceNodeBuilder;
 pub use decoder::{CallTraceDecoder, CallTraceDecoderBuilder};
 use ethers::{
-    abi::{ethereum_types::BigEndianHash, Address, RawLog},
-    core::utils::to_checksum,
-    types::{GethDebugTracingOptions, GethTrace, StructLog, H256, U256},
+    abi::{ethereum_types::BigEndianHash, Address, RawLog, Token},
+    core::utils::{keccak256, to_checksum},
+    solc::sourcemap::{Jump, SourceElement},
+    types::{
+        AccountDiff, Bytes, ChangedType, Diff, GethDebugTracingOptions, GethTrace, MemoryDiff,
+        StateDiff, StorageDiff, StructLog, VMExecutedOperation, VMOperation, VMTrace, H256, U256,
+    },
 };
-use foundry_common::contracts::{ContractsByAddress, ContractsByArtifact};
+use foundry_common::{
+    abi::{encode_args, get_func},
+    contracts::{ContractsByAddress, ContractsByArtifact},
+    SELECTOR_LEN,
+};
+pub use geth::GethTraceJson;
 use hashbrown::HashMap;
 use node::CallTraceNode;
-use revm::{CallContext, Memory, Return, Stack};
+use revm::{opcode, CallContext, Memory, Return, Stack};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{BTreeMap, HashSet},
+    collections::{btree_map::Entry, BTreeMap, BTreeSet, HashSet},
     fmt::{self, Write},
 };
 use yansi::{Color, Paint};
@@ -74,6 +90,413 @@ impl CallTraceArena {
         }
     }
 
+    /// Serializes the arena into a compact binary format, to be read back with
+    /// [Self::from_bytes].
+    ///
+    /// This is considerably smaller and faster than JSON for large traces, e.g. to cache them on
+    /// disk.
+    pub fn to_bytes(&self) -> bincode::Result<Vec<u8>> {
+        bincode::serialize(self)
+    }
+
+    /// Deserializes an arena that was serialized with [Self::to_bytes]
+    pub fn from_bytes(bytes: &[u8]) -> bincode::Result<Self> {
+        bincode::deserialize(bytes)
+    }
+
+    /// Returns a hash of all calls in the arena that is stable across runs, see
+    /// [CallTraceNode::trace_hash]
+    pub fn trace_hash(&self) -> H256 {
+        self.arena[0].trace_hash(&self.arena)
+    }
+
+    /// Returns the logs whose first topic is `topic0`, i.e. the logs of the event with that
+    /// signature, along with the index of the node that emitted them.
+    ///
+    /// Logs are matched whether they are decoded or not, see [CallTraceNode::log_topic0].
+    pub fn find_logs_by_topic0(&self, topic0: H256) -> Vec<(usize, &RawOrDecodedLog)> {
+        self.arena
+            .iter()
+            .flat_map(|node| {
+                node.logs
+                    .iter()
+                    .enumerate()
+                    .filter(move |(idx, _)| node.log_topic0(*idx) == Some(topic0))
+                    .map(move |(_, log)| (node.idx, log))
+            })
+            .collect()
+    }
+
+    /// Returns a sub-arena of the nodes whose address matches the `predicate` and their ancestors,
+    /// which are kept for context.
+    ///
+    /// The nodes are reindexed in the sub-arena, and the ordering of each node only refers to its
+    /// remaining calls. The top-level call is always kept.
+    pub fn filter_addresses(&self, predicate: impl Fn(&Address) -> bool) -> CallTraceArena {
+        let mut keep = vec![false; self.arena.len()];
+        keep[0] = true;
+        for node in self.arena.iter().filter(|node| predicate(&node.trace.address)) {
+            let mut idx = Some(node.idx);
+            while let Some(current) = idx.filter(|current| !keep[*current]) {
+                keep[current] = true;
+                idx = self.arena[current].parent;
+            }
+        }
+
+        // The new index of every kept node
+        let mut indices = vec![None; self.arena.len()];
+        self.arena
+            .iter()
+            .filter(|node| keep[node.idx])
+            .enumerate()
+            .for_each(|(new_idx, node)| indices[node.idx] = Some(new_idx));
+
+        let arena = self
+            .arena
+            .iter()
+            .filter(|node| keep[node.idx])
+            .map(|node| {
+                // The new location of every kept child in `children`
+                let mut locations = Vec::with_capacity(node.children.len());
+                let mut children = Vec::new();
+                for child in &node.children {
+                    locations.push(indices[*child].map(|_| children.len()));
+                    if let Some(child) = indices[*child] {
+                        children.push(child);
+                    }
+                }
+
+                CallTraceNode {
+                    parent: node.parent.and_then(|parent| indices[parent]),
+                    children,
+                    idx: indices[node.idx].expect("node is kept"),
+                    trace: node.trace.clone(),
+                    logs: node.logs.clone(),
+                    first_log_topics: node.first_log_topics.clone(),
+                    decoded_log_params: node.decoded_log_params.clone(),
+                    annotations: node.annotations.clone(),
+                    ordering: node
+                        .ordering
+                        .iter()
+                        .filter_map(|order| match order {
+                            LogCallOrder::Log(idx) => Some(LogCallOrder::Log(*idx)),
+                            LogCallOrder::Call(idx) => locations[*idx].map(LogCallOrder::Call),
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+
+        CallTraceArena { arena }
+    }
+
+    /// Returns a sub-arena without the calls whose gas cost is below `min_gas`, to focus on the
+    /// expensive calls.
+    ///
+    /// The kept sub-calls of a removed call take its place in its nearest kept ancestor, while its
+    /// logs are removed with it. Reverted calls and the top-level call are always kept. The nodes
+    /// are reindexed in the sub-arena, see [Self::filter_addresses].
+    pub fn prune_below_gas(&self, min_gas: u64) -> CallTraceArena {
+        self.prune(|node| !node.trace.success || node.trace.gas_cost >= min_gas)
+    }
+
+    /// Returns a sub-arena without the calls to the cheatcode address, e.g. `vm.prank`, for traces
+    /// that look like the ones of production calls.
+    ///
+    /// Like in [Self::prune_below_gas], the sub-calls of a removed call take its place while its
+    /// logs are removed with it. See [Self::filter_cheatcodes] to keep them, e.g. for debugging.
+    pub fn without_cheatcodes(&self) -> CallTraceArena {
+        self.filter_cheatcodes(false)
+    }
+
+    /// Returns the arena as is if `keep_cheatcodes` is set, otherwise without the calls to the
+    /// cheatcode address, see [Self::without_cheatcodes].
+    pub fn filter_cheatcodes(&self, keep_cheatcodes: bool) -> CallTraceArena {
+        if keep_cheatcodes {
+            return self.clone()
+        }
+        self.prune(|node| node.trace.address != CHEATCODE_ADDRESS)
+    }
+
+    /// Returns a sub-arena of the top-level call and the calls for which `keep` returns true,
+    /// where the kept sub-calls of a removed call take its place in its nearest kept ancestor.
+    fn prune(&self, keep: impl Fn(&CallTraceNode) -> bool) -> CallTraceArena {
+        let keep = self.arena.iter().map(|node| node.idx == 0 || keep(node)).collect::<Vec<_>>();
+
+        // The new index of every kept node
+        let mut indices = vec![None; self.arena.len()];
+        self.arena
+            .iter()
+            .filter(|node| keep[node.idx])
+            .enumerate()
+            .for_each(|(new_idx, node)| indices[node.idx] = Some(new_idx));
+
+        // Collects the call itself if it's kept, otherwise its nearest kept descendants in order
+        fn surviving_calls(
+            arena: &[CallTraceNode],
+            keep: &[bool],
+            idx: usize,
+            calls: &mut Vec<usize>,
+        ) {
+            if keep[idx] {
+                calls.push(idx);
+            } else {
+                for child in &arena[idx].children {
+                    surviving_calls(arena, keep, *child, calls);
+                }
+            }
+        }
+
+        let arena = self
+            .arena
+            .iter()
+            .filter(|node| keep[node.idx])
+            .map(|node| {
+                let mut children = Vec::new();
+                let mut ordering = Vec::with_capacity(node.ordering.len());
+                for order in &node.ordering {
+                    match order {
+                        LogCallOrder::Log(idx) => ordering.push(LogCallOrder::Log(*idx)),
+                        LogCallOrder::Call(idx) => {
+                            let mut calls = Vec::new();
+                            surviving_calls(&self.arena, &keep, node.children[*idx], &mut calls);
+                            for call in calls {
+                                ordering.push(LogCallOrder::Call(children.len()));
+                                children.push(indices[call].expect("call is kept"));
+                            }
+                        }
+                    }
+                }
+
+                // A call that takes the place of removed calls is made at the step of the kept
+                // ancestor that made the top-most removed call
+                let mut parent = node.parent;
+                let mut trace = node.trace.clone();
+                while let Some(current) = parent.filter(|current| !keep[*current]) {
+                    trace.parent_step = self.arena[current].trace.parent_step;
+                    parent = self.arena[current].parent;
+                }
+
+                CallTraceNode {
+                    parent: parent.and_then(|parent| indices[parent]),
+                    children,
+                    idx: indices[node.idx].expect("node is kept"),
+                    trace,
+                    logs: node.logs.clone(),
+                    first_log_topics: node.first_log_topics.clone(),
+                    decoded_log_params: node.decoded_log_params.clone(),
+                    annotations: node.annotations.clone(),
+                    ordering,
+                }
+            })
+            .collect();
+
+        CallTraceArena { arena }
+    }
+
+    /// Returns whether the arenas have the same calls in the same structure, ignoring labels and
+    /// decoding, see [CallTraceNode::eq_structural]
+    pub fn eq_structural(&self, other: &CallTraceArena) -> bool {
+        self.arena.len() == other.arena.len() &&
+            self.arena.iter().zip(&other.arena).all(|(node, other)| {
+                node.parent == other.parent &&
+                    node.children == other.children &&
+                    node.eq_structural(other)
+            })
+    }
+
+    /// Returns a display of the calls in the arena, where runs of at least `min_repeats`
+    /// consecutive sub-calls to the same function of the same address, e.g. in loops, are folded
+    /// into a single line with their total gas cost and number of calls.
+    ///
+    /// Calls are not folded if `min_repeats` is less than 2. The arena itself is left as is.
+    pub fn folded(&self, min_repeats: usize) -> CallTraceArenaDisplay<'_> {
+        self.display().fold_repeats(min_repeats)
+    }
+
+    /// Returns a display of the calls in the arena that can be configured per render, e.g. to fold
+    /// repeated calls or to change how much raw data is shown.
+    ///
+    /// By default, raw calldata and return data is truncated to [DEFAULT_MAX_RAW_BYTES] bytes.
+    pub fn display(&self) -> CallTraceArenaDisplay<'_> {
+        CallTraceArenaDisplay {
+            arena: self,
+            min_repeats: 0,
+            max_raw_bytes: Some(DEFAULT_MAX_RAW_BYTES),
+            show_selectors: false,
+            color: true,
+        }
+    }
+
+    /// Returns the gas cost of each node as a percentage of the root node's
+    /// [cumulative gas](CallTraceNode::cumulative_gas), keyed by the index of the node.
+    ///
+    /// The percentages are not rounded, format them with e.g. `{:.2}` for display.
+    pub fn gas_percentages(&self) -> HashMap<usize, f64> {
+        let total = self.arena[0].cumulative_gas(&self.arena);
+        self.arena
+            .iter()
+            .map(|node| {
+                let percentage = match total {
+                    0 => 0.0,
+                    total => node.trace.gas_cost as f64 / total as f64 * 100.0,
+                };
+                (node.idx, percentage)
+            })
+            .collect()
+    }
+
+    /// Reconciles the gas used according to the recorded steps with the `expected` gas used by the
+    /// transaction, e.g. from its receipt, to find gaps in the gas tracking of the steps.
+    ///
+    /// The gas used is the `intrinsic_gas` of the transaction plus the gas costs of the steps of
+    /// the top-level call, which include the gas used by their sub-calls, minus the refund, which
+    /// is capped to a fifth of the gas used (EIP-3529).
+    ///
+    /// Returns `None` if the steps of the top-level call were not recorded.
+    pub fn reconcile_gas(&self, intrinsic_gas: u64, expected: u64) -> Option<GasReconciliation> {
+        let steps = &self.arena[0].trace.steps;
+        let refund_counter = steps.last()?.gas_refund_counter;
+
+        let execution_gas = steps.iter().map(|step| step.gas_cost).sum::<u64>();
+        let gas_used = intrinsic_gas + execution_gas;
+        let refund = refund_counter.min(gas_used / 5);
+        Some(GasReconciliation { execution_gas, refund, gas_used: gas_used - refund, expected })
+    }
+
+    /// Returns the gas costs of the functions called in the arena, keyed by the called address and
+    /// the signature of the function, for gas snapshots that can be compared across runs.
+    ///
+    /// The gas costs of repeated calls to the same function are aggregated. Calls that are not
+    /// decoded are keyed by their selector, e.g. `0xa9059cbb`, or as `fallback` if they have none,
+    /// and contract creations as `constructor`.
+    pub fn gas_snapshot(&self) -> BTreeMap<Address, BTreeMap<String, FunctionGas>> {
+        let mut snapshot: BTreeMap<Address, BTreeMap<String, FunctionGas>> = BTreeMap::new();
+        for node in &self.arena {
+            let signature = match &node.trace.data {
+                _ if node.kind().is_create() => "constructor".to_string(),
+                RawOrDecodedCall::Decoded(name, signature, _) if signature.is_empty() => {
+                    name.clone()
+                }
+                RawOrDecodedCall::Decoded(_, signature, _) => signature.clone(),
+                RawOrDecodedCall::Raw(_) => node.trace.data.selector().map_or_else(
+                    || "fallback".to_string(),
+                    |selector| format!("0x{}", hex::encode(selector)),
+                ),
+            };
+            snapshot
+                .entry(node.trace.address)
+                .or_default()
+                .entry(signature)
+                .or_default()
+                .add(node.trace.gas_cost);
+        }
+        snapshot
+    }
+
+    /// Compares the calls of the arena with the calls of the `other` arena.
+    ///
+    /// Both arenas are walked from the top-level call in lockstep, and calls are matched by their
+    /// position among the sub-calls of their parent, their kind and their selector. A call that
+    /// does not match is reported as removed from this arena, and the call at its position as
+    /// added in the `other` arena, along with all of their sub-calls.
+    pub fn diff(&self, other: &[CallTraceNode]) -> TraceDiff {
+        let mut diff = TraceDiff::default();
+        if other.is_empty() {
+            push_call_diffs(&self.arena, 0, |old| CallDiff::Removed { old }, &mut diff.calls);
+        } else {
+            diff_calls(&self.arena, other, 0, 0, &mut diff.calls);
+        }
+        diff
+    }
+
+    /// Returns the arena with the `DELEGATECALL`s of proxies merged into the proxy calls.
+    ///
+    /// A call is considered a call to a proxy if its only sub-call is a `DELEGATECALL`. The merged
+    /// call keeps the address and label of the proxy, but shows the calldata and return data of
+    /// the implementation. The steps, logs and sub-calls of the `DELEGATECALL` are added to the
+    /// merged call in execution order. All other calls are untouched.
+    pub fn collapse_delegate_calls(&self) -> CallTraceArena {
+        let mut arena = Vec::with_capacity(self.arena.len());
+        self.collapse_delegate_calls_into(0, None, 0, &mut arena);
+        CallTraceArena { arena }
+    }
+
+    /// Adds the node at `idx` and its sub-calls to `arena`, returning the new index of the node.
+    ///
+    /// The `step_offset` is added to the calling steps of the node, since the steps of its caller
+    /// may have been merged with those of a `DELEGATECALL`.
+    fn collapse_delegate_calls_into(
+        &self,
+        idx: usize,
+        parent: Option<usize>,
+        step_offset: usize,
+        arena: &mut Vec<CallTraceNode>,
+    ) -> usize {
+        let mut node = self.arena[idx].clone();
+        node.trace.parent_step = node.trace.parent_step.map(|step| step + step_offset);
+
+        // The index of the first step of the call whose sub-calls are the children of the node
+        let mut children_offset = 0;
+        while let [child] = node.children[..] {
+            let child = &self.arena[child];
+            if !child.kind().is_delegate() {
+                break
+            }
+
+            node.trace.data = child.trace.data.clone();
+            node.trace.decoded_inputs = child.trace.decoded_inputs.clone();
+            node.trace.output = child.trace.output.clone();
+
+            // The steps of the `DELEGATECALL` follow the step that made it
+            let offset = child
+                .trace
+                .parent_step
+                .map_or(node.trace.steps.len(), |step| children_offset + step + 1);
+            node.trace.steps.splice(offset..offset, child.trace.steps.iter().cloned());
+            children_offset = offset;
+
+            let logs = node.logs.len();
+            node.logs.extend(child.logs.iter().cloned());
+            node.first_log_topics.resize(logs, None);
+            node.first_log_topics.extend(child.first_log_topics.iter().copied());
+            node.first_log_topics.resize(node.logs.len(), None);
+            node.decoded_log_params.resize(logs, None);
+            node.decoded_log_params.extend(child.decoded_log_params.iter().cloned());
+            node.decoded_log_params.resize(node.logs.len(), None);
+            node.ordering = node
+                .ordering
+                .iter()
+                .flat_map(|order| match order {
+                    LogCallOrder::Log(idx) => vec![LogCallOrder::Log(*idx)],
+                    LogCallOrder::Call(_) => child
+                        .ordering
+                        .iter()
+                        .map(|order| match order {
+                            LogCallOrder::Log(idx) => LogCallOrder::Log(logs + idx),
+                            LogCallOrder::Call(idx) => LogCallOrder::Call(*idx),
+                        })
+                        .collect(),
+                })
+                .collect();
+            node.children = child.children.clone();
+        }
+
+        let new_idx = arena.len();
+        node.idx = new_idx;
+        node.parent = parent;
+        let children = std::mem::take(&mut node.children);
+        arena.push(node);
+
+        arena[new_idx].children = children
+            .into_iter()
+            .map(|child| {
+                self.collapse_delegate_calls_into(child, Some(new_idx), children_offset, arena)
+            })
+            .collect();
+        new_idx
+    }
+
     pub fn addresses(&self) -> HashSet<(&Address, Option<&Vec<u8>>)> {
         self.arena
             .iter()
@@ -89,33 +512,25 @@ impl CallTraceArena {
             .collect()
     }
 
+    /// Returns the geth-style `debug_traceTransaction` trace of this arena.
+    ///
+    /// The top-level `gas` is the total gas used by the transaction, whereas every `StructLog`
+    /// carries the remaining gas and the cost of its step.
     pub fn geth_trace(&self, receipt_gas_used: U256, opts: GethDebugTracingOptions) -> GethTrace {
-        let mut storage = HashMap::<Address, BTreeMap<H256, H256>>::new();
-        let mut trace = self.arena.iter().fold(GethTrace::default(), |mut acc, trace| {
-            acc.failed |= !trace.trace.success;
-
-            acc.struct_logs.extend(trace.trace.steps.iter().map(|step| {
-                let mut log: StructLog = step.into();
-
-                if !opts.disable_storage.unwrap_or_default() {
-                    let contract_storage = storage.entry(step.contract).or_default();
-                    if let Some((key, value)) = step.state_diff {
-                        contract_storage.insert(H256::from_uint(&key), H256::from_uint(&value));
-                        log.storage = Some(contract_storage.clone());
-                    }
-                }
-                if opts.disable_stack.unwrap_or_default() {
-                    log.stack = None;
-                }
-                if !opts.enable_memory.unwrap_or_default() {
-                    log.memory = None;
-                }
-
-                log
-            }));
+        self.geth_trace_with(receipt_gas_used, opts.into())
+    }
 
-            acc
-        });
+    /// Same as [Self::geth_trace], but the memory, stack and storage of the steps are only
+    /// collected if enabled in `opts`
+    ///
+    /// The steps of all calls are in execution order, i.e. the steps of a sub-call directly follow
+    /// the step of its caller that made the call.
+    pub fn geth_trace_with(&self, receipt_gas_used: U256, opts: GethTraceOptions) -> GethTrace {
+        let mut trace = GethTrace {
+            failed: self.arena.iter().any(|node| !node.trace.success),
+            ..Default::default()
+        };
+        self.add_struct_logs(0, &opts, &mut HashMap::new(), &mut trace.struct_logs);
 
         trace.gas = receipt_gas_used.as_u64();
         if let Some(last_trace) = self.arena.first() {
@@ -124,459 +539,4509 @@ impl CallTraceArena {
 
         trace
     }
-}
 
-const PIPE: &str = "  │ ";
-const EDGE: &str = "  └─ ";
-const BRANCH: &str = "  ├─ ";
-const CALL: &str = "→ ";
-const RETURN: &str = "← ";
+    /// Adds the `StructLog`s of the node at `idx` and all of its sub-calls in execution order
+    fn add_struct_logs(
+        &self,
+        idx: usize,
+        opts: &GethTraceOptions,
+        storage: &mut HashMap<Address, BTreeMap<H256, H256>>,
+        struct_logs: &mut Vec<StructLog>,
+    ) {
+        let node = &self.arena[idx];
+        let include_steps = opts.max_depth.map_or(true, |depth| node.trace.depth <= depth);
+        // Sub-calls are ordered by their calling step, calls without a recorded calling step
+        // follow all steps of the node
+        let mut children = node.children.iter().peekable();
 
-impl fmt::Display for CallTraceArena {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fn inner(
-            arena: &CallTraceArena,
-            writer: &mut (impl Write + ?Sized),
-            idx: usize,
-            left: &str,
-            child: &str,
-            verbose: bool,
-        ) -> fmt::Result {
-            let node = &arena.arena[idx];
-
-            // Display trace header
-            if !verbose {
-                writeln!(writer, "{}{}", left, node.trace)?;
-            } else {
-                writeln!(writer, "{}{:#}", left, node.trace)?;
-            }
-
-            // Display logs and subcalls
-            let left_prefix = format!("{child}{BRANCH}");
-            let right_prefix = format!("{child}{PIPE}");
-            for child in &node.ordering {
-                match child {
-                    LogCallOrder::Log(index) => {
-                        let mut log = String::new();
-                        write!(log, "{}", node.logs[*index])?;
-
-                        // Prepend our tree structure symbols to each line of the displayed log
-                        log.lines().enumerate().try_for_each(|(i, line)| {
-                            writeln!(
-                                writer,
-                                "{}{}",
-                                if i == 0 { &left_prefix } else { &right_prefix },
-                                line
-                            )
-                        })?;
-                    }
-                    LogCallOrder::Call(index) => {
-                        inner(
-                            arena,
-                            writer,
-                            node.children[*index],
-                            &left_prefix,
-                            &right_prefix,
-                            verbose,
-                        )?;
-                    }
+        for (step_idx, step) in node.trace.steps.iter().enumerate() {
+            // The storage is also tracked for omitted steps, since deeper calls (e.g.
+            // `DELEGATECALL`s) can change the storage shown for the steps of their callers.
+            let mut changed_storage = None;
+            if !opts.disable_storage {
+                let contract_storage = storage.entry(step.contract).or_default();
+                if let Some((key, value)) = step.state_diff {
+                    contract_storage.insert(H256::from_uint(&key), H256::from_uint(&value));
+                    changed_storage = Some(contract_storage);
                 }
             }
 
-            // Display trace return data
-            let color = trace_color(&node.trace);
-            write!(writer, "{}{}", child, EDGE)?;
-            write!(writer, "{}", color.paint(RETURN))?;
-            if node.trace.created() {
-                if let RawOrDecodedReturnData::Raw(bytes) = &node.trace.output {
-                    writeln!(writer, "{} bytes of code", bytes.len())?;
-                } else {
-                    unreachable!("We should never have decoded calldata for contract creations");
-                }
-            } else {
-                writeln!(writer, "{}", node.trace.output)?;
+            if include_steps {
+                let mut log = step.to_struct_log(opts);
+                log.storage = changed_storage.cloned();
+                struct_logs.push(log);
             }
 
-            Ok(())
+            while let Some(child) = children.next_if(|child| {
+                self.arena[**child].trace.parent_step.map_or(false, |parent| parent <= step_idx)
+            }) {
+                self.add_struct_logs(*child, opts, storage, struct_logs);
+            }
         }
 
-        inner(self, f, 0, "  ", "  ", f.alternate())
+        for child in children {
+            self.add_struct_logs(*child, opts, storage, struct_logs);
+        }
     }
-}
 
-/// A raw or decoded log.
-#[derive(Debug, Clone, PartialEq)]
-pub enum RawOrDecodedLog {
-    /// A raw log
-    Raw(RawLog),
-    /// A decoded log.
-    ///
-    /// The first member of the tuple is the event name, and the second is a vector of decoded
-    /// parameters.
-    Decoded(String, Vec<(String, String)>),
-}
+    /// Returns the state before and after the transaction of all accounts touched by the calls in
+    /// this arena, `None` if an account did not exist.
+    fn account_states(&self) -> BTreeMap<Address, (Option<AccountState>, Option<&AccountState>)> {
+        let mut pre = BTreeMap::<Address, Option<AccountState>>::new();
+        // The node that recorded the latest post state of an account
+        let mut post = BTreeMap::<Address, usize>::new();
 
-impl fmt::Display for RawOrDecodedLog {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            RawOrDecodedLog::Raw(log) => {
-                for (i, topic) in log.topics.iter().enumerate() {
-                    writeln!(
-                        f,
-                        "{:>13}: {}",
-                        if i == 0 { "emit topic 0".to_string() } else { format!("topic {i}") },
-                        Paint::cyan(format!("0x{}", hex::encode(topic)))
-                    )?;
+        for node in &self.arena {
+            for (address, state) in &node.trace.pre_state {
+                // The pre state of a call nested in another call of the account is the state in
+                // the middle of that call, and the outer call already covers all of its changes
+                let nested = std::iter::successors(node.parent, |idx| self.arena[*idx].parent)
+                    .any(|idx| self.arena[idx].trace.pre_state.contains_key(address));
+                if nested {
+                    continue
+                }
+                match pre.entry(*address) {
+                    Entry::Vacant(entry) => {
+                        entry.insert(state.clone());
+                    }
+                    Entry::Occupied(mut entry) => {
+                        // Add the initial values of slots that were changed later on
+                        if let (Some(pre), Some(state)) = (entry.get_mut(), state) {
+                            for (slot, value) in &state.storage {
+                                pre.storage.entry(*slot).or_insert(*value);
+                            }
+                        }
+                    }
                 }
-
-                write!(
-                    f,
-                    "          data: {}",
-                    Paint::cyan(format!("0x{}", hex::encode(&log.data)))
-                )
             }
-            RawOrDecodedLog::Decoded(name, params) => {
-                let params = params
-                    .iter()
-                    .map(|(name, value)| format!("{name}: {value}"))
-                    .collect::<Vec<String>>()
-                    .join(", ");
 
-                write!(f, "emit {}({})", Paint::cyan(name.clone()), params)
+            for address in node.trace.post_state.keys() {
+                // Accounts that were not loaded before the call are created by it
+                pre.entry(*address).or_insert(None);
+                // Nodes ordered after the latest node are either its descendants, which end before
+                // it, or are called after it ended
+                if !post.get(address).map_or(false, |latest| self.is_ancestor(*latest, node.idx)) {
+                    post.insert(*address, node.idx);
+                }
             }
         }
+
+        post.into_iter()
+            .map(|(address, idx)| {
+                let pre = pre.remove(&address).flatten();
+                (address, (pre, self.arena[idx].trace.post_state[&address].as_ref()))
+            })
+            .collect()
     }
-}
 
-/// Ordering enum for calls and logs
-///
-/// i.e. if Call 0 occurs before Log 0, it will be pushed into the `CallTraceNode`'s ordering before
-/// the log.
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub enum LogCallOrder {
-    Log(usize),
-    Call(usize),
-}
+    /// Returns the parity `stateDiff` of all accounts touched by the calls in this arena, which
+    /// requires the state of the accounts to be recorded, see
+    /// [Tracer::with_state_diff_recording](crate::executor::inspector::Tracer::with_state_diff_recording).
+    ///
+    /// Accounts that self-destructed are shown as `Died`, created accounts as `Born`.
+    pub fn parity_state_diff(&self) -> StateDiff {
+        StateDiff(
+            self.account_states()
+                .into_iter()
+                .filter_map(|(address, (pre, post))| {
+                    let pre = pre.as_ref();
 
-// TODO: Maybe unify with output
-/// Raw or decoded calldata.
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
-pub enum RawOrDecodedCall {
-    /// Raw calldata
-    Raw(Vec<u8>),
-    /// Decoded calldata.
+                    let slots = pre
+                        .into_iter()
+                        .chain(post)
+                        .flat_map(|state| state.storage.keys())
+                        .collect::<BTreeSet<_>>();
+                    let storage = slots
+                        .into_iter()
+                        .map(|slot| {
+                            (
+                                H256::from_uint(slot),
+                                diff(
+                                    pre.and_then(|pre| pre.storage.get(slot)).map(H256::from_uint),
+                                    post.and_then(|post| post.storage.get(slot))
+                                        .map(H256::from_uint),
+                                ),
+                            )
+                        })
+                        .filter(|(_, diff)| !matches!(diff, Diff::Same))
+                        .collect::<BTreeMap<_, _>>();
+
+                    let account = AccountDiff {
+                        balance: diff(pre.map(|pre| pre.balance), post.map(|post| post.balance)),
+                        nonce: diff(
+                            pre.map(|pre| pre.nonce.into()),
+                            post.map(|post| post.nonce.into()),
+                        ),
+                        code: diff(
+                            pre.map(|pre| pre.code.clone()),
+                            post.map(|post| post.code.clone()),
+                        ),
+                        storage,
+                    };
+
+                    let unchanged = matches!(account.balance, Diff::Same) &&
+                        matches!(account.nonce, Diff::Same) &&
+                        matches!(account.code, Diff::Same) &&
+                        account.storage.is_empty();
+                    (!unchanged).then_some((address, account))
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns the state of all accounts touched by the calls in this arena before the
+    /// transaction, in the format of geth's `prestateTracer`.
     ///
-    /// The first element in the tuple is the function name, second is the function signature and
-    /// the third element is a vector of decoded parameters.
-    Decoded(String, String, Vec<String>),
-}
+    /// Accounts that did not exist are included with a zero balance. Note that the storage only
+    /// contains the slots that were changed in the transaction, not the ones that were only read.
+    pub fn geth_prestate_trace(&self) -> BTreeMap<Address, PrestateAccount> {
+        self.account_states()
+            .into_iter()
+            .map(|(address, (pre, _))| {
+                let account = pre.as_ref().map_or_else(
+                    || PrestateAccount { balance: Some(U256::zero()), ..Default::default() },
+                    PrestateAccount::from_state,
+                );
+                (address, account)
+            })
+            .collect()
+    }
 
-impl RawOrDecodedCall {
-    pub fn to_raw(&self) -> Vec<u8> {
-        match self {
-            RawOrDecodedCall::Raw(raw) => raw.clone(),
-            RawOrDecodedCall::Decoded(_, _, _) => {
-                vec![]
+    /// Returns the state of all accounts changed by the calls in this arena before and after the
+    /// transaction, in the format of geth's `prestateTracer` with `diffMode` enabled.
+    ///
+    /// The pre state contains the full state of the changed accounts that existed before, the post
+    /// state only the fields that changed of the accounts that exist after the transaction.
+    pub fn geth_prestate_diff(&self) -> PrestateDiff {
+        let mut prestate = PrestateDiff::default();
+        for (address, (pre, post)) in self.account_states() {
+            if pre.as_ref() == post {
+                continue
+            }
+
+            if let Some(post) = post {
+                let pre = pre.clone().unwrap_or_default();
+                let account = PrestateAccount {
+                    balance: (pre.balance != post.balance).then_some(post.balance),
+                    nonce: (pre.nonce != post.nonce).then_some(post.nonce),
+                    code: (pre.code != post.code).then(|| post.code.clone()),
+                    storage: post
+                        .storage
+                        .iter()
+                        .filter(|(slot, value)| pre.storage.get(*slot) != Some(*value))
+                        .map(|(slot, value)| (H256::from_uint(slot), H256::from_uint(value)))
+                        .collect(),
+                };
+                prestate.post.insert(address, account);
+            }
+            if let Some(pre) = &pre {
+                prestate.pre.insert(address, PrestateAccount::from_state(pre));
             }
         }
+        prestate
     }
-}
 
-impl Default for RawOrDecodedCall {
-    fn default() -> Self {
-        RawOrDecodedCall::Raw(Vec::new())
+    /// Returns the parity `vmTrace` of the calls in this arena, which requires steps to be
+    /// recorded.
+    ///
+    /// The executed operations contain the stack items pushed by each step, the memory it wrote
+    /// and the storage slot it stored, and the sub-calls are nested in the steps that made them.
+    pub fn vm_trace(&self) -> VMTrace {
+        self.vm_trace_of(0)
     }
-}
 
-/// Raw or decoded return data.
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
-pub enum RawOrDecodedReturnData {
-    /// Raw return data
-    Raw(Vec<u8>),
-    /// Decoded return data
-    Decoded(String),
-}
+    /// Returns the parity `vmTrace` of the node at `idx` and its sub-calls
+    fn vm_trace_of(&self, idx: usize) -> VMTrace {
+        let node = &self.arena[idx];
+        let code = if node.kind().is_create() {
+            node.trace.data.to_raw().into()
+        } else {
+            node.trace
+                .pre_state
+                .get(&node.trace.address)
+                .and_then(|state| state.as_ref())
+                .map(|state| state.code.clone())
+                .unwrap_or_default()
+        };
 
-impl RawOrDecodedReturnData {
-    pub fn to_raw(&self) -> Vec<u8> {
-        match self {
-            RawOrDecodedReturnData::Raw(raw) => raw.clone(),
-            RawOrDecodedReturnData::Decoded(val) => val.as_bytes().to_vec(),
-        }
+        let steps = &node.trace.steps;
+        let mut children = node.children.iter().map(|child| &self.arena[*child]).peekable();
+        let ops = steps
+            .iter()
+            .enumerate()
+            .map(|(step_idx, step)| {
+                let cost = match step.gas_cost {
+                    0 => step.estimated_gas_cost().unwrap_or_default(),
+                    gas_cost => gas_cost,
+                };
+                let ex = step.error.is_none().then(|| {
+                    let next = steps.get(step_idx + 1);
+                    VMExecutedOperation {
+                        used: next.map_or(step.gas.saturating_sub(cost), |next| next.gas),
+                        push: next.map(|next| pushed_items(step, next)).unwrap_or_default(),
+                        mem: next
+                            .and_then(|next| memory_diff(step.memory.data(), next.memory.data())),
+                        store: (step.op == Instruction::OpCode(opcode::SSTORE))
+                            .then_some(step.state_diff)
+                            .flatten()
+                            .map(|(key, val)| StorageDiff { key, val }),
+                    }
+                });
+                let sub = children
+                    .next_if(|child| child.trace.parent_step == Some(step_idx))
+                    .map(|child| self.vm_trace_of(child.idx));
+                VMOperation { pc: step.pc, cost, ex, sub }
+            })
+            .collect();
+
+        VMTrace { code, ops }
     }
-}
 
-impl Default for RawOrDecodedReturnData {
-    fn default() -> Self {
-        RawOrDecodedReturnData::Raw(Vec::new())
+    /// Returns whether the node at `ancestor` is an ancestor of the node at `idx`
+    fn is_ancestor(&self, ancestor: usize, mut idx: usize) -> bool {
+        while let Some(parent) = self.arena[idx].parent {
+            if parent == ancestor {
+                return true
+            }
+            idx = parent;
+        }
+        false
     }
-}
 
-impl fmt::Display for RawOrDecodedReturnData {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match &self {
-            RawOrDecodedReturnData::Raw(bytes) => {
-                if bytes.is_empty() {
-                    write!(f, "()")
-                } else {
-                    write!(f, "0x{}", hex::encode(bytes))
+    /// Returns the token transfers of the arena in execution order, found in the decoded
+    /// `Transfer` logs of ERC-20 and ERC-721 tokens, and the `TransferSingle` and `TransferBatch`
+    /// logs of ERC-1155 tokens.
+    ///
+    /// The logs must have been decoded with a [CallTraceDecoder] first, raw logs are skipped, as
+    /// are the logs of deserialized arenas, which don't keep the decoded tokens.
+    pub fn token_transfers(
+        &self,
+        labels: &std::collections::HashMap<Address, String>,
+    ) -> Vec<TokenTransfer> {
+        let mut transfers = Vec::new();
+        self.add_token_transfers(0, labels, &mut transfers);
+        transfers
+    }
+
+    /// Adds the token transfers of the node at `idx` and its sub-calls to `transfers`
+    fn add_token_transfers(
+        &self,
+        idx: usize,
+        labels: &std::collections::HashMap<Address, String>,
+        transfers: &mut Vec<TokenTransfer>,
+    ) {
+        let node = &self.arena[idx];
+        for order in &node.ordering {
+            let params = match order {
+                LogCallOrder::Log(log) => {
+                    match (&node.logs[*log], node.decoded_log_params.get(*log)) {
+                        (RawOrDecodedLog::Decoded(name, _), Some(Some(tokens))) => {
+                            (name.as_str(), tokens.as_slice())
+                        }
+                        _ => continue,
+                    }
+                }
+                LogCallOrder::Call(child) => {
+                    self.add_token_transfers(node.children[*child], labels, transfers);
+                    continue
                 }
+            };
+
+            // Logs of `DELEGATECALL`s are emitted by the caller
+            let mut emitter = node;
+            while let (true, Some(parent)) = (emitter.kind().is_delegate(), emitter.parent) {
+                emitter = &self.arena[parent];
+            }
+            let token = utils::label(&Token::Address(emitter.trace.address), labels);
+            let transfer =
+                |from: &Token, to: &Token, id: Option<&Token>, amount: &Token| TokenTransfer {
+                    token: token.clone(),
+                    from: utils::label(from, labels),
+                    to: utils::label(to, labels),
+                    id: id.map(|id| utils::label(id, labels)),
+                    amount: utils::label(amount, labels),
+                };
+
+            match params {
+                ("Transfer", [from, to, amount]) => {
+                    transfers.push(transfer(from, to, None, amount));
+                }
+                ("TransferSingle", [_, from, to, id, amount]) => {
+                    transfers.push(transfer(from, to, Some(id), amount));
+                }
+                ("TransferBatch", [_, from, to, Token::Array(ids), Token::Array(amounts)]) => {
+                    for (id, amount) in ids.iter().zip(amounts) {
+                        transfers.push(transfer(from, to, Some(id), amount));
+                    }
+                }
+                _ => {}
             }
-            RawOrDecodedReturnData::Decoded(decoded) => write!(f, "{}", decoded.clone()),
         }
     }
-}
 
-#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
-pub struct CallTraceStep {
-    // Fields filled in `step`
-    /// Call depth
-    pub depth: u64,
-    /// Program counter before step execution
-    pub pc: usize,
-    /// Opcode to be executed
-    pub op: Instruction,
-    /// Current contract address
-    pub contract: Address,
-    /// Stack before step execution
-    pub stack: Stack,
-    /// Memory before step execution
-    pub memory: Memory,
-    /// Remaining gas before step execution
-    pub gas: u64,
-    /// Gas refund counter before step execution
-    pub gas_refund_counter: u64,
+    /// Returns the calls of the arena as events of the Chrome Trace Event format, which can be
+    /// viewed as a flamegraph in e.g. `chrome://tracing` or Perfetto.
+    ///
+    /// Gas is used instead of time, i.e. the timestamp of a call is the gas used in the
+    /// transaction before it and its duration is its gas cost.
+    pub fn chrome_trace(&self) -> ChromeTrace {
+        let mut trace_events = Vec::with_capacity(self.arena.len());
+        self.add_chrome_trace_events(0, 0, &mut trace_events);
+        ChromeTrace { trace_events }
+    }
 
-    // Fields filled in `step_end`
-    /// Gas cost of step execution
-    pub gas_cost: u64,
-    /// Change of the contract state after step execution (effect of the SLOAD/SSTORE instructions)
-    pub state_diff: Option<(U256, U256)>,
-    /// Error (if any) after after step execution
-    pub error: Option<String>,
-}
+    /// Adds the events of the node at `idx` and its sub-calls, starting at the gas `ts`
+    fn add_chrome_trace_events(&self, idx: usize, ts: u64, events: &mut Vec<ChromeTraceEvent>) {
+        let node = &self.arena[idx];
+        let mut args = BTreeMap::from([
+            ("address", to_checksum(&node.trace.address, None)),
+            ("caller", to_checksum(&node.trace.caller, None)),
+            ("kind", format!("{:?}", node.kind())),
+        ]);
+        if let Some(label) = &node.trace.label {
+            args.insert("label", label.clone());
+        }
+        events.push(ChromeTraceEvent {
+            name: frame_name(&node.trace),
+            ph: "X",
+            ts,
+            dur: node.trace.gas_cost,
+            pid: 1,
+            tid: 1,
+            args,
+        });
 
-impl From<&CallTraceStep> for StructLog {
-    fn from(step: &CallTraceStep) -> Self {
-        StructLog {
-            depth: step.depth,
-            error: step.error.clone(),
-            gas: step.gas,
-            gas_cost: step.gas_cost,
-            memory: Some(convert_memory(step.memory.data())),
-            op: step.op.to_string(),
-            pc: step.pc as u64,
-            refund_counter: if step.gas_refund_counter > 0 {
-                Some(step.gas_refund_counter)
-            } else {
-                None
-            },
-            stack: Some(step.stack.data().clone()),
-            // Filled in `CallTraceArena::geth_trace` as a result of compounding all slot changes
-            storage: None,
+        let mut offset = ts;
+        for child in &node.children {
+            let child = &self.arena[*child];
+            // Sub-calls start after the gas used by the call before them, if steps are recorded
+            let start = child
+                .trace
+                .parent_step
+                .and_then(|step| {
+                    let used = node
+                        .trace
+                        .steps
+                        .first()?
+                        .gas
+                        .saturating_sub(node.trace.steps.get(step)?.gas);
+                    Some(ts + used)
+                })
+                .map_or(offset, |start| start.max(offset));
+            self.add_chrome_trace_events(child.idx, start, events);
+            offset = start + child.trace.gas_cost;
         }
     }
-}
 
-/// A trace of a call.
-#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
-pub struct CallTrace {
-    /// The depth of the call
-    pub depth: usize,
-    /// Whether the call was successful
-    pub success: bool,
-    /// The name of the contract, if any.
+    /// Labels the `DELEGATECALL`s of EIP-1967 proxies to their implementation as
+    /// `<proxy> -> <implementation>`.
     ///
-    /// The format is `"<artifact>:<contract>"` for easy lookup in local contracts.
+    /// A call is considered a proxy if it loaded the address it delegates to from the EIP-1967
+    /// implementation slot, so this requires steps to be recorded. Calls are left as is if the slot
+    /// is empty or holds another address.
+    pub fn label_eip1967_proxies(&mut self) {
+        // `keccak256("eip1967.proxy.implementation") - 1`
+        let slot = U256::from(keccak256("eip1967.proxy.implementation")) - 1;
+
+        let labels = self
+            .arena
+            .iter()
+            .filter(|node| node.kind() == CallKind::DelegateCall)
+            .filter_map(|node| {
+                let proxy = &self.arena[node.parent?];
+                let steps = node
+                    .trace
+                    .parent_step
+                    .and_then(|step| proxy.trace.steps.get(..step))
+                    .unwrap_or(&proxy.trace.steps);
+                let implementation = steps
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, step)| {
+                        step.op == Instruction::OpCode(opcode::SLOAD) &&
+                            step.stack_top(0) == Some(slot)
+                    })
+                    .filter_map(|(idx, step)| {
+                        // The loaded value is on top of the stack after the `SLOAD`
+                        step.state_diff
+                            .map(|(_, value)| value)
+                            .or_else(|| proxy.trace.steps.get(idx + 1)?.stack_top(0))
+                    })
+                    .last()?;
+                if implementation.is_zero() ||
+                    Address::from(H256::from_uint(&implementation)) != node.trace.address
+                {
+                    return None
+                }
+
+                let label = |trace: &CallTrace| {
+                    trace.label.clone().unwrap_or_else(|| to_checksum(&trace.address, None))
+                };
+                Some((node.idx, format!("{} -> {}", label(&proxy.trace), label(&node.trace))))
+            })
+            .collect::<Vec<_>>();
+
+        for (idx, label) in labels {
+            self.arena[idx].trace.label = Some(label);
+        }
+    }
+
+    /// Returns the calls of the arena as folded stacks keyed by gas, which can be turned into a
+    /// flamegraph with e.g. `inferno` or `flamegraph.pl`.
     ///
-    /// This member is not used by the core call tracing functionality (decoding/displaying). The
-    /// intended use case is for other components that may want to process traces by specific
-    /// contracts (e.g. gas reports).
-    pub contract: Option<String>,
-    /// The label for the destination address, if any
-    pub label: Option<String>,
-    /// caller of this call
-    pub caller: Address,
-    /// The destination address of the call or the address from the created contract
-    pub address: Address,
-    /// The kind of call this is
-    pub kind: CallKind,
-    /// The value transferred in the call
-    pub value: U256,
-    /// The calldata for the call, or the init code for contract creations
-    pub data: RawOrDecodedCall,
-    /// The return data of the call if this was not a contract creation, otherwise it is the
-    /// runtime bytecode of the created contract
-    pub output: RawOrDecodedReturnData,
-    /// The gas cost of the call
-    pub gas_cost: u64,
-    /// The status of the trace's call
-    pub status: Return,
-    /// call context of the runtime
-    pub call_context: Option<CallContext>,
-    /// Opcode-level execution steps
-    pub steps: Vec<CallTraceStep>,
-}
+    /// Every call is a line of the names of the calls from the top-level call to it separated by
+    /// `;`, followed by the gas used by the call itself, see [CallTraceNode::self_gas].
+    pub fn folded_stacks(&self) -> String {
+        let mut stacks = String::new();
+        for node in &self.arena {
+            let mut path = vec![frame_name(&node.trace)];
+            let mut parent = node.parent;
+            while let Some(idx) = parent {
+                path.push(frame_name(&self.arena[idx].trace));
+                parent = self.arena[idx].parent;
+            }
+            path.reverse();
+            writeln!(stacks, "{} {}", path.join(";"), node.self_gas(&self.arena)).unwrap();
+        }
+        stacks
+    }
 
-// === impl CallTrace ===
+    /// Returns the trace of this arena in the format of geth's `callTracer`
+    pub fn geth_call_trace(&self) -> CallFrame {
+        fn inner(arena: &[CallTraceNode], idx: usize) -> CallFrame {
+            let node = &arena[idx];
+            let mut frame = node.geth_call_frame();
+            frame.calls = node.children.iter().map(|child| inner(arena, *child)).collect();
+            frame
+        }
 
-impl CallTrace {
-    /// Whether this is a contract creation or not
-    pub fn created(&self) -> bool {
-        matches!(self.kind, CallKind::Create | CallKind::Create2)
+        inner(&self.arena, 0)
     }
 }
 
-impl Default for CallTrace {
-    fn default() -> Self {
+/// A call frame of a geth `callTracer` trace
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallFrame {
+    /// The kind of the call
+    #[serde(rename = "type")]
+    pub kind: CallKind,
+    pub from: Address,
+    /// The destination of the call, or the address of the created contract
+    pub to: Address,
+    /// The value transferred, not set for calls that can't transfer value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<U256>,
+    pub gas: U256,
+    pub gas_used: U256,
+    pub input: Bytes,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<Bytes>,
+    /// The error of the call, if it failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The sub-calls of the call
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub calls: Vec<CallFrame>,
+}
+
+/// The state of an account in a geth `prestateTracer` trace, fields that are not set are omitted
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrestateAccount {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balance: Option<U256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<Bytes>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub storage: BTreeMap<H256, H256>,
+}
+
+impl PrestateAccount {
+    /// Returns the full state of the account, omitting a zero nonce and empty code like geth
+    fn from_state(state: &AccountState) -> Self {
         Self {
-            depth: Default::default(),
-            success: Default::default(),
-            contract: Default::default(),
-            label: Default::default(),
-            caller: Default::default(),
-            address: Default::default(),
-            kind: Default::default(),
-            value: Default::default(),
-            data: Default::default(),
-            output: Default::default(),
-            gas_cost: Default::default(),
-            status: Return::Continue,
-            call_context: Default::default(),
-            steps: Default::default(),
+            balance: Some(state.balance),
+            nonce: (state.nonce != 0).then_some(state.nonce),
+            code: (!state.code.is_empty()).then(|| state.code.clone()),
+            storage: state
+                .storage
+                .iter()
+                .map(|(slot, value)| (H256::from_uint(slot), H256::from_uint(value)))
+                .collect(),
         }
     }
 }
 
-impl fmt::Display for CallTrace {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let address = to_checksum(&self.address, None);
-        if self.created() {
-            write!(
-                f,
-                "[{}] {}{} {}@{}",
-                self.gas_cost,
-                Paint::yellow(CALL),
-                Paint::yellow("new"),
-                self.label.as_ref().unwrap_or(&"<Unknown>".to_string()),
-                address
-            )?;
-        } else {
-            let (func, inputs) = match &self.data {
-                RawOrDecodedCall::Raw(bytes) => {
-                    // We assume that the fallback function (`data.len() < 4`) counts as decoded
-                    // calldata
-                    assert!(bytes.len() >= 4);
-                    (hex::encode(&bytes[0..4]), hex::encode(&bytes[4..]))
-                }
-                RawOrDecodedCall::Decoded(func, _, inputs) => (func.clone(), inputs.join(", ")),
-            };
+/// The result of a geth `prestateTracer` trace with `diffMode` enabled
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrestateDiff {
+    /// The state of the changed accounts before the transaction
+    pub pre: BTreeMap<Address, PrestateAccount>,
+    /// The changed fields of the accounts after the transaction
+    pub post: BTreeMap<Address, PrestateAccount>,
+}
 
-            let action = match self.kind {
-                // do not show anything for CALLs
-                CallKind::Call => "",
-                CallKind::StaticCall => "[staticcall]",
-                CallKind::CallCode => "[callcode]",
-                CallKind::DelegateCall => "[delegatecall]",
-                _ => unreachable!(),
-            };
+/// Returns the parity diff between two values, where `None` means that the value does not exist
+fn diff<T: PartialEq>(pre: Option<T>, post: Option<T>) -> Diff<T> {
+    match (pre, post) {
+        (Some(from), Some(to)) if from != to => Diff::Changed(ChangedType { from, to }),
+        (None, Some(to)) => Diff::Born(to),
+        (Some(from), None) => Diff::Died(from),
+        _ => Diff::Same,
+    }
+}
 
-            let color = trace_color(self);
-            write!(
-                f,
-                "[{}] {}::{}{}({}) {}",
-                self.gas_cost,
-                color.paint(self.label.as_ref().unwrap_or(&address)),
-                color.paint(func),
-                if !self.value.is_zero() {
-                    format!("{{value: {}}}", self.value)
-                } else {
-                    "".to_string()
-                },
-                inputs,
-                Paint::yellow(action),
-            )?;
-        }
+/// A trace in the Chrome Trace Event format, see [CallTraceArena::chrome_trace]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChromeTrace {
+    pub trace_events: Vec<ChromeTraceEvent>,
+}
 
-        Ok(())
+/// A call as a complete event of the Chrome Trace Event format
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChromeTraceEvent {
+    /// The name of the call, e.g. `USDC::transfer`
+    pub name: String,
+    /// The phase of the event, `"X"` for complete events
+    pub ph: &'static str,
+    /// The gas used in the transaction before the call
+    pub ts: u64,
+    /// The gas cost of the call
+    pub dur: u64,
+    pub pid: u64,
+    pub tid: u64,
+    /// The address, label, caller and kind of the call
+    pub args: BTreeMap<&'static str, String>,
+}
+
+/// The aggregated gas costs of the calls to a function, see [CallTraceArena::gas_snapshot]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FunctionGas {
+    /// The number of calls
+    pub calls: u64,
+    /// The total gas cost of all calls
+    pub total: u64,
+    /// The lowest gas cost of a call
+    pub min: u64,
+    /// The highest gas cost of a call
+    pub max: u64,
+}
+
+impl FunctionGas {
+    /// Returns the average gas cost of a call, rounded down
+    pub fn mean(&self) -> u64 {
+        self.total.checked_div(self.calls).unwrap_or_default()
+    }
+
+    fn add(&mut self, gas_cost: u64) {
+        self.min = if self.calls == 0 { gas_cost } else { self.min.min(gas_cost) };
+        self.max = self.max.max(gas_cost);
+        self.total += gas_cost;
+        self.calls += 1;
     }
 }
 
-/// Specifies the kind of trace.
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
-pub enum TraceKind {
-    Deployment,
-    Setup,
-    Execution,
+/// The gas used according to the steps of a transaction compared to the gas it actually used, see
+/// [CallTraceArena::reconcile_gas]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GasReconciliation {
+    /// The total gas cost of the steps
+    pub execution_gas: u64,
+    /// The refunded gas
+    pub refund: u64,
+    /// The gas used according to the steps, including the intrinsic gas and the refund
+    pub gas_used: u64,
+    /// The gas actually used
+    pub expected: u64,
 }
 
-/// Chooses the color of the trace depending on the destination address and status of the call.
-fn trace_color(trace: &CallTrace) -> Color {
-    if trace.address == CHEATCODE_ADDRESS {
-        Color::Blue
-    } else if trace.success {
-        Color::Green
-    } else {
-        Color::Red
+impl GasReconciliation {
+    /// Returns by how much the gas used according to the steps exceeds the gas actually used, which
+    /// is negative if the steps account for less gas
+    pub fn discrepancy(&self) -> i128 {
+        self.gas_used as i128 - self.expected as i128
+    }
+
+    /// Whether the gas used according to the steps matches the gas actually used
+    pub fn is_reconciled(&self) -> bool {
+        self.discrepancy() == 0
     }
 }
 
-/// Given a list of traces and artifacts, it returns a map connecting address to abi
-pub fn load_contracts(
-    traces: Vec<(TraceKind, CallTraceArena)>,
-    known_contracts: Option<&ContractsByArtifact>,
-) -> ContractsByAddress {
-    if let Some(contracts) = known_contracts {
-        let mut local_identifier = LocalTraceIdentifier::new(contracts);
-        let mut decoder = CallTraceDecoderBuilder::new().build();
-        for (_, trace) in &traces {
-            decoder.identify(trace, &mut local_identifier);
-        }
+/// A transfer of tokens, see [CallTraceArena::token_transfers]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenTransfer {
+    /// The labeled address of the token
+    pub token: String,
+    /// The labeled sender of the tokens
+    pub from: String,
+    /// The labeled recipient of the tokens
+    pub to: String,
+    /// The id of the transferred ERC-1155 tokens
+    pub id: Option<String>,
+    /// The amount of ERC-20 or ERC-1155 tokens, or the id of the ERC-721 token
+    pub amount: String,
+}
 
-        decoder
-            .contracts
-            .iter()
-            .filter_map(|(addr, name)| {
-                if let Ok(Some((_, (abi, _)))) = contracts.find_by_name_or_identifier(name) {
-                    return Some((*addr, (name.clone(), abi.clone())))
-                }
-                None
-            })
-            .collect()
-    } else {
-        BTreeMap::new()
+/// The differences between the calls of two arenas, see [CallTraceArena::diff]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceDiff {
+    /// The calls of both arenas in depth-first order, paired up where they match
+    pub calls: Vec<CallDiff>,
+}
+
+impl TraceDiff {
+    /// Whether the calls of both arenas match and have the same gas costs and return data
+    pub fn is_empty(&self) -> bool {
+        self.calls.iter().all(|call| matches!(call, CallDiff::Unchanged { .. }))
     }
 }
 
-/// creates the memory data in 32byte chunks
-/// see <https://github.com/ethereum/go-ethereum/blob/366d2169fbc0e0f803b68c042b77b6b480836dbc/eth/tracers/logger/logger.go#L450-L452>
-fn convert_memory(data: &[u8]) -> Vec<String> {
-    let mut memory = Vec::with_capacity((data.len() + 31) / 32);
-    for idx in (0..data.len()).step_by(32) {
-        let len = std::cmp::min(idx + 32, data.len());
-        memory.push(hex::encode(&data[idx..len]));
+/// The difference of a call between two arenas, referencing the calls by their index in the arenas
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallDiff {
+    /// The call is in both arenas with the same gas cost and return data
+    Unchanged { old: usize, new: usize },
+    /// The call is in both arenas, but its gas cost or its return data changed
+    Changed {
+        old: usize,
+        new: usize,
+        /// The old and the new gas cost, if it changed
+        gas_cost: Option<(u64, u64)>,
+        /// Whether the return data changed
+        output: bool,
+    },
+    /// The call is only in the old arena
+    Removed { old: usize },
+    /// The call is only in the new arena
+    Added { new: usize },
+}
+
+/// Compares the call at `old_idx` in `old` and the call at `new_idx` in `new`, and their sub-calls
+fn diff_calls(
+    old: &[CallTraceNode],
+    new: &[CallTraceNode],
+    old_idx: usize,
+    new_idx: usize,
+    calls: &mut Vec<CallDiff>,
+) {
+    let (old_node, new_node) = (&old[old_idx], &new[new_idx]);
+    // compare the raw data where it's known, so that the decoding of the calls doesn't matter
+    let selector = |trace: &CallTrace| trace.raw_selector().or_else(|| trace.data.selector());
+    let is_same_call = old_node.kind() == new_node.kind() &&
+        (old_node.kind().is_create() || selector(&old_node.trace) == selector(&new_node.trace));
+    if !is_same_call {
+        push_call_diffs(old, old_idx, |old| CallDiff::Removed { old }, calls);
+        push_call_diffs(new, new_idx, |new| CallDiff::Added { new }, calls);
+        return
+    }
+
+    let gas_cost = (old_node.trace.gas_cost != new_node.trace.gas_cost)
+        .then_some((old_node.trace.gas_cost, new_node.trace.gas_cost));
+    let output = match (old_node.trace.raw_output(), new_node.trace.raw_output()) {
+        (Some(old_output), Some(new_output)) => old_output != new_output,
+        _ => old_node.trace.output != new_node.trace.output,
+    };
+    calls.push(if gas_cost.is_none() && !output {
+        CallDiff::Unchanged { old: old_idx, new: new_idx }
+    } else {
+        CallDiff::Changed { old: old_idx, new: new_idx, gas_cost, output }
+    });
+
+    for i in 0..old_node.children.len().max(new_node.children.len()) {
+        match (old_node.children.get(i), new_node.children.get(i)) {
+            (Some(old_child), Some(new_child)) => {
+                diff_calls(old, new, *old_child, *new_child, calls)
+            }
+            (Some(old_child), None) => {
+                push_call_diffs(old, *old_child, |old| CallDiff::Removed { old }, calls)
+            }
+            (None, Some(new_child)) => {
+                push_call_diffs(new, *new_child, |new| CallDiff::Added { new }, calls)
+            }
+            (None, None) => unreachable!(),
+        }
     }
-    memory
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Pushes the `diff` of the call at `idx` and of all of its sub-calls in depth-first order
+fn push_call_diffs(
+    arena: &[CallTraceNode],
+    idx: usize,
+    diff: fn(usize) -> CallDiff,
+    calls: &mut Vec<CallDiff>,
+) {
+    calls.push(diff(idx));
+    for child in &arena[idx].children {
+        push_call_diffs(arena, *child, diff, calls);
+    }
+}
+
+/// Options for [CallTraceArena::geth_trace_with].
+///
+/// By default, everything is included in the trace.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GethTraceOptions {
+    /// Whether to omit the memory of the steps
+    pub disable_memory: bool,
+    /// Whether to omit the stack of the steps
+    pub disable_stack: bool,
+    /// Whether to omit the storage of the steps
+    pub disable_storage: bool,
+    /// The maximum depth of the calls whose steps are included, where the top-level call has a
+    /// depth of 0. The steps of deeper calls are omitted, if set.
+    pub max_depth: Option<usize>,
+}
+
+impl From<GethDebugTracingOptions> for GethTraceOptions {
+    fn from(opts: GethDebugTracingOptions) -> Self {
+        Self {
+            disable_memory: !opts.enable_memory.unwrap_or_default(),
+            disable_stack: opts.disable_stack.unwrap_or_default(),
+            disable_storage: opts.disable_storage.unwrap_or_default(),
+            max_depth: None,
+        }
+    }
+}
+
+const PIPE: &str = "  │ ";
+const EDGE: &str = "  └─ ";
+const BRANCH: &str = "  ├─ ";
+const CALL: &str = "→ ";
+const RETURN: &str = "← ";
+
+/// The default maximum number of bytes of raw calldata and return data shown by
+/// [CallTraceArena::display]
+pub const DEFAULT_MAX_RAW_BYTES: usize = 128;
+
+/// Displays the calls as a tree, where the logs and sub-calls of every call are shown in the order
+/// they happened, see [LogCallOrder]
+///
+/// Nothing is folded or truncated, see [CallTraceArena::display] for that.
+impl fmt::Display for CallTraceArena {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let display = CallTraceArenaDisplay {
+            arena: self,
+            min_repeats: 0,
+            max_raw_bytes: None,
+            show_selectors: false,
+            color: true,
+        };
+        fmt::Display::fmt(&display, f)
+    }
+}
+
+/// Displays the calls of an arena as a tree with configurable formatting, see
+/// [CallTraceArena::display]
+#[derive(Debug, Clone, Copy)]
+pub struct CallTraceArenaDisplay<'a> {
+    arena: &'a CallTraceArena,
+    min_repeats: usize,
+    max_raw_bytes: Option<usize>,
+    show_selectors: bool,
+    color: bool,
+}
+
+impl CallTraceArenaDisplay<'_> {
+    /// Folds runs of at least `min_repeats` consecutive sub-calls to the same function of the same
+    /// address into a single line, see [CallTraceArena::folded]
+    pub fn fold_repeats(mut self, min_repeats: usize) -> Self {
+        self.min_repeats = min_repeats;
+        self
+    }
+
+    /// Truncates raw calldata and return data to `max_raw_bytes` bytes, followed by the number of
+    /// omitted bytes, or shows it in full if `None`. Decoded calldata and return data is not
+    /// truncated.
+    pub fn max_raw_bytes(mut self, max_raw_bytes: Option<usize>) -> Self {
+        self.max_raw_bytes = max_raw_bytes;
+        self
+    }
+
+    /// Appends the selector of the original calldata to decoded calls, e.g. `[0x12345678]`, which
+    /// shows ABI mismatches where the selector of the decoded function differs from the called
+    /// one.
+    ///
+    /// The selector is only known for calls decoded in this session, since the raw calldata is not
+    /// serialized.
+    pub fn show_selectors(mut self, show_selectors: bool) -> Self {
+        self.show_selectors = show_selectors;
+        self
+    }
+
+    /// Colors the output if `color` is set, which is the default, or displays plain text otherwise,
+    /// e.g. when the output is not a terminal.
+    ///
+    /// Successful calls are shown in green, reverted calls in red, and raw data is dimmed.
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Displays the node at `idx` and its logs and sub-calls
+    fn fmt_node(
+        &self,
+        writer: &mut (impl Write + ?Sized),
+        idx: usize,
+        left: &str,
+        child: &str,
+    ) -> fmt::Result {
+        let arena = self.arena;
+        let node = &arena.arena[idx];
+
+        // Display trace header
+        write!(writer, "{left}")?;
+        node.trace.fmt_truncated(writer, self.max_raw_bytes, self.show_selectors, self.color)?;
+        writeln!(writer)?;
+
+        // Display annotations and logs and subcalls
+        let left_prefix = format!("{child}{BRANCH}");
+        let right_prefix = format!("{child}{PIPE}");
+        for line in node.annotations.iter().flat_map(|annotation| annotation.lines()) {
+            writeln!(writer, "{}{}", right_prefix, painted(Paint::new(line).dimmed(), self.color))?;
+        }
+        let mut ordering = node.ordering.iter().peekable();
+        while let Some(order) = ordering.next() {
+            match order {
+                LogCallOrder::Log(index) => {
+                    let mut log = String::new();
+                    node.logs[*index].fmt_colored(&mut log, self.color)?;
+
+                    // Prepend our tree structure symbols to each line of the displayed log
+                    log.lines().enumerate().try_for_each(|(i, line)| {
+                        writeln!(
+                            writer,
+                            "{}{}",
+                            if i == 0 { &left_prefix } else { &right_prefix },
+                            line
+                        )
+                    })?;
+                }
+                LogCallOrder::Call(index) => {
+                    let first = &arena.arena[node.children[*index]];
+                    let mut repeats = vec![first];
+                    if self.min_repeats > 1 {
+                        while let Some(LogCallOrder::Call(next)) = ordering.peek() {
+                            let next = &arena.arena[node.children[*next]];
+                            if !is_repeated_call(first, next) {
+                                break
+                            }
+                            repeats.push(next);
+                            ordering.next();
+                        }
+                    }
+
+                    if repeats.len() >= self.min_repeats.max(2) {
+                        writeln!(
+                            writer,
+                            "{}{}",
+                            left_prefix,
+                            fmt_repeated_calls(&repeats, self.color)
+                        )?;
+                    } else {
+                        for repeat in repeats {
+                            self.fmt_node(writer, repeat.idx, &left_prefix, &right_prefix)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        // Display trace return data
+        let color = trace_color(&node.trace);
+        write!(writer, "{}{}", child, EDGE)?;
+        write!(writer, "{}", painted(color.paint(RETURN), self.color))?;
+        if node.trace.created() {
+            if let RawOrDecodedReturnData::Raw(bytes) = &node.trace.output {
+                writeln!(writer, "{} bytes of code", bytes.len())?;
+            } else {
+                unreachable!("We should never have decoded calldata for contract creations");
+            }
+        } else if !node.trace.success && node.trace.output == RawOrDecodedReturnData::Raw(vec![]) {
+            // Show why the call failed if it has no revert data
+            writeln!(writer, "{}", node.status_string())?;
+        } else {
+            node.trace.output.fmt_truncated(writer, self.max_raw_bytes, self.color)?;
+            writeln!(writer)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for CallTraceArenaDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_node(f, 0, "  ", "  ")
+    }
+}
+
+/// Hex encodes `bytes`, truncated to `max_bytes` bytes followed by the number of omitted bytes, if
+/// set
+fn truncated_hex(bytes: &[u8], max_bytes: Option<usize>) -> String {
+    match max_bytes {
+        Some(max_bytes) if bytes.len() > max_bytes => format!(
+            "{}\u{2026}(+{} bytes)",
+            hex::encode(&bytes[..max_bytes]),
+            bytes.len() - max_bytes
+        ),
+        _ => hex::encode(bytes),
+    }
+}
+
+/// Whether `call` repeats the call `first`, i.e. calls the same function of the same address with
+/// the same outcome, so that folding them does not hide a call that failed or returned something
+/// else
+fn is_repeated_call(first: &CallTraceNode, call: &CallTraceNode) -> bool {
+    let (first, call) = (&first.trace, &call.trace);
+    let selector = |trace: &CallTrace| trace.raw_selector().or_else(|| trace.data.selector());
+    let same_output = match (first.raw_output(), call.raw_output()) {
+        (Some(first_output), Some(output)) => first_output == output,
+        _ => first.output == call.output,
+    };
+    !first.kind.is_create() &&
+        first.kind == call.kind &&
+        first.address == call.address &&
+        selector(first) == selector(call) &&
+        first.success == call.success &&
+        first.status == call.status &&
+        same_output
+}
+
+/// Formats a run of repeated calls as `[<total gas>] <address>::<function>(..) x <repeats>`,
+/// colored if `color` is set
+fn fmt_repeated_calls(calls: &[&CallTraceNode], color: bool) -> String {
+    let first = &calls[0].trace;
+    let func = match &first.data {
+        RawOrDecodedCall::Decoded(func, _, _) => func.clone(),
+        RawOrDecodedCall::Raw(bytes) => hex::encode(bytes.get(..SELECTOR_LEN).unwrap_or(bytes)),
+    };
+    let trace_color = trace_color(first);
+    format!(
+        "[{}] {}::{}(..) {}",
+        calls.iter().map(|call| call.trace.gas_cost).sum::<u64>(),
+        painted(
+            trace_color
+                .paint(first.label.clone().unwrap_or_else(|| to_checksum(&first.address, None))),
+            color
+        ),
+        painted(trace_color.paint(func), color),
+        painted(Paint::new(format!("x {}", calls.len())).dimmed(), color)
+    )
+}
+
+/// A raw or decoded log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RawOrDecodedLog {
+    /// A raw log
+    #[serde(with = "raw_log")]
+    Raw(RawLog),
+    /// A decoded log.
+    ///
+    /// The first member of the tuple is the event name, and the second is a vector of decoded
+    /// parameters.
+    Decoded(String, Vec<(String, String)>),
+}
+
+/// (De)serializes a [RawLog], which does not implement serde itself
+mod raw_log {
+    use super::*;
+    use serde::{Deserializer, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Log {
+        topics: Vec<H256>,
+        data: Bytes,
+    }
+
+    pub fn serialize<S: Serializer>(log: &RawLog, serializer: S) -> Result<S::Ok, S::Error> {
+        Log { topics: log.topics.clone(), data: log.data.clone().into() }.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<RawLog, D::Error> {
+        let log = Log::deserialize(deserializer)?;
+        Ok(RawLog { topics: log.topics, data: log.data.to_vec() })
+    }
+}
+
+impl fmt::Display for RawOrDecodedLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_colored(f, true)
+    }
+}
+
+impl RawOrDecodedLog {
+    /// Displays the log, colored if `color` is set
+    fn fmt_colored(&self, f: &mut (impl Write + ?Sized), color: bool) -> fmt::Result {
+        match self {
+            RawOrDecodedLog::Raw(log) => {
+                for (i, topic) in log.topics.iter().enumerate() {
+                    writeln!(
+                        f,
+                        "{:>13}: {}",
+                        if i == 0 { "emit topic 0".to_string() } else { format!("topic {i}") },
+                        painted(Paint::cyan(format!("0x{}", hex::encode(topic))), color)
+                    )?;
+                }
+
+                write!(
+                    f,
+                    "          data: {}",
+                    painted(Paint::cyan(format!("0x{}", hex::encode(&log.data))), color)
+                )
+            }
+            RawOrDecodedLog::Decoded(name, params) => {
+                let params = params
+                    .iter()
+                    .map(|(name, value)| format!("{name}: {value}"))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+
+                write!(f, "emit {}({})", painted(Paint::cyan(name.clone()), color), params)
+            }
+        }
+    }
+}
+
+/// Ordering enum for calls and logs
+///
+/// i.e. if Call 0 occurs before Log 0, it will be pushed into the `CallTraceNode`'s ordering before
+/// the log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogCallOrder {
+    Log(usize),
+    Call(usize),
+}
+
+// TODO: Maybe unify with output
+/// Raw or decoded calldata.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum RawOrDecodedCall {
+    /// Raw calldata
+    Raw(Vec<u8>),
+    /// Decoded calldata.
+    ///
+    /// The first element in the tuple is the function name, second is the function signature and
+    /// the third element is a vector of decoded parameters.
+    Decoded(String, String, Vec<String>),
+}
+
+impl RawOrDecodedCall {
+    pub fn to_raw(&self) -> Vec<u8> {
+        match self {
+            RawOrDecodedCall::Raw(raw) => raw.clone(),
+            RawOrDecodedCall::Decoded(_, _, _) => {
+                vec![]
+            }
+        }
+    }
+
+    /// Returns the function selector of the call, `None` if the calldata is too short or the
+    /// decoded signature is not a function, e.g. for the fallback function
+    pub fn selector(&self) -> Option<[u8; SELECTOR_LEN]> {
+        match self {
+            RawOrDecodedCall::Raw(raw) => raw.get(..SELECTOR_LEN)?.try_into().ok(),
+            RawOrDecodedCall::Decoded(_, signature, _) => {
+                get_func(signature).ok().map(|func| func.short_signature())
+            }
+        }
+    }
+
+    /// Returns the calldata of the call, re-encoding the parameters if the call is decoded.
+    ///
+    /// Returns `None` if the decoded parameters can't be parsed back into tokens, e.g. because
+    /// addresses were replaced by their labels.
+    ///
+    /// Note that decoded precompile calls are encoded with a selector as well, even though
+    /// precompiles take their input as is.
+    pub fn to_calldata(&self) -> Option<Bytes> {
+        match self {
+            RawOrDecodedCall::Raw(raw) => Some(raw.clone().into()),
+            RawOrDecodedCall::Decoded(_, signature, params) => {
+                let func = get_func(signature).ok()?;
+                if func.inputs.len() != params.len() {
+                    return None
+                }
+                encode_args(&func, &params[..]).ok().map(Into::into)
+            }
+        }
+    }
+}
+
+impl Default for RawOrDecodedCall {
+    fn default() -> Self {
+        RawOrDecodedCall::Raw(Vec::new())
+    }
+}
+
+/// Raw or decoded return data.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum RawOrDecodedReturnData {
+    /// Raw return data
+    Raw(Vec<u8>),
+    /// Decoded return data
+    Decoded(String),
+}
+
+impl RawOrDecodedReturnData {
+    pub fn to_raw(&self) -> Vec<u8> {
+        match self {
+            RawOrDecodedReturnData::Raw(raw) => raw.clone(),
+            RawOrDecodedReturnData::Decoded(val) => val.as_bytes().to_vec(),
+        }
+    }
+}
+
+impl Default for RawOrDecodedReturnData {
+    fn default() -> Self {
+        RawOrDecodedReturnData::Raw(Vec::new())
+    }
+}
+
+impl RawOrDecodedReturnData {
+    /// Displays the return data, truncating raw data to `max_raw_bytes` bytes if set, and dimming
+    /// it if `color` is set
+    fn fmt_truncated(
+        &self,
+        f: &mut (impl Write + ?Sized),
+        max_raw_bytes: Option<usize>,
+        color: bool,
+    ) -> fmt::Result {
+        match &self {
+            RawOrDecodedReturnData::Raw(bytes) => {
+                if bytes.is_empty() {
+                    write!(f, "()")
+                } else {
+                    let hex = truncated_hex(bytes, max_raw_bytes);
+                    write!(f, "{}", painted(Paint::new(format!("0x{hex}")).dimmed(), color))
+                }
+            }
+            RawOrDecodedReturnData::Decoded(decoded) => write!(f, "{}", decoded.clone()),
+        }
+    }
+}
+
+impl fmt::Display for RawOrDecodedReturnData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_truncated(f, None, true)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CallTraceStep {
+    // Fields filled in `step`
+    /// Call depth
+    pub depth: u64,
+    /// Program counter before step execution
+    pub pc: usize,
+    /// Opcode to be executed
+    pub op: Instruction,
+    /// Current contract address
+    pub contract: Address,
+    /// Stack before step execution
+    pub stack: Stack,
+    /// Memory before step execution
+    pub memory: Memory,
+    /// Remaining gas before step execution
+    pub gas: u64,
+    /// Gas refund counter of the whole transaction before step execution
+    pub gas_refund_counter: u64,
+
+    // Fields filled in `step_end`
+    /// Gas cost of step execution
+    pub gas_cost: u64,
+    /// Change of the contract state after step execution (effect of the SLOAD/SSTORE instructions)
+    pub state_diff: Option<(U256, U256)>,
+    /// Error (if any) after after step execution
+    pub error: Option<String>,
+
+    /// The location of the instruction in the sources, if annotated with
+    /// [CallTraceNode::annotate_source_locations]
+    #[serde(default)]
+    pub source_location: Option<SourceLoc>,
+    /// Whether the account or storage slot accessed by the step was warm or cold (EIP-2929),
+    /// `None` if the step does not access one.
+    ///
+    /// Accounts and slots are warm once they were accessed in the transaction, so warmth is reset
+    /// at transaction boundaries.
+    #[serde(default)]
+    pub access: Option<Warmth>,
+    /// Whether the step executes EVM Object Format (EOF) code, whose opcodes are named
+    /// differently, see [Self::op_name]
+    #[serde(default)]
+    pub eof: bool,
+}
+
+/// The operands of a call made by a step, see [CallTraceStep::call_operands]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CallOperands {
+    /// The gas passed on to the call, before it's capped to all but one 64th of the remaining gas
+    pub gas: U256,
+    /// The called address, i.e. the code address of a `DELEGATECALL` or `CALLCODE`
+    pub target: Address,
+    /// The value transferred in the call, zero for a `DELEGATECALL` or `STATICCALL`
+    pub value: U256,
+    /// The offset of the calldata in memory
+    pub args_offset: U256,
+    /// The length of the calldata
+    pub args_len: U256,
+}
+
+/// Whether an account or storage slot was already accessed in the transaction (EIP-2929)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Warmth {
+    /// Accessed before, or in the access list of the transaction
+    Warm,
+    /// Accessed for the first time, which costs extra gas
+    Cold,
+}
+
+/// The location of an instruction in the sources, as specified by the source map of a contract
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SourceLoc {
+    /// The index of the source file, `None` for code generated by the compiler
+    pub index: Option<u32>,
+    /// The byte offset of the source range
+    pub offset: usize,
+    /// The length of the source range in bytes
+    pub length: usize,
+    /// Whether the instruction jumps into or out of a function
+    pub jump: JumpType,
+    /// The depth of the modifiers the instruction is in, `0` if it is not in a modifier
+    pub modifier_depth: usize,
+}
+
+impl From<&SourceElement> for SourceLoc {
+    fn from(element: &SourceElement) -> Self {
+        Self {
+            index: element.index,
+            offset: element.offset,
+            length: element.length,
+            jump: element.jump.into(),
+            modifier_depth: element.modifier_depth,
+        }
+    }
+}
+
+/// The kind of jump of an instruction in a source map
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum JumpType {
+    /// A jump into a function
+    In,
+    /// A jump out of a function
+    Out,
+    /// A regular jump, e.g. of a loop, or no jump at all
+    Regular,
+}
+
+impl From<Jump> for JumpType {
+    fn from(jump: Jump) -> Self {
+        match jump {
+            Jump::In => JumpType::In,
+            Jump::Out => JumpType::Out,
+            Jump::Regular => JumpType::Regular,
+        }
+    }
+}
+
+// === impl CallTraceStep ===
+
+impl CallTraceStep {
+    /// Returns the name of the opcode of the step, e.g. `"SSTORE"`, which accounts for the opcodes
+    /// introduced by EOF, like `RJUMP` or `CALLF`, in EOF code.
+    ///
+    /// Note that `CALLF` and `RETF` call and return from functions of the same code, so they don't
+    /// change the [depth](Self::depth) of the steps, like in geth.
+    pub fn op_name(&self) -> String {
+        match self.op {
+            Instruction::OpCode(op) if self.eof => {
+                eof_opcode_name(op).map_or_else(|| self.op.to_string(), str::to_string)
+            }
+            _ => self.op.to_string(),
+        }
+    }
+
+    /// Returns an estimate of the gas cost of the step, based on the static gas cost of its
+    /// opcode, see [static_gas_cost], and the extra cost of a cold [access](Self::access)
+    pub fn estimated_gas_cost(&self) -> Option<u64> {
+        let cost = static_gas_cost(&self.op_name())?;
+        if self.access != Some(Warmth::Cold) {
+            return Some(cost)
+        }
+        let extra = match self.op {
+            Instruction::OpCode(opcode::SLOAD) => COLD_SLOAD_COST - WARM_STORAGE_READ_COST,
+            // `SSTORE` only pays for the cold slot on top of the cost of the store
+            Instruction::OpCode(opcode::SSTORE) => COLD_SLOAD_COST,
+            Instruction::OpCode(opcode::SELFDESTRUCT) => COLD_ACCOUNT_ACCESS_COST,
+            _ => COLD_ACCOUNT_ACCESS_COST - WARM_STORAGE_READ_COST,
+        };
+        Some(cost + extra)
+    }
+
+    /// Returns the `n`th item of the stack before the step, where `0` is the top of the stack
+    /// like in the EVM, e.g. the gas of a `CALL`
+    pub fn stack_top(&self, n: usize) -> Option<U256> {
+        self.stack.data().iter().rev().nth(n).copied()
+    }
+
+    /// Returns the operands of a `CALL`, `CALLCODE`, `DELEGATECALL` or `STATICCALL` step, `None`
+    /// for other steps or if the stack is too short
+    pub fn call_operands(&self) -> Option<CallOperands> {
+        let (value, args) = match self.op {
+            Instruction::OpCode(opcode::CALL | opcode::CALLCODE) => (self.stack_top(2)?, 3),
+            Instruction::OpCode(opcode::DELEGATECALL | opcode::STATICCALL) => (U256::zero(), 2),
+            _ => return None,
+        };
+        Some(CallOperands {
+            gas: self.stack_top(0)?,
+            target: Address::from(H256::from_uint(&self.stack_top(1)?)),
+            value,
+            args_offset: self.stack_top(args)?,
+            args_len: self.stack_top(args + 1)?,
+        })
+    }
+
+    /// Returns the number of items on the stack before the step
+    pub fn stack_len(&self) -> usize {
+        self.stack.data().len()
+    }
+
+    /// Returns the memory before the step as 32-byte words along with their offset.
+    ///
+    /// The last word is padded with zeros if the size of the memory is not a multiple of 32.
+    pub fn memory_words(&self) -> impl Iterator<Item = (usize, [u8; 32])> + '_ {
+        memory_words(self.memory.data())
+    }
+
+    /// Converts the step into a [StructLog], only cloning memory and stack if enabled in `opts`
+    ///
+    /// If the gas cost of the step was not recorded, it's estimated with
+    /// [CallTraceStep::estimated_gas_cost].
+    fn to_struct_log(&self, opts: &GethTraceOptions) -> StructLog {
+        let op = self.op_name();
+        StructLog {
+            depth: self.depth,
+            error: self.error.clone(),
+            gas: self.gas,
+            gas_cost: match self.gas_cost {
+                0 => self.estimated_gas_cost().unwrap_or_default(),
+                gas_cost => gas_cost,
+            },
+            memory: (!opts.disable_memory).then(|| convert_memory(self.memory.data())),
+            op,
+            pc: self.pc as u64,
+            refund_counter: Some(self.gas_refund_counter),
+            stack: (!opts.disable_stack).then(|| self.stack.data().clone()),
+            // Filled in `CallTraceArena::geth_trace` as a result of compounding all slot changes
+            storage: None,
+        }
+    }
+}
+
+/// The cost of reading a warm storage slot or accessing a warm account (EIP-2929)
+const WARM_STORAGE_READ_COST: u64 = 100;
+/// The cost of reading a cold storage slot (EIP-2929)
+const COLD_SLOAD_COST: u64 = 2100;
+/// The cost of accessing a cold account (EIP-2929)
+const COLD_ACCOUNT_ACCESS_COST: u64 = 2600;
+
+/// Returns the name of an opcode that only exists in EOF code (EIP-3540, EIP-4200, EIP-4750,
+/// EIP-6206, EIP-663, EIP-7069, EIP-7480 and EIP-7620), `None` for opcodes that are named like in
+/// legacy code.
+///
+/// The revm version in use can not execute EOF code, so these only appear in traces from other
+/// sources.
+fn eof_opcode_name(op: u8) -> Option<&'static str> {
+    let name = match op {
+        0xd0 => "DATALOAD",
+        0xd1 => "DATALOADN",
+        0xd2 => "DATASIZE",
+        0xd3 => "DATACOPY",
+        0xe0 => "RJUMP",
+        0xe1 => "RJUMPI",
+        0xe2 => "RJUMPV",
+        0xe3 => "CALLF",
+        0xe4 => "RETF",
+        0xe5 => "JUMPF",
+        0xe6 => "DUPN",
+        0xe7 => "SWAPN",
+        0xe8 => "EXCHANGE",
+        0xec => "EOFCREATE",
+        0xee => "RETURNCONTRACT",
+        0xf7 => "RETURNDATALOAD",
+        0xf8 => "EXTCALL",
+        0xf9 => "EXTDELEGATECALL",
+        0xfb => "EXTSTATICCALL",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Returns the static gas cost of the opcode with the given name, e.g. `"ADD"`, `None` for unknown
+/// opcodes.
+///
+/// This is exact for opcodes with a fixed cost. For opcodes with a dynamic cost, it's only the
+/// minimum cost of the opcode, since their actual cost depends on the execution:
+///
+/// - memory expansion: `MLOAD`, `MSTORE`, `MSTORE8`, `CALLDATACOPY`, `CODECOPY`, `RETURNDATACOPY`,
+///   `EXTCODECOPY`, `SHA3`, `LOG0`-`LOG4`, `RETURN`, `REVERT`
+/// - the size of the data: `SHA3`, `CALLDATACOPY`, `CODECOPY`, `RETURNDATACOPY`, `EXTCODECOPY`,
+///   `LOG0`-`LOG4`, `EXP`
+/// - cold access of accounts and slots: `BALANCE`, `EXTCODESIZE`, `EXTCODEHASH`, `EXTCODECOPY`,
+///   `SLOAD`, `SSTORE`, the calls and `SELFDESTRUCT`
+/// - the stored value: `SSTORE`
+/// - the transferred value, new accounts and the gas passed on: `CALL`, `CALLCODE`, `DELEGATECALL`,
+///   `STATICCALL`, `CREATE`, `CREATE2`, `SELFDESTRUCT`
+pub fn static_gas_cost(op: &str) -> Option<u64> {
+    let cost = match op {
+        "STOP" | "RETURN" | "REVERT" => 0,
+        "JUMPDEST" => 1,
+        "ADDRESS" | "ORIGIN" | "CALLER" | "CALLVALUE" | "CALLDATASIZE" | "CODESIZE" |
+        "GASPRICE" | "RETURNDATASIZE" | "COINBASE" | "TIMESTAMP" | "NUMBER" | "DIFFICULTY" |
+        "PREVRANDAO" | "GASLIMIT" | "CHAINID" | "BASEFEE" | "POP" | "PC" | "MSIZE" | "GAS" |
+        "PUSH0" => 2,
+        "ADD" | "SUB" | "NOT" | "LT" | "GT" | "SLT" | "SGT" | "EQ" | "ISZERO" | "AND" | "OR" |
+        "XOR" | "BYTE" | "SHL" | "SHR" | "SAR" | "CALLDATALOAD" | "MLOAD" | "MSTORE" |
+        "MSTORE8" | "CALLDATACOPY" | "CODECOPY" | "RETURNDATACOPY" => 3,
+        op if op.starts_with("PUSH") || op.starts_with("DUP") || op.starts_with("SWAP") => 3,
+        "MUL" | "DIV" | "SDIV" | "MOD" | "SMOD" | "SIGNEXTEND" | "SELFBALANCE" => 5,
+        "ADDMOD" | "MULMOD" | "JUMP" => 8,
+        "JUMPI" | "EXP" => 10,
+        "BLOCKHASH" => 20,
+        "SHA3" | "KECCAK256" => 30,
+        "BALANCE" | "EXTCODESIZE" | "EXTCODEHASH" | "EXTCODECOPY" | "SLOAD" | "SSTORE" |
+        "CALL" | "CALLCODE" | "DELEGATECALL" | "STATICCALL" => 100,
+        "LOG0" => 375,
+        "LOG1" => 750,
+        "LOG2" => 1125,
+        "LOG3" => 1500,
+        "LOG4" => 1875,
+        "SELFDESTRUCT" => 5000,
+        "CREATE" | "CREATE2" => 32000,
+        _ => return None,
+    };
+    Some(cost)
+}
+
+impl From<&CallTraceStep> for StructLog {
+    fn from(step: &CallTraceStep) -> Self {
+        step.to_struct_log(&GethTraceOptions::default())
+    }
+}
+
+/// A trace of a call.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CallTrace {
+    /// The depth of the call
+    pub depth: usize,
+    /// Whether the call was successful
+    pub success: bool,
+    /// The name of the contract, if any.
+    ///
+    /// The format is `"<artifact>:<contract>"` for easy lookup in local contracts.
+    ///
+    /// This member is not used by the core call tracing functionality (decoding/displaying). The
+    /// intended use case is for other components that may want to process traces by specific
+    /// contracts (e.g. gas reports).
+    pub contract: Option<String>,
+    /// The label for the destination address, if any
+    pub label: Option<String>,
+    /// caller of this call
+    pub caller: Address,
+    /// The destination address of the call or the address from the created contract
+    pub address: Address,
+    /// The kind of call this is
+    pub kind: CallKind,
+    /// The value transferred in the call
+    pub value: U256,
+    /// The calldata for the call, or the init code for contract creations
+    pub data: RawOrDecodedCall,
+    /// The decoded parameters of the call, if the calldata was decoded.
+    ///
+    /// These are the tokens [Self::data] is displayed with, before labels are applied. They are
+    /// not kept for cheatcode calls, which may contain secrets, and are not serialized.
+    #[serde(skip)]
+    pub decoded_inputs: Option<Vec<Token>>,
+    /// Whether the calldata was decoded with a function resolved by its selector from a
+    /// [SignatureLookup](identifier::SignatureLookup) instead of a known ABI, which is a
+    /// best-effort guess since selectors can collide
+    #[serde(default)]
+    pub best_effort_signature: bool,
+    /// The raw calldata and return data of the call, if kept with [CallTraceNode::retain_raw]
+    /// when the call was decoded, so it can be decoded again, see [CallTraceNode::restore_raw]
+    #[serde(skip)]
+    pub raw: Option<(Vec<u8>, Vec<u8>)>,
+    /// The return data of the call if this was not a contract creation, otherwise it is the
+    /// runtime bytecode of the created contract
+    pub output: RawOrDecodedReturnData,
+    /// The gas cost of the call
+    pub gas_cost: u64,
+    /// The gas limit of the call
+    #[serde(default)]
+    pub gas_limit: u64,
+    /// The status of the trace's call
+    pub status: Return,
+    /// call context of the runtime
+    pub call_context: Option<CallContext>,
+    /// Opcode-level execution steps
+    pub steps: Vec<CallTraceStep>,
+    /// The index of the step of the parent call that made this call, `None` for the top-level
+    /// call or if steps are not recorded
+    #[serde(default)]
+    pub parent_step: Option<usize>,
+    /// Whether the code of the call is EVM Object Format (EOF) code, i.e. starts with `0xEF00`
+    #[serde(default)]
+    pub eof: bool,
+    /// The state of the caller and the destination at the start of the call, `None` if an account
+    /// did not exist. Only recorded if enabled with
+    /// [Tracer::with_state_diff_recording](crate::executor::inspector::Tracer::with_state_diff_recording).
+    ///
+    /// The storage contains the values at the start of the call of the slots that were changed in
+    /// the transaction before the end of the call.
+    #[serde(default)]
+    pub pre_state: BTreeMap<Address, Option<AccountState>>,
+    /// The state of the caller and the destination after the call, `None` if an account does not
+    /// exist (anymore)
+    #[serde(default)]
+    pub post_state: BTreeMap<Address, Option<AccountState>>,
+}
+
+/// The state of an account touched by a [CallTrace]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AccountState {
+    pub balance: U256,
+    pub nonce: u64,
+    pub code: Bytes,
+    /// The storage slots that were changed in the transaction
+    pub storage: BTreeMap<U256, U256>,
+}
+
+// === impl CallTrace ===
+
+impl CallTrace {
+    /// Whether this is a contract creation or not
+    pub fn created(&self) -> bool {
+        self.kind.is_create()
+    }
+
+    /// Whether this is a call to one of the Ethereum precompiles at the addresses `0x01` to
+    /// `0x09`.
+    ///
+    /// Precompiles of other chains are not known here, but can be added to
+    /// [CallTraceDecoder::precompiles] to decode calls to them.
+    pub fn is_precompile(&self) -> bool {
+        let (prefix, last) = self.address.as_bytes().split_at(19);
+        prefix.iter().all(|byte| *byte == 0) && (1..=9).contains(&last[0])
+    }
+}
+
+impl Default for CallTrace {
+    fn default() -> Self {
+        Self {
+            depth: Default::default(),
+            success: Default::default(),
+            contract: Default::default(),
+            label: Default::default(),
+            caller: Default::default(),
+            address: Default::default(),
+            kind: Default::default(),
+            value: Default::default(),
+            data: Default::default(),
+            decoded_inputs: Default::default(),
+            best_effort_signature: Default::default(),
+            raw: Default::default(),
+            output: Default::default(),
+            gas_cost: Default::default(),
+            gas_limit: Default::default(),
+            status: Return::Continue,
+            call_context: Default::default(),
+            steps: Default::default(),
+            parent_step: Default::default(),
+            eof: Default::default(),
+            pre_state: Default::default(),
+            post_state: Default::default(),
+        }
+    }
+}
+
+impl fmt::Display for CallTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_truncated(f, None, false, true)
+    }
+}
+
+impl CallTrace {
+    /// Returns the raw calldata of the call, also if it was decoded, `None` if it was decoded
+    /// without keeping the raw data, e.g. in deserialized traces
+    pub fn raw_calldata(&self) -> Option<&[u8]> {
+        match (&self.data, &self.raw) {
+            (RawOrDecodedCall::Raw(data), _) | (_, Some((data, _))) => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Returns the raw return data of the call, also if it was decoded, see [Self::raw_calldata]
+    pub fn raw_output(&self) -> Option<&[u8]> {
+        match (&self.output, &self.raw) {
+            (RawOrDecodedReturnData::Raw(output), _) | (_, Some((_, output))) => Some(output),
+            _ => None,
+        }
+    }
+
+    /// Returns the selector of the calldata the call was made with, also if it is decoded,
+    /// `None` for contract creations and calls without a selector
+    pub fn raw_selector(&self) -> Option<[u8; SELECTOR_LEN]> {
+        if self.created() {
+            return None
+        }
+        self.raw_calldata()?.get(..SELECTOR_LEN)?.try_into().ok()
+    }
+
+    /// Displays the call, truncating raw calldata to `max_raw_bytes` bytes if set, and showing the
+    /// selector of the calldata after the inputs of decoded calls if `show_selector` is set. The
+    /// call is colored if `color` is set.
+    fn fmt_truncated(
+        &self,
+        f: &mut (impl Write + ?Sized),
+        max_raw_bytes: Option<usize>,
+        show_selector: bool,
+        color: bool,
+    ) -> fmt::Result {
+        let address = to_checksum(&self.address, None);
+        if self.created() {
+            write!(
+                f,
+                "[{}] {}{} {}@{}",
+                self.gas_cost,
+                painted(Paint::yellow(CALL), color),
+                painted(Paint::yellow("new"), color),
+                self.label.as_ref().unwrap_or(&"<Unknown>".to_string()),
+                address
+            )?;
+        } else {
+            let (func, inputs) = match &self.data {
+                RawOrDecodedCall::Raw(bytes) => {
+                    // We assume that the fallback function (`data.len() < 4`) counts as decoded
+                    // calldata
+                    assert!(bytes.len() >= 4);
+                    (
+                        hex::encode(&bytes[0..4]),
+                        painted(
+                            Paint::new(truncated_hex(&bytes[4..], max_raw_bytes)).dimmed(),
+                            color,
+                        ),
+                    )
+                }
+                RawOrDecodedCall::Decoded(func, _, inputs) => (func.clone(), inputs.join(", ")),
+            };
+
+            let action = match self.kind {
+                // do not show anything for CALLs
+                CallKind::Call => "",
+                CallKind::StaticCall => "[staticcall]",
+                CallKind::CallCode => "[callcode]",
+                CallKind::DelegateCall => "[delegatecall]",
+                CallKind::AuthCall => "[authcall]",
+                _ => unreachable!(),
+            };
+
+            let selector = match self.raw_selector() {
+                Some(selector)
+                    if show_selector && matches!(self.data, RawOrDecodedCall::Decoded(..)) =>
+                {
+                    painted(Paint::new(format!(" [0x{}]", hex::encode(selector))).dimmed(), color)
+                }
+                _ => "".to_string(),
+            };
+
+            let trace_color = trace_color(self);
+            write!(
+                f,
+                "[{}] {}::{}{}({}){} {}{}",
+                self.gas_cost,
+                painted(trace_color.paint(self.label.as_ref().unwrap_or(&address)), color),
+                painted(trace_color.paint(func), color),
+                // a `DELEGATECALL` does not transfer value, unlike a `CALLCODE`
+                if !self.value.is_zero() && !self.kind.is_delegate() {
+                    format!("{{value: {}}}", self.value)
+                } else {
+                    "".to_string()
+                },
+                inputs,
+                selector,
+                painted(Paint::yellow(action), color),
+                if self.best_effort_signature {
+                    painted(Paint::new(" [best-effort signature]").dimmed(), color)
+                } else {
+                    "".to_string()
+                },
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Specifies the kind of trace.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TraceKind {
+    Deployment,
+    Setup,
+    Execution,
+}
+
+/// Displays `paint` with its colors and styles if `color` is set, or just its item otherwise
+fn painted<T: fmt::Display>(paint: Paint<T>, color: bool) -> String {
+    if color {
+        paint.to_string()
+    } else {
+        paint.inner().to_string()
+    }
+}
+
+/// Chooses the color of the trace depending on the destination address and status of the call.
+fn trace_color(trace: &CallTrace) -> Color {
+    if trace.address == CHEATCODE_ADDRESS {
+        Color::Blue
+    } else if trace.success {
+        Color::Green
+    } else {
+        Color::Red
+    }
+}
+
+/// Returns the name of the call for exported traces, i.e. `<label or address>::<function>`, or
+/// `new <label or address>` for contract creations
+fn frame_name(trace: &CallTrace) -> String {
+    let address = trace.label.clone().unwrap_or_else(|| to_checksum(&trace.address, None));
+    if trace.created() {
+        return format!("new {}", address)
+    }
+    let func = match &trace.data {
+        RawOrDecodedCall::Decoded(func, _, _) => func.clone(),
+        RawOrDecodedCall::Raw(bytes) if bytes.len() >= SELECTOR_LEN => {
+            hex::encode(&bytes[..SELECTOR_LEN])
+        }
+        RawOrDecodedCall::Raw(_) => "fallback".to_string(),
+    };
+    format!("{}::{}", address, func)
+}
+
+/// Given a list of traces and artifacts, it returns a map connecting address to abi
+pub fn load_contracts(
+    traces: Vec<(TraceKind, CallTraceArena)>,
+    known_contracts: Option<&ContractsByArtifact>,
+) -> ContractsByAddress {
+    if let Some(contracts) = known_contracts {
+        let mut local_identifier = LocalTraceIdentifier::new(contracts);
+        let mut decoder = CallTraceDecoderBuilder::new().build();
+        for (_, trace) in &traces {
+            decoder.identify(trace, &mut local_identifier);
+        }
+
+        decoder
+            .contracts
+            .iter()
+            .filter_map(|(addr, name)| {
+                if let Ok(Some((_, (abi, _)))) = contracts.find_by_name_or_identifier(name) {
+                    return Some((*addr, (name.clone(), abi.clone())))
+                }
+                None
+            })
+            .collect()
+    } else {
+        BTreeMap::new()
+    }
+}
+
+/// creates the memory data in 32byte chunks
+/// see <https://github.com/ethereum/go-ethereum/blob/366d2169fbc0e0f803b68c042b77b6b480836dbc/eth/tracers/logger/logger.go#L450-L452>
+fn convert_memory(data: &[u8]) -> Vec<String> {
+    let mut memory = Vec::with_capacity((data.len() + 31) / 32);
+    for idx in (0..data.len()).step_by(32) {
+        let len = std::cmp::min(idx + 32, data.len());
+        memory.push(hex::encode(&data[idx..len]));
+    }
+    memory
+}
+
+/// Returns the stack items pushed by the `step`, given the `next` step of the same call
+fn pushed_items(step: &CallTraceStep, next: &CallTraceStep) -> Vec<U256> {
+    let pushed = match step.op {
+        Instruction::OpCode(op @ opcode::SWAP1..=opcode::SWAP16) => {
+            (op - opcode::SWAP1 + 2) as usize
+        }
+        Instruction::OpCode(
+            opcode::POP |
+            opcode::MSTORE |
+            opcode::MSTORE8 |
+            opcode::SSTORE |
+            opcode::JUMP |
+            opcode::JUMPI |
+            opcode::JUMPDEST |
+            opcode::CALLDATACOPY |
+            opcode::CODECOPY |
+            opcode::EXTCODECOPY |
+            opcode::RETURNDATACOPY |
+            opcode::LOG0..=opcode::LOG4 |
+            opcode::STOP |
+            opcode::RETURN |
+            opcode::REVERT |
+            opcode::INVALID |
+            opcode::SELFDESTRUCT,
+        ) => 0,
+        _ => 1,
+    };
+    let stack = next.stack.data();
+    stack[stack.len().saturating_sub(pushed)..].to_vec()
+}
+
+/// Returns the range of memory that changed from `before` to `after`, `None` if it did not change
+fn memory_diff(before: &[u8], after: &[u8]) -> Option<MemoryDiff> {
+    let changed = |offset: &usize| before.get(*offset) != after.get(*offset);
+    let start = (0..after.len()).find(changed)?;
+    let end = (start..after.len()).rev().find(changed)? + 1;
+    Some(MemoryDiff { off: start, data: after[start..end].to_vec().into() })
+}
+
+/// Returns the memory `data` in 32-byte words along with their offsets, padding the last word with
+/// zeros
+fn memory_words(data: &[u8]) -> impl Iterator<Item = (usize, [u8; 32])> + '_ {
+    data.chunks(32).enumerate().map(|(idx, chunk)| {
+        let mut word = [0u8; 32];
+        word[..chunk.len()].copy_from_slice(chunk);
+        (idx * 32, word)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use foundry_common::abi::get_event;
+
+    /// Returns a step with the given opcode at depth 1 and its other fields empty, to build steps
+    /// with struct update syntax
+    fn step_of(op: u8) -> CallTraceStep {
+        CallTraceStep {
+            depth: 1,
+            pc: 0,
+            op: Instruction::OpCode(op),
+            contract: Address::zero(),
+            stack: Stack::new(),
+            memory: Memory::new(),
+            gas: 0,
+            gas_refund_counter: 0,
+            gas_cost: 0,
+            state_diff: None,
+            error: None,
+            source_location: None,
+            access: None,
+            eof: false,
+        }
+    }
+
+    #[test]
+    fn can_convert_memory() {
+        let mut data = vec![0u8; 32];
+        assert_eq!(
+            convert_memory(&data),
+            vec!["0000000000000000000000000000000000000000000000000000000000000000".to_string()]
+        );
+        data.extend(data.clone());
+        assert_eq!(
+            convert_memory(&data),
+            vec![
+                "0000000000000000000000000000000000000000000000000000000000000000".to_string(),
+                "0000000000000000000000000000000000000000000000000000000000000000".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn can_reencode_decoded_call() {
+        let to = Address::random();
+        let func = get_func("transfer(address,uint256)").unwrap();
+        let calldata =
+            func.encode_input(&[Token::Address(to), Token::Uint(100u64.into())]).unwrap();
+
+        let decoded = RawOrDecodedCall::Decoded(
+            func.name.clone(),
+            func.signature(),
+            vec![to_checksum(&to, None), "100".to_string()],
+        );
+        assert_eq!(decoded.to_calldata(), Some(calldata.into()));
+
+        let labeled = RawOrDecodedCall::Decoded(
+            func.name.clone(),
+            func.signature(),
+            vec![format!("alice: [{}]", to_checksum(&to, None)), "100".to_string()],
+        );
+        assert_eq!(labeled.to_calldata(), None);
+    }
+
+    #[test]
+    fn can_serialize_node_logs() {
+        let node = CallTraceNode {
+            logs: vec![
+                RawOrDecodedLog::Raw(RawLog { topics: vec![H256::random()], data: vec![1, 2, 3] }),
+                RawOrDecodedLog::Decoded(
+                    "Transfer".to_string(),
+                    vec![("amount".to_string(), "100".to_string())],
+                ),
+            ],
+            ordering: vec![LogCallOrder::Log(0), LogCallOrder::Call(0), LogCallOrder::Log(1)],
+            ..Default::default()
+        };
+
+        let json = serde_json::to_string(&node).unwrap();
+        let deserialized: CallTraceNode = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, node);
+    }
+
+    #[test]
+    fn can_serialize_arena_to_bytes() {
+        let mut arena = CallTraceArena::default();
+        arena.arena[0].trace.output = RawOrDecodedReturnData::Raw(vec![1, 2, 3]);
+        arena.push_trace(
+            0,
+            CallTrace {
+                depth: 1,
+                data: RawOrDecodedCall::Decoded(
+                    "transfer".to_string(),
+                    "transfer(address,uint256)".to_string(),
+                    vec!["0x0000000000000000000000000000000000000000".to_string(), "1".to_string()],
+                ),
+                output: RawOrDecodedReturnData::Decoded("true".to_string()),
+                ..Default::default()
+            },
+        );
+
+        let bytes = arena.to_bytes().unwrap();
+        assert_eq!(CallTraceArena::from_bytes(&bytes).unwrap(), arena);
+    }
+
+    #[test]
+    fn serializes_geth_trace_like_geth() {
+        let mut stack = Stack::new();
+        stack.push(U256::from(0x60u64)).unwrap();
+        let step = CallTraceStep {
+            pc: 2,
+            stack,
+            gas: 100,
+            gas_cost: 20_000,
+            state_diff: Some((U256::one(), U256::from(2u64))),
+            ..step_of(revm::opcode::SSTORE)
+        };
+        let mut arena = CallTraceArena::default();
+        arena.arena[0].trace.steps = vec![step];
+        arena.arena[0].trace.success = true;
+        arena.arena[0].trace.output = RawOrDecodedReturnData::Raw(vec![0xab]);
+
+        let trace = arena.geth_trace_with(21_000u64.into(), GethTraceOptions::default());
+        let slot = |n: u8| format!("{}{n:02x}", "0".repeat(62));
+        assert_eq!(
+            serde_json::to_string(&GethTraceJson(&trace)).unwrap(),
+            format!(
+                r#"{{"gas":21000,"failed":false,"returnValue":"ab","structLogs":[{{"pc":2,"op":"SSTORE","gas":100,"gasCost":20000,"depth":1,"stack":["{}"],"memory":[],"storage":{{"{}":"{}"}}}}]}}"#,
+                slot(0x60),
+                slot(1),
+                slot(2)
+            )
+        );
+    }
+
+    #[test]
+    fn geth_trace_is_in_execution_order() {
+        let step =
+            |depth: u64, pc: usize| CallTraceStep { depth, pc, ..step_of(revm::opcode::CALL) };
+
+        let mut arena = CallTraceArena::default();
+        arena.arena[0].trace.steps = vec![step(1, 0), step(1, 1)];
+        arena.push_trace(
+            0,
+            CallTrace {
+                depth: 1,
+                steps: vec![step(2, 0)],
+                parent_step: Some(0),
+                ..Default::default()
+            },
+        );
+
+        let trace = arena.geth_trace_with(U256::zero(), Default::default());
+        let steps = trace.struct_logs.iter().map(|log| (log.depth, log.pc)).collect::<Vec<_>>();
+        assert_eq!(steps, vec![(1, 0), (2, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn can_omit_memory_stack_and_storage_of_geth_trace() {
+        let mut stack = Stack::new();
+        stack.push(U256::one()).unwrap();
+        let mut arena = CallTraceArena::default();
+        arena.arena[0].trace.steps = vec![CallTraceStep {
+            stack,
+            state_diff: Some((U256::one(), U256::from(2u64))),
+            ..step_of(revm::opcode::SSTORE)
+        }];
+
+        let trace = arena.geth_trace_with(U256::zero(), GethTraceOptions::default());
+        let log = &trace.struct_logs[0];
+        assert_eq!(log.stack, Some(vec![U256::one()]));
+        assert_eq!(log.memory, Some(vec![]));
+        assert_eq!(log.storage.as_ref().map(BTreeMap::len), Some(1));
+
+        let opts = GethTraceOptions {
+            disable_memory: true,
+            disable_stack: true,
+            disable_storage: true,
+            ..Default::default()
+        };
+        let trace = arena.geth_trace_with(U256::zero(), opts);
+        let log = &trace.struct_logs[0];
+        assert_eq!((&log.stack, &log.memory, &log.storage), (&None, &None, &None));
+
+        let opts = GethDebugTracingOptions { enable_memory: Some(true), ..Default::default() };
+        let opts = GethTraceOptions::from(opts);
+        assert!(!opts.disable_memory && !opts.disable_stack && !opts.disable_storage);
+        assert!(GethTraceOptions::from(GethDebugTracingOptions::default()).disable_memory);
+    }
+
+    #[test]
+    fn can_build_geth_call_trace() {
+        let (sender, to, callee) = (Address::random(), Address::random(), Address::random());
+        let mut arena = CallTraceArena::default();
+        arena.arena[0].trace = CallTrace {
+            caller: sender,
+            address: to,
+            value: 1u64.into(),
+            gas_limit: 100_000,
+            gas_cost: 50_000,
+            success: true,
+            data: RawOrDecodedCall::Raw(vec![1, 2, 3, 4]),
+            output: RawOrDecodedReturnData::Raw(vec![0xab]),
+            ..Default::default()
+        };
+        arena.push_trace(
+            0,
+            CallTrace {
+                depth: 1,
+                caller: to,
+                address: callee,
+                kind: CallKind::StaticCall,
+                gas_limit: 60_000,
+                gas_cost: 100,
+                status: Return::Revert,
+                ..Default::default()
+            },
+        );
+
+        let frame = arena.geth_call_trace();
+        assert_eq!((frame.kind, frame.from, frame.to), (CallKind::Call, sender, to));
+        assert_eq!(frame.value, Some(1u64.into()));
+        assert_eq!((frame.gas, frame.gas_used), (100_000u64.into(), 50_000u64.into()));
+        assert_eq!(frame.input, Bytes::from(vec![1, 2, 3, 4]));
+        assert_eq!(frame.output, Some(Bytes::from(vec![0xab])));
+        assert_eq!(frame.error, None);
+
+        assert_eq!(frame.calls.len(), 1);
+        let sub_call = &frame.calls[0];
+        assert_eq!((sub_call.kind, sub_call.from, sub_call.to), (CallKind::StaticCall, to, callee));
+        assert_eq!((sub_call.value, &sub_call.output), (None, &None));
+        assert_eq!(sub_call.error.as_deref(), Some("execution reverted"));
+        assert!(sub_call.calls.is_empty());
+
+        let json = serde_json::to_value(&frame).unwrap();
+        assert_eq!(json["type"], "CALL");
+        assert_eq!(json["calls"][0]["type"], "STATICCALL");
+        assert!(json["calls"][0].get("value").is_none());
+    }
+
+    #[test]
+    fn deserializes_traces_without_new_fields() {
+        let mut json =
+            serde_json::to_value(CallTrace { gas_limit: 1, ..Default::default() }).unwrap();
+        for field in ["gas_limit", "pre_state", "post_state", "parent_step"] {
+            json.as_object_mut().unwrap().remove(field);
+        }
+        let trace: CallTrace = serde_json::from_value(json).unwrap();
+        assert_eq!(trace, CallTrace::default());
+    }
+
+    #[test]
+    fn can_decode_logs() {
+        let erc20 =
+            get_event("event Transfer(address indexed from, address indexed to, uint256 value)")
+                .unwrap();
+        let erc721 = get_event(
+            "event Transfer(address indexed from, address indexed to, uint256 indexed tokenId)",
+        )
+        .unwrap();
+        let (from, to) = (Address::random(), Address::random());
+        let log = RawLog {
+            topics: vec![erc20.signature(), H256::from(from), H256::from(to)],
+            data: ethers::abi::encode(&[Token::Uint(100u64.into())]),
+        };
+        let unknown = RawLog { topics: vec![H256::random()], data: vec![] };
+        let mut node = CallTraceNode {
+            logs: vec![RawOrDecodedLog::Raw(log.clone()), RawOrDecodedLog::Raw(unknown.clone())],
+            ..Default::default()
+        };
+
+        let labels = std::collections::HashMap::from([(from, "alice".to_string())]);
+        node.decode_logs(&[erc721.clone(), erc20], &labels);
+        assert_eq!(
+            node.logs,
+            vec![
+                RawOrDecodedLog::Decoded(
+                    "Transfer".to_string(),
+                    vec![
+                        ("from".to_string(), format!("alice: [{}]", to_checksum(&from, None))),
+                        ("to".to_string(), to_checksum(&to, None)),
+                        ("value".to_string(), "100".to_string()),
+                    ]
+                ),
+                RawOrDecodedLog::Raw(unknown),
+            ]
+        );
+
+        // Both events have the same `topic0` and decode the log to different params
+        let weth =
+            get_event("event Transfer(address indexed src, address indexed dst, uint256 wad)")
+                .unwrap();
+        let erc20 =
+            get_event("event Transfer(address indexed from, address indexed to, uint256 value)")
+                .unwrap();
+        let mut node =
+            CallTraceNode { logs: vec![RawOrDecodedLog::Raw(log.clone())], ..Default::default() };
+        node.decode_logs(&[erc20, weth, erc721], &Default::default());
+        assert_eq!(node.logs, vec![RawOrDecodedLog::Raw(log)]);
+    }
+
+    #[test]
+    fn reads_selfdestruct_beneficiary() {
+        use ethers::types::Action;
+
+        let (contract, beneficiary) = (Address::random(), Address::random());
+        let mut stack = Stack::new();
+        stack.push(H256::from(beneficiary).into_uint()).unwrap();
+        let mut node = CallTraceNode {
+            trace: CallTrace {
+                address: contract,
+                value: 10u64.into(),
+                status: Return::SelfDestruct,
+                steps: vec![CallTraceStep { stack, ..step_of(revm::opcode::SELFDESTRUCT) }],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let suicide = |node: &CallTraceNode| match node.parity_action() {
+            Action::Suicide(suicide) => (suicide.address, suicide.refund_address, suicide.balance),
+            action => panic!("unexpected action {action:?}"),
+        };
+        assert_eq!(suicide(&node), (contract, beneficiary, 10u64.into()));
+
+        // the beneficiary is unknown without steps
+        node.trace.steps.clear();
+        assert_eq!(suicide(&node), (contract, Address::zero(), 10u64.into()));
+    }
+
+    #[test]
+    fn omits_steps_deeper_than_max_depth() {
+        let step = |depth: u64, pc: usize, state_diff| CallTraceStep {
+            depth,
+            pc,
+            state_diff,
+            ..step_of(revm::opcode::SSTORE)
+        };
+
+        let mut arena = CallTraceArena::default();
+        arena.arena[0].trace.steps = vec![step(1, 0, None), step(1, 1, None)];
+        arena.push_trace(
+            0,
+            CallTrace {
+                depth: 1,
+                steps: vec![step(2, 0, Some((U256::one(), U256::from(2u64))))],
+                parent_step: Some(0),
+                ..Default::default()
+            },
+        );
+
+        let opts = GethTraceOptions { max_depth: Some(0), ..Default::default() };
+        let trace = arena.geth_trace_with(U256::zero(), opts);
+        let steps = trace.struct_logs.iter().map(|log| (log.depth, log.pc)).collect::<Vec<_>>();
+        assert_eq!(steps, vec![(1, 0), (1, 1)]);
+
+        let opts = GethTraceOptions { max_depth: Some(1), ..Default::default() };
+        assert_eq!(arena.geth_trace_with(U256::zero(), opts).struct_logs.len(), 3);
+    }
+
+    #[test]
+    fn can_estimate_static_gas_cost() {
+        assert_eq!(static_gas_cost("ADD"), Some(3));
+        assert_eq!(static_gas_cost("PUSH32"), Some(3));
+        assert_eq!(static_gas_cost("SLOAD"), Some(100));
+        assert_eq!(static_gas_cost("UNDEFINED(0x0c)"), None);
+    }
+
+    #[test]
+    fn can_read_call_operands() {
+        let step = |op, stack: &[u64]| {
+            let mut step_stack = Stack::new();
+            // the first item is the top of the stack
+            for value in stack.iter().rev() {
+                step_stack.push((*value).into()).unwrap();
+            }
+            CallTraceStep { stack: step_stack, ..step_of(op) }
+        };
+        let target = Address::from_low_u64_be(0xbeef);
+
+        assert_eq!(
+            step(opcode::CALL, &[5000, 0xbeef, 7, 32, 68, 0, 32]).call_operands(),
+            Some(CallOperands {
+                gas: 5000u64.into(),
+                target,
+                value: 7u64.into(),
+                args_offset: 32u64.into(),
+                args_len: 68u64.into(),
+            })
+        );
+        assert_eq!(
+            step(opcode::STATICCALL, &[5000, 0xbeef, 32, 68, 0, 32]).call_operands(),
+            Some(CallOperands {
+                gas: 5000u64.into(),
+                target,
+                value: U256::zero(),
+                args_offset: 32u64.into(),
+                args_len: 68u64.into(),
+            })
+        );
+        assert_eq!(step(opcode::CALL, &[5000, 0xbeef]).call_operands(), None);
+        assert_eq!(step(opcode::SLOAD, &[0]).call_operands(), None);
+    }
+
+    #[test]
+    fn can_estimate_cold_access_gas_cost() {
+        let step = |op, access| CallTraceStep { access, ..step_of(op) };
+
+        assert_eq!(step(opcode::SLOAD, Some(Warmth::Warm)).estimated_gas_cost(), Some(100));
+        assert_eq!(step(opcode::SLOAD, Some(Warmth::Cold)).estimated_gas_cost(), Some(2100));
+        assert_eq!(step(opcode::BALANCE, Some(Warmth::Cold)).estimated_gas_cost(), Some(2600));
+        assert_eq!(step(opcode::CALL, Some(Warmth::Cold)).estimated_gas_cost(), Some(2600));
+        assert_eq!(step(opcode::ADD, None).estimated_gas_cost(), Some(3));
+    }
+
+    #[test]
+    fn can_filter_addresses() {
+        let (a, b, c) = (Address::random(), Address::random(), Address::random());
+        let call = |depth, address| CallTrace { depth, address, ..Default::default() };
+
+        // root -> a -> b, root -> c
+        let mut arena = CallTraceArena::default();
+        let a_idx = arena.push_trace(0, call(1, a));
+        arena.push_trace(a_idx, call(2, b));
+        arena.push_trace(0, call(1, c));
+
+        let filtered = arena.filter_addresses(|address| *address == b);
+        let nodes = filtered
+            .arena
+            .iter()
+            .map(|node| (node.idx, node.parent, node.children.clone(), node.trace.address))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            nodes,
+            vec![
+                (0, None, vec![1], Address::zero()),
+                (1, Some(0), vec![2], a),
+                (2, Some(1), vec![], b)
+            ]
+        );
+        assert_eq!(filtered.arena[0].ordering, vec![LogCallOrder::Call(0)]);
+    }
+
+    #[test]
+    fn displays_logs_between_calls() {
+        let call = |depth, func: &str| CallTrace {
+            depth,
+            data: RawOrDecodedCall::Decoded(func.to_string(), format!("{func}()"), vec![]),
+            ..Default::default()
+        };
+        let log = |name: &str| RawOrDecodedLog::Decoded(name.to_string(), vec![]);
+
+        let mut arena = CallTraceArena::default();
+        arena.arena[0].trace = call(0, "outer");
+        arena.arena[0].logs = vec![log("First")];
+        arena.arena[0].ordering.push(LogCallOrder::Log(0));
+        arena.push_trace(0, call(1, "inner"));
+        arena.arena[0].logs.push(log("Second"));
+        arena.arena[0].ordering.push(LogCallOrder::Log(1));
+
+        let out = arena.to_string();
+        let position = |s| out.find(s).unwrap();
+        assert!(position("outer") < position("First"));
+        assert!(position("First") < position("inner"));
+        assert!(position("inner") < position("Second"));
+    }
+
+    #[test]
+    fn distinguishes_invalid_opcode_from_revert() {
+        let invalid = Some(Instruction::OpCode(opcode::INVALID));
+        assert_eq!(utils::geth_error(Return::InvalidOpcode, invalid), "invalid opcode: INVALID");
+        assert_eq!(utils::geth_error(Return::InvalidOpcode, None), "invalid opcode: INVALID");
+        assert_eq!(
+            utils::geth_error(Return::OpcodeNotFound, Some(Instruction::OpCode(0x0c))),
+            "invalid opcode: opcode 0xc not defined"
+        );
+        assert_eq!(
+            utils::geth_error(Return::Revert, Some(Instruction::OpCode(opcode::REVERT))),
+            "execution reverted"
+        );
+
+        let mut node = CallTraceNodeBuilder::new().success(false).build();
+        node.trace.status = Return::InvalidOpcode;
+        node.trace.steps.push(step_of(opcode::INVALID));
+        assert_eq!(node.status_string(), "invalid opcode: INVALID");
+        assert_eq!(node.geth_call_frame().error.as_deref(), Some("invalid opcode: INVALID"));
+    }
+
+    #[test]
+    fn describes_status() {
+        let node = |status| {
+            let mut node = CallTraceNodeBuilder::new().success(false).build();
+            node.trace.status = status;
+            node
+        };
+        assert_eq!(node(Return::Revert).status_string(), "reverted");
+        assert_eq!(node(Return::OutOfGas).status_string(), "out of gas");
+        assert_eq!(node(Return::SelfDestruct).status_string(), "self-destructed");
+        assert_eq!(utils::geth_error(Return::Revert, None), "execution reverted");
+        assert_eq!(utils::geth_error(Return::InvalidJump, None), "invalid jump destination");
+
+        let mut arena = CallTraceNodeBuilder::new()
+            .calldata([0x12, 0x34, 0x56, 0x78])
+            .child(CallTraceNodeBuilder::new().calldata([0x12, 0x34, 0x56, 0x78]).success(false))
+            .build_arena();
+        arena.arena[1].trace.status = Return::OutOfGas;
+        assert!(arena
+            .display()
+            .max_raw_bytes(None)
+            .color(false)
+            .to_string()
+            .contains("← out of gas"));
+    }
+
+    #[test]
+    fn can_replay_call() {
+        let (provider, mock) = ethers::providers::Provider::mocked();
+        let provider = crate::executor::fork::BlockingProvider::new(provider);
+        let mut node = CallTraceNodeBuilder::new()
+            .kind(CallKind::DelegateCall)
+            .address(Address::from_low_u64_be(1))
+            .caller(Address::from_low_u64_be(2))
+            .value(1u64.into())
+            .calldata([0x12, 0x34, 0x56, 0x78])
+            .build();
+        // the gas forwarded to the call does not cover the intrinsic gas of a transaction
+        node.trace.gas_limit = 21_000;
+        // a delegate call runs with the value of its caller, which it is not sent
+        let mut tx: ethers::types::transaction::eip2718::TypedTransaction =
+            ethers::types::TransactionRequest::new()
+                .from(Address::from_low_u64_be(2))
+                .to(Address::from_low_u64_be(1))
+                .value(0u64)
+                .data(vec![0x12, 0x34, 0x56, 0x78])
+                .into();
+
+        mock.push(Bytes::from(vec![0xab])).unwrap();
+        assert_eq!(node.replay_call(&provider, None).unwrap(), Bytes::from(vec![0xab]));
+        mock.assert_request("eth_call", serde_json::json!([tx, "latest"])).unwrap();
+
+        let overrides = [(
+            Address::from_low_u64_be(1),
+            crate::executor::fork::AccountOverride {
+                balance: Some(1u64.into()),
+                ..Default::default()
+            },
+        )]
+        .into_iter()
+        .collect();
+        mock.push(Bytes::from(vec![0xcd])).unwrap();
+        assert_eq!(
+            node.replay_call_with(&provider, None, Some(100_000), &overrides).unwrap(),
+            Bytes::from(vec![0xcd])
+        );
+        tx.set_gas(100_000u64);
+        mock.assert_request("eth_call", serde_json::json!([tx, "latest", overrides])).unwrap();
+    }
+
+    #[test]
+    fn distinguishes_callcode_from_delegatecall() {
+        use ethers::types::{Action, CallType};
+
+        let call = |kind| {
+            CallTraceNodeBuilder::new()
+                .kind(kind)
+                .caller(Address::from_low_u64_be(1))
+                .address(Address::from_low_u64_be(2))
+                .value(5u64.into())
+                .calldata([0x12, 0x34, 0x56, 0x78])
+        };
+        let arena = CallTraceNodeBuilder::new()
+            .calldata([0; 4])
+            .child(call(CallKind::CallCode))
+            .child(call(CallKind::DelegateCall))
+            .build_arena();
+
+        let out = arena.display().max_raw_bytes(None).color(false).to_string();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert!(lines[1].ends_with("::12345678{value: 5}() [callcode]"));
+        assert!(lines[3].ends_with("::12345678() [delegatecall]"));
+
+        let (callcode, delegatecall) = (&arena.arena[1], &arena.arena[2]);
+        match (callcode.parity_action(), delegatecall.parity_action()) {
+            (Action::Call(callcode), Action::Call(delegatecall)) => {
+                assert_eq!(callcode.call_type, CallType::CallCode);
+                assert_eq!(callcode.value, 5u64.into());
+                assert_eq!(delegatecall.call_type, CallType::DelegateCall);
+                assert_eq!(delegatecall.value, U256::zero());
+            }
+            actions => panic!("unexpected actions {actions:?}"),
+        }
+        assert_eq!(callcode.geth_call_frame().value, Some(5u64.into()));
+        assert_eq!(delegatecall.geth_call_frame().value, None);
+    }
+
+    #[test]
+    fn traces_authcall_as_call_of_authorized_account() {
+        use ethers::types::{Action, ActionType, CallType, Res};
+
+        let (authorized, invoker) = (Address::from_low_u64_be(1), Address::from_low_u64_be(2));
+        let arena = CallTraceNodeBuilder::new()
+            .calldata([0; 4])
+            .child(
+                CallTraceNodeBuilder::new()
+                    .kind(CallKind::AuthCall)
+                    .caller(authorized)
+                    .address(invoker)
+                    .value(5u64.into())
+                    .calldata([0x12, 0x34, 0x56, 0x78]),
+            )
+            .build_arena();
+
+        let out = arena.display().max_raw_bytes(None).color(false).to_string();
+        assert!(out.lines().nth(1).unwrap().ends_with("::12345678{value: 5}() [authcall]"));
+
+        let node = &arena.arena[1];
+        assert_eq!(ActionType::from(node.kind()), ActionType::Call);
+        match node.parity_action() {
+            Action::Call(call) => {
+                assert_eq!((call.from, call.to), (authorized, invoker));
+                assert_eq!(call.call_type, CallType::Call);
+                assert_eq!(call.value, 5u64.into());
+            }
+            action => panic!("unexpected action {action:?}"),
+        }
+        assert!(matches!(node.parity_result(), Res::Call(_)));
+        assert_eq!(serde_json::to_value(node.geth_call_frame()).unwrap()["type"], "AUTHCALL");
+    }
+
+    #[test]
+    fn converts_gas_cost_to_wei() {
+        use node::GasPricing;
+
+        let node = CallTraceNodeBuilder::new().gas_cost(21_000).build();
+        let gwei = U256::exp10(9);
+        assert_eq!(
+            node.gas_cost_in_wei(GasPricing::Legacy { gas_price: gwei * 20 }),
+            U256::from(420_000u64) * gwei
+        );
+        assert_eq!(
+            node.gas_cost_in_wei(GasPricing::Eip1559 {
+                base_fee: gwei * 15,
+                priority_fee: gwei * 2
+            }),
+            U256::from(357_000u64) * gwei
+        );
+    }
+
+    #[test]
+    fn can_build_arena_incrementally() {
+        let call = |address| CallTrace {
+            address: Address::from_low_u64_be(address),
+            ..Default::default()
+        };
+        let log = RawLog { topics: vec![H256::repeat_byte(1)], data: vec![] };
+
+        let mut builder = ArenaBuilder::new();
+        assert_eq!(builder.push_call(call(0)), 0);
+        assert_eq!(builder.push_call(call(1)), 1);
+        builder.push_log(log.clone());
+        builder.end_call(Return::Return, 100, vec![1]);
+        builder.push_log(log.clone());
+        assert_eq!(builder.push_call(call(2)), 2);
+        assert_eq!(builder.current(), Some(2));
+        builder.end_call(Return::Revert, 50, vec![]);
+        builder.end_call(Return::Stop, 1_000, vec![]);
+        let arena = builder.finish();
+
+        let mut expected = CallTraceArena::default();
+        expected.push_trace(0, call(0));
+        expected.push_trace(0, CallTrace { depth: 1, ..call(1) });
+        expected.push_trace(0, CallTrace { depth: 1, ..call(2) });
+        assert_eq!(arena.arena.len(), 3);
+        for (node, expected) in arena.arena.iter().zip(&expected.arena) {
+            assert_eq!(
+                (node.idx, node.parent, &node.children),
+                (expected.idx, expected.parent, &expected.children)
+            );
+            assert_eq!(node.trace.depth, expected.trace.depth);
+        }
+        assert_eq!(
+            arena.arena[0].ordering,
+            vec![LogCallOrder::Call(0), LogCallOrder::Log(0), LogCallOrder::Call(1)]
+        );
+        assert_eq!(arena.arena[1].first_log_topics, vec![Some(H256::repeat_byte(1))]);
+        assert!(arena.arena[1].trace.success && !arena.arena[2].trace.success);
+        assert_eq!(arena.arena[1].trace.output, RawOrDecodedReturnData::Raw(vec![1]));
+
+        assert_eq!(ArenaBuilder::new().finish(), CallTraceArena::default());
+    }
+
+    #[test]
+    fn reads_immutables_of_created_contract() {
+        use ethers::solc::artifacts::Offsets;
+
+        let mut code = vec![0x60; 10];
+        code.extend([0xaa; 32]);
+        code.extend([0x60; 4]);
+        let node = CallTraceNodeBuilder::new().kind(CallKind::Create).output(code).build();
+        let references = BTreeMap::from([
+            ("3".to_string(), vec![Offsets { start: 10, length: 32 }]),
+            ("7".to_string(), vec![Offsets { start: 40, length: 32 }]),
+        ]);
+
+        let immutables = node.immutables(Some(&references)).unwrap();
+        assert_eq!(immutables.len(), 1);
+        assert_eq!(immutables["3"], Bytes::from(vec![0xaa; 32]));
+
+        assert_eq!(node.immutables(None), None);
+        assert_eq!(node.immutables(Some(&BTreeMap::new())), None);
+        let call = CallTraceNodeBuilder::new().output(vec![0xaa; 32]).build();
+        assert_eq!(call.immutables(Some(&references)), None);
+    }
+
+    #[test]
+    fn reconciles_gas_of_steps() {
+        let step = |gas_cost, gas_refund_counter| CallTraceStep {
+            depth: 0,
+            gas_refund_counter,
+            gas_cost,
+            ..step_of(opcode::SSTORE)
+        };
+        let mut arena = CallTraceArena::default();
+        assert_eq!(arena.reconcile_gas(21_000, 21_000), None);
+
+        arena.arena[0].trace.steps = vec![step(5_000, 0), step(2_900, 0), step(0, 4_800)];
+        let reconciliation = arena.reconcile_gas(21_000, 24_100).unwrap();
+        assert_eq!(reconciliation.execution_gas, 7_900);
+        assert_eq!(reconciliation.refund, 4_800);
+        assert!(reconciliation.is_reconciled());
+
+        // the refund is capped to a fifth of the gas used
+        arena.arena[0].trace.steps[2].gas_refund_counter = 20_000;
+        let reconciliation = arena.reconcile_gas(21_000, 24_100).unwrap();
+        assert_eq!(reconciliation.refund, 5_780);
+        assert_eq!(reconciliation.discrepancy(), -980);
+    }
+
+    #[test]
+    fn computes_self_and_cumulative_gas() {
+        let trace = |depth, gas_cost| CallTrace { depth, gas_cost, ..Default::default() };
+        let mut arena = CallTraceArena::default();
+        arena.push_trace(0, trace(0, 100_000));
+        arena.push_trace(0, CallTrace { kind: CallKind::DelegateCall, ..trace(1, 30_000) });
+        arena.push_trace(0, trace(2, 10_000));
+        arena.push_trace(0, trace(1, 5_000));
+        let nodes = &arena.arena;
+
+        assert_eq!(nodes[0].self_gas(nodes), 65_000);
+        assert_eq!(nodes[1].self_gas(nodes), 20_000);
+        assert_eq!(nodes[2].self_gas(nodes), 10_000);
+        assert_eq!(nodes[0].cumulative_gas(nodes), 100_000);
+        assert_eq!(nodes[1].cumulative_gas(nodes), 30_000);
+
+        // sub-calls that used more gas than their caller don't underflow
+        let mut arena = CallTraceArena::default();
+        arena.push_trace(0, trace(0, 1_000));
+        arena.push_trace(0, trace(1, 2_000));
+        assert_eq!(arena.arena[0].self_gas(&arena.arena), 0);
+        assert_eq!(arena.arena[0].cumulative_gas(&arena.arena), 2_000);
+    }
+
+    #[test]
+    fn finds_steps_by_opcode() {
+        let node = CallTraceNode {
+            trace: CallTrace {
+                steps: vec![
+                    CallTraceStep { pc: 0, ..step_of(revm::opcode::SSTORE) },
+                    CallTraceStep { pc: 1, ..step_of(revm::opcode::SLOAD) },
+                    CallTraceStep { pc: 2, ..step_of(revm::opcode::SSTORE) },
+                    CallTraceStep { pc: 3, eof: true, ..step_of(0xe0) },
+                ],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let pcs = |op| node.steps_with_op(op).map(|step| step.pc).collect::<Vec<_>>();
+
+        assert_eq!(pcs("SSTORE"), vec![0, 2]);
+        assert_eq!(pcs("sload"), vec![1]);
+        assert_eq!(pcs("RJUMP"), vec![3]);
+        assert!(pcs("CALL").is_empty());
+    }
+
+    #[test]
+    fn names_eof_opcodes() {
+        let step = |op, eof| CallTraceStep { depth: 0, eof, ..step_of(op) };
+        assert_eq!(step(0xe3, true).op_name(), "CALLF");
+        assert_eq!(step(0xe0, true).to_struct_log(&GethTraceOptions::default()).op, "RJUMP");
+        assert_eq!(step(opcode::SSTORE, true).op_name(), "SSTORE");
+        // legacy code is unchanged
+        assert_eq!(step(0xe3, false).op_name(), Instruction::OpCode(0xe3).to_string());
+        assert_eq!(step(opcode::SSTORE, false).op_name(), "SSTORE");
+    }
+
+    #[test]
+    fn finds_callers_of_address() {
+        let proxy = Address::from_low_u64_be(1);
+        let implementation = Address::from_low_u64_be(2);
+        let arena = CallTraceNodeBuilder::new()
+            .child(
+                CallTraceNodeBuilder::new().address(proxy).child(
+                    CallTraceNodeBuilder::new()
+                        .kind(CallKind::DelegateCall)
+                        .caller(proxy)
+                        .address(implementation),
+                ),
+            )
+            .child(CallTraceNodeBuilder::new().kind(CallKind::Create).address(implementation))
+            .child(CallTraceNodeBuilder::new().caller(proxy).address(implementation))
+            .build_arena();
+
+        let calls = arena.arena[0].callers_of(&arena.arena, implementation);
+        assert_eq!(calls, vec![2, 4]);
+        assert!(calls.iter().all(|idx| arena.arena[*idx].trace.caller == proxy));
+        assert_eq!(arena.arena[1].callers_of(&arena.arena, implementation), vec![2]);
+        assert!(arena.arena[0].callers_of(&arena.arena, Address::repeat_byte(0xff)).is_empty());
+    }
+
+    #[test]
+    fn prunes_cheap_calls() {
+        let call = |address: u64, gas_cost| {
+            CallTraceNodeBuilder::new()
+                .address(Address::from_low_u64_be(address))
+                .gas_cost(gas_cost)
+        };
+        let arena = call(0, 100_000)
+            .child(call(1, 50).child(call(2, 10_000)).child(call(3, 20)))
+            .child(call(4, 10).success(false))
+            .child(call(5, 20_000))
+            .build_arena();
+
+        let pruned = arena.prune_below_gas(1_000);
+        let addresses =
+            pruned.arena.iter().map(|node| node.trace.address.to_low_u64_be()).collect::<Vec<_>>();
+        assert_eq!(addresses, vec![0, 2, 4, 5]);
+        assert_eq!(pruned.arena[0].children, vec![1, 2, 3]);
+        assert_eq!(
+            pruned.arena[0].ordering,
+            vec![LogCallOrder::Call(0), LogCallOrder::Call(1), LogCallOrder::Call(2)]
+        );
+        assert!(pruned.arena[1..]
+            .iter()
+            .all(|node| node.parent == Some(0) && node.children.is_empty()));
+        assert!(pruned.arena.iter().enumerate().all(|(idx, node)| node.idx == idx));
+
+        assert_eq!(arena.prune_below_gas(0), arena);
+    }
+
+    #[test]
+    fn removes_cheatcode_calls() {
+        let call = |address| CallTraceNodeBuilder::new().address(address);
+        let target = Address::from_low_u64_be(1);
+        let mut arena = call(Address::zero())
+            .child(call(CHEATCODE_ADDRESS))
+            .child(call(target))
+            .child(call(CHEATCODE_ADDRESS).child(call(target)))
+            .build_arena();
+        arena.arena[1].logs.push(RawOrDecodedLog::Raw(RawLog { topics: vec![], data: vec![] }));
+        arena.arena[1].ordering.push(LogCallOrder::Log(0));
+
+        let filtered = arena.without_cheatcodes();
+        assert_eq!(filtered.arena.len(), 3);
+        assert!(filtered.arena.iter().all(|node| node.trace.address != CHEATCODE_ADDRESS));
+        assert!(filtered.arena.iter().all(|node| node.logs.is_empty()));
+        assert_eq!(filtered.arena[0].children, vec![1, 2]);
+        assert_eq!(filtered.arena[2].parent, Some(0));
+
+        assert_eq!(arena.filter_cheatcodes(true), arena);
+    }
+
+    #[test]
+    fn compares_arenas_structurally() {
+        let transfer = get_func("transfer(address,uint256)").unwrap();
+        let calldata = transfer
+            .encode_input(&[Token::Address(Address::zero()), Token::Uint(1u64.into())])
+            .unwrap();
+        let arena = CallTraceNodeBuilder::new()
+            .calldata([0; 4])
+            .child(CallTraceNodeBuilder::new().calldata(calldata.clone()))
+            .build_arena();
+
+        let mut decoded = arena.clone();
+        decoded.arena[1].retain_raw();
+        decoded.arena[1].decode_function_with(
+            &[transfer],
+            &Default::default(),
+            &Default::default(),
+            false,
+        );
+        decoded.arena[1].trace.label = Some("token".to_string());
+        assert_ne!(arena, decoded);
+        assert!(arena.eq_structural(&decoded));
+        assert!(arena.arena[1].eq_structural(&decoded.arena[1]));
+
+        let other = CallTraceNodeBuilder::new()
+            .calldata([0; 4])
+            .child(CallTraceNodeBuilder::new().calldata([calldata.as_slice(), &[0]].concat()))
+            .build_arena();
+        assert!(!arena.eq_structural(&other));
+
+        let flat = CallTraceNodeBuilder::new().calldata([0; 4]).build_arena();
+        assert!(!arena.eq_structural(&flat));
+        assert!(!arena.arena[0].eq_structural(&flat.arena[0]));
+    }
+
+    #[test]
+    fn shows_selectors_of_decoded_calls() {
+        let transfer = get_func("transfer(address,uint256)").unwrap();
+        let calldata = transfer
+            .encode_input(&[Token::Address(Address::zero()), Token::Uint(1u64.into())])
+            .unwrap();
+        let mut arena = CallTraceNodeBuilder::new()
+            .calldata([0; 4])
+            .child(CallTraceNodeBuilder::new().calldata(calldata))
+            .build_arena();
+        arena.arena[1].retain_raw();
+        arena.arena[1].decode_function_with(
+            &[transfer],
+            &Default::default(),
+            &Default::default(),
+            false,
+        );
+
+        let out = arena.display().show_selectors(true).color(false).to_string();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert!(lines[1]
+            .contains("::transfer(0x0000000000000000000000000000000000000000, 1) [0xa9059cbb]"));
+        // raw calls already show their selector
+        assert!(!lines[0].contains("[0x"));
+        assert!(!arena.display().color(false).to_string().contains("[0xa9059cbb]"));
+    }
+
+    #[test]
+    fn truncates_raw_data() {
+        let mut calldata = vec![0x12, 0x34, 0x56, 0x78];
+        calldata.extend([0xaa; 200]);
+        let arena =
+            CallTraceNodeBuilder::new().calldata(calldata).output([0xbb; 200]).build_arena();
+
+        let out = arena.display().max_raw_bytes(Some(4)).color(false).to_string();
+        assert!(out.contains("::12345678(aaaaaaaa\u{2026}(+196 bytes))"));
+        assert!(out.contains("0xbbbbbbbb\u{2026}(+196 bytes)"));
+
+        let out = arena.display().color(false).to_string();
+        assert!(out.contains(&format!("(+{} bytes)", 200 - DEFAULT_MAX_RAW_BYTES)));
+        assert_eq!(arena.display().max_raw_bytes(None).to_string(), arena.to_string());
+        assert!(!arena.to_string().contains('\u{2026}'));
+    }
+
+    #[test]
+    fn folds_repeated_calls() {
+        let token = Address::from_low_u64_be(1);
+        let call = |selector: u8| {
+            CallTraceNodeBuilder::new().address(token).calldata([selector; 4]).gas_cost(100)
+        };
+        let mut builder = CallTraceNodeBuilder::new().calldata([0; 4]);
+        for _ in 0..3 {
+            builder = builder.child(call(1));
+        }
+        let arena = builder.child(call(2)).child(call(1)).build_arena();
+
+        let folded = arena.folded(3).color(false).to_string();
+        let lines = folded.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 7);
+        assert!(
+            lines[1].ends_with(&format!("[300] {}::01010101(..) x 3", to_checksum(&token, None)))
+        );
+        assert!(lines[2].contains("::02020202("));
+        assert!(lines[4].contains("::01010101("));
+
+        // nothing is folded below the threshold
+        assert_eq!(arena.folded(4).to_string(), arena.display().to_string());
+        assert_eq!(arena.arena.len(), 6);
+    }
+
+    #[test]
+    fn only_folds_calls_with_the_same_outcome() {
+        let token = Address::from_low_u64_be(1);
+        let call = || CallTraceNodeBuilder::new().address(token).calldata([1; 4]).output([1]);
+        let arena = CallTraceNodeBuilder::new()
+            .calldata([0; 4])
+            .child(call())
+            .child(call().success(false))
+            .child(call())
+            .child(call().output([2]))
+            .child(call())
+            .child(call())
+            .build_arena();
+
+        let folded = arena.folded(2).color(false).to_string();
+        let lines = folded.lines().collect::<Vec<_>>();
+        assert!(lines[1].contains("::01010101(") && !lines[1].contains(" x "));
+        assert!(lines.iter().any(|line| line.contains("← 0x02")));
+        assert!(lines.iter().any(|line| line.ends_with("::01010101(..) x 2")));
+        assert_eq!(lines.iter().filter(|line| line.contains(" x ")).count(), 1);
+    }
+
+    #[test]
+    fn displays_annotations_under_node() {
+        let mut arena = CallTraceArena::default();
+        arena.arena[0].trace.data =
+            RawOrDecodedCall::Decoded("swap".to_string(), "swap()".to_string(), vec![]);
+        arena.push_trace(
+            0,
+            CallTrace {
+                depth: 1,
+                data: RawOrDecodedCall::Decoded(
+                    "transfer".to_string(),
+                    "transfer()".to_string(),
+                    vec![],
+                ),
+                ..Default::default()
+            },
+        );
+        arena.arena[0].annotate("price impact: 2%");
+        arena.arena[0].annotate("route: WETH -> USDC\nfee: 0.3%");
+
+        let out = arena.display().max_raw_bytes(None).color(false).to_string();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert!(lines[0].contains("swap()"));
+        assert_eq!(
+            &lines[1..4],
+            ["    │ price impact: 2%", "    │ route: WETH -> USDC", "    │ fee: 0.3%"]
+        );
+    }
+
+    #[test]
+    fn can_display_without_colors() {
+        let mut arena = CallTraceArena::default();
+        arena.arena[0].trace = CallTrace {
+            success: false,
+            data: RawOrDecodedCall::Raw(vec![0xde, 0xad, 0xbe, 0xef, 0x01]),
+            output: RawOrDecodedReturnData::Raw(vec![0x01]),
+            ..Default::default()
+        };
+
+        let plain = arena.display().color(false).to_string();
+        assert!(!plain.contains('\x1b'));
+        assert!(plain.contains("::deadbeef(01)"));
+        assert!(plain.contains("← 0x01"));
+        assert!(arena.display().to_string().contains(&Paint::new("0x01").dimmed().to_string()));
+    }
+
+    #[test]
+    fn can_verify_create2_address() {
+        // first example of EIP-1014
+        let address = "0x4D1A2e2bB4F88F0250f26Ffff098B0b30B26BF38".parse().unwrap();
+        let node = CallTraceNode {
+            trace: CallTrace { kind: CallKind::Create2, address, ..Default::default() },
+            ..Default::default()
+        };
+        assert!(node.verify_create2_address(Address::zero(), H256::zero(), &[0]));
+        assert!(!node.verify_create2_address(Address::zero(), H256::zero(), &[1]));
+    }
+
+    #[test]
+    fn can_collapse_delegate_calls() {
+        let (proxy, implementation, other) =
+            (Address::random(), Address::random(), Address::random());
+        let call = |depth, kind, address, func: &str| CallTrace {
+            depth,
+            kind,
+            address,
+            data: RawOrDecodedCall::Decoded(func.to_string(), format!("{func}()"), vec![]),
+            ..Default::default()
+        };
+
+        // proxy -> delegatecall implementation -> call other
+        let mut arena = CallTraceArena::default();
+        arena.arena[0].trace = call(0, CallKind::Call, proxy, "fallback");
+        let idx = arena.push_trace(0, call(1, CallKind::DelegateCall, implementation, "transfer"));
+        arena.push_trace(idx, call(2, CallKind::Call, other, "balanceOf"));
+
+        let collapsed = arena.collapse_delegate_calls();
+        assert_eq!(collapsed.arena.len(), 2);
+        let root = &collapsed.arena[0];
+        assert_eq!(root.trace.address, proxy);
+        assert_eq!(root.trace.data, arena.arena[1].trace.data);
+        assert_eq!(root.children, vec![1]);
+        assert_eq!(root.ordering, vec![LogCallOrder::Call(0)]);
+        assert_eq!(collapsed.arena[1].trace.address, other);
+        assert_eq!(collapsed.arena[1].parent, Some(0));
+    }
+
+    #[test]
+    fn can_compute_gas_percentages() {
+        let mut arena = CallTraceArena::default();
+        arena.arena[0].trace = CallTrace { gas_cost: 1000, ..Default::default() };
+        arena.push_trace(0, CallTrace { depth: 1, gas_cost: 250, ..Default::default() });
+        arena.push_trace(0, CallTrace { depth: 1, gas_cost: 125, ..Default::default() });
+
+        let percentages = arena.gas_percentages();
+        assert_eq!(percentages[&0], 100.0);
+        assert_eq!(percentages[&1], 25.0);
+        assert_eq!(format!("{:.2}", percentages[&2]), "12.50");
+    }
+
+    #[test]
+    fn can_decode_multicall() {
+        let (token, to) = (Address::random(), Address::random());
+        let transfer = get_func("transfer(address,uint256)").unwrap();
+        let aggregate3 = get_func("aggregate3((address,bool,bytes)[])").unwrap();
+        let call = |data: Vec<u8>| {
+            Token::Tuple(vec![Token::Address(token), Token::Bool(false), Token::Bytes(data)])
+        };
+        let calldata = aggregate3
+            .encode_input(&[Token::Array(vec![
+                call(
+                    transfer
+                        .encode_input(&[Token::Address(to), Token::Uint(100u64.into())])
+                        .unwrap(),
+                ),
+                call(vec![0xde, 0xad, 0xbe, 0xef]),
+            ])])
+            .unwrap();
+
+        let functions = [&transfer, &aggregate3]
+            .into_iter()
+            .map(|func| (func.short_signature(), vec![func.clone()]))
+            .collect::<BTreeMap<_, _>>();
+        let labels = std::collections::HashMap::from([(token, "Token".to_string())]);
+
+        let mut node = CallTraceNode {
+            trace: CallTrace { data: RawOrDecodedCall::Raw(calldata.into()), ..Default::default() },
+            ..Default::default()
+        };
+        node.decode_function_with(&[aggregate3], &labels, &Default::default(), false);
+        node.decode_multicall(&functions, &labels, false);
+
+        match node.trace.data {
+            RawOrDecodedCall::Decoded(name, _, inputs) => {
+                assert_eq!(name, "aggregate3");
+                assert_eq!(
+                    inputs,
+                    vec![format!(
+                        "[Token::transfer({}, 100), Token::0xdeadbeef]",
+                        to_checksum(&to, None)
+                    )]
+                );
+            }
+            data => panic!("unexpected calldata {data:?}"),
+        }
+    }
+
+    #[test]
+    fn can_diff_arenas() {
+        let call = |depth, signature: &str, gas_cost, output: &str| CallTrace {
+            depth,
+            data: RawOrDecodedCall::Raw(get_func(signature).unwrap().short_signature().to_vec()),
+            gas_cost,
+            output: RawOrDecodedReturnData::Decoded(output.to_string()),
+            ..Default::default()
+        };
+
+        let mut old = CallTraceArena::default();
+        old.push_trace(0, call(0, "run()", 1000, ""));
+        old.push_trace(0, call(1, "transfer(address,uint256)", 100, "true"));
+        old.push_trace(0, call(1, "approve(address,uint256)", 100, "true"));
+
+        let mut new = CallTraceArena::default();
+        new.push_trace(0, call(0, "run()", 1200, ""));
+        new.push_trace(0, call(1, "transfer(address,uint256)", 100, "false"));
+        new.push_trace(0, call(1, "mint(uint256)", 100, ""));
+        new.push_trace(0, call(1, "burn(uint256)", 100, ""));
+
+        assert!(old.diff(&old.arena).is_empty());
+        assert_eq!(
+            old.diff(&new.arena).calls,
+            vec![
+                CallDiff::Changed { old: 0, new: 0, gas_cost: Some((1000, 1200)), output: false },
+                CallDiff::Changed { old: 1, new: 1, gas_cost: None, output: true },
+                CallDiff::Removed { old: 2 },
+                CallDiff::Added { new: 2 },
+                CallDiff::Added { new: 3 },
+            ]
+        );
+    }
+
+    #[test]
+    fn diffs_raw_data_of_decoded_calls() {
+        let transfer = get_func("transfer(address,uint256)").unwrap();
+        let calldata = transfer
+            .encode_input(&[Token::Address(Address::zero()), Token::Uint(1u64.into())])
+            .unwrap();
+        let build = |output: bool| {
+            let mut arena = CallTraceArena::default();
+            arena.push_trace(
+                0,
+                CallTrace {
+                    depth: 1,
+                    data: RawOrDecodedCall::Raw(calldata.clone()),
+                    output: RawOrDecodedReturnData::Raw(ethers::abi::encode(&[Token::Bool(
+                        output,
+                    )])),
+                    ..Default::default()
+                },
+            );
+            arena
+        };
+
+        let old = build(true);
+        let mut decoded = build(true);
+        let node = &mut decoded.arena[1];
+        node.retain_raw();
+        node.trace.data = RawOrDecodedCall::Decoded(
+            "transfer".to_string(),
+            transfer.signature(),
+            vec!["zero: [0x0000000000000000000000000000000000000000]".to_string(), "1".to_string()],
+        );
+        node.trace.output = RawOrDecodedReturnData::Decoded("true".to_string());
+        assert!(old.diff(&decoded.arena).is_empty());
+        assert!(decoded.diff(&old.arena).is_empty());
+
+        assert_eq!(
+            decoded.diff(&build(false).arena).calls[1],
+            CallDiff::Changed { old: 1, new: 1, gas_cost: None, output: true }
+        );
+    }
+
+    #[test]
+    fn can_trace_prestate() {
+        let (sender, created) = (Address::random(), Address::random());
+        let state = |balance: u64, nonce, code: &[u8], storage: &[(u64, u64)]| AccountState {
+            balance: balance.into(),
+            nonce,
+            code: code.to_vec().into(),
+            storage: storage
+                .iter()
+                .map(|(slot, value)| ((*slot).into(), (*value).into()))
+                .collect(),
+        };
+
+        let mut arena = CallTraceArena::default();
+        arena.arena[0].trace = CallTrace {
+            pre_state: BTreeMap::from([(sender, Some(state(100, 1, &[], &[]))), (created, None)]),
+            post_state: BTreeMap::from([
+                (sender, Some(state(90, 2, &[], &[]))),
+                (created, Some(state(10, 1, &[0x60], &[(0, 1)]))),
+            ]),
+            ..Default::default()
+        };
+
+        let prestate = arena.geth_prestate_trace();
+        assert_eq!(
+            prestate[&sender],
+            PrestateAccount { balance: Some(100u64.into()), nonce: Some(1), ..Default::default() }
+        );
+        assert_eq!(
+            prestate[&created],
+            PrestateAccount { balance: Some(U256::zero()), ..Default::default() }
+        );
+
+        let diff = arena.geth_prestate_diff();
+        assert_eq!(diff.pre.keys().collect::<Vec<_>>(), vec![&sender]);
+        assert_eq!(
+            diff.post[&sender],
+            PrestateAccount { balance: Some(90u64.into()), nonce: Some(2), ..Default::default() }
+        );
+        assert_eq!(
+            diff.post[&created],
+            PrestateAccount {
+                balance: Some(10u64.into()),
+                nonce: Some(1),
+                code: Some(vec![0x60].into()),
+                storage: BTreeMap::from([(H256::zero(), H256::from_low_u64_be(1))]),
+            }
+        );
+    }
+
+    #[test]
+    fn can_diff_state_of_touched_accounts() {
+        let (sender, created, destroyed, unchanged) =
+            (Address::random(), Address::random(), Address::random(), Address::random());
+        let state = |balance: u64, nonce, code: &[u8], storage: &[(u64, u64)]| AccountState {
+            balance: balance.into(),
+            nonce,
+            code: code.to_vec().into(),
+            storage: storage
+                .iter()
+                .map(|(slot, value)| ((*slot).into(), (*value).into()))
+                .collect(),
+        };
+
+        let mut arena = CallTraceArena::default();
+        arena.arena[0].trace = CallTrace {
+            pre_state: BTreeMap::from([
+                (sender, Some(state(100, 1, &[], &[(1, 1)]))),
+                (created, None),
+                (destroyed, Some(state(5, 1, &[0xff], &[]))),
+                (unchanged, Some(state(1, 0, &[], &[]))),
+            ]),
+            post_state: BTreeMap::from([
+                (sender, Some(state(90, 2, &[], &[(1, 2)]))),
+                (created, Some(state(10, 1, &[0x60], &[(0, 1)]))),
+                (destroyed, None),
+                (unchanged, Some(state(1, 0, &[], &[]))),
+            ]),
+            ..Default::default()
+        };
+
+        let diff = arena.parity_state_diff().0;
+        assert_eq!(
+            diff.keys().collect::<BTreeSet<_>>(),
+            BTreeSet::from([&sender, &created, &destroyed])
+        );
+
+        let sender = &diff[&sender];
+        assert_eq!(
+            sender.balance,
+            Diff::Changed(ChangedType { from: 100u64.into(), to: 90u64.into() })
+        );
+        assert_eq!(sender.nonce, Diff::Changed(ChangedType { from: 1u64.into(), to: 2u64.into() }));
+        assert_eq!(sender.code, Diff::Same);
+        assert_eq!(
+            sender.storage,
+            BTreeMap::from([(
+                H256::from_low_u64_be(1),
+                Diff::Changed(ChangedType {
+                    from: H256::from_low_u64_be(1),
+                    to: H256::from_low_u64_be(2)
+                })
+            )])
+        );
+
+        let created = &diff[&created];
+        assert_eq!(created.balance, Diff::Born(10u64.into()));
+        assert_eq!(created.code, Diff::Born(vec![0x60].into()));
+        assert_eq!(
+            created.storage,
+            BTreeMap::from([(H256::zero(), Diff::Born(H256::from_low_u64_be(1)))])
+        );
+
+        assert_eq!(diff[&destroyed].balance, Diff::Died(5u64.into()));
+        assert_eq!(diff[&destroyed].code, Diff::Died(vec![0xff].into()));
+    }
+
+    #[test]
+    fn records_state_at_the_start_of_nested_calls() {
+        use crate::executor::inspector::Tracer;
+        use revm::{
+            db::{CacheDB, EmptyDB},
+            AccountInfo, Bytecode, Env, TransactTo,
+        };
+
+        let (sender, contract, library) = (Address::random(), Address::random(), Address::random());
+        let mut db = CacheDB::new(EmptyDB::default());
+        let mut insert_code = |address, code: Vec<u8>| {
+            let code = Bytecode::new_raw(code.into()).to_checked();
+            db.insert_account_info(address, AccountInfo { code: Some(code), ..Default::default() });
+        };
+        // stores 1 in slot 0 and delegate calls the library
+        insert_code(
+            contract,
+            [
+                [
+                    0x60, 0x01, 0x60, 0x00, 0x55, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00,
+                    0x73,
+                ]
+                .as_slice(),
+                library.as_bytes(),
+                &[0x5a, 0xf4, 0x50, 0x00],
+            ]
+            .concat(),
+        );
+        // reads slot 0 and stores it incremented by 1
+        insert_code(library, vec![0x60, 0x00, 0x54, 0x60, 0x01, 0x01, 0x60, 0x00, 0x55, 0x00]);
+
+        let mut env = Env::default();
+        env.block.gas_limit = 1_000_000u64.into();
+        env.tx.caller = sender;
+        env.tx.transact_to = TransactTo::Call(contract);
+        env.tx.gas_limit = 1_000_000;
+        let mut tracer = Tracer::default().with_state_diff_recording();
+        let (result, _) = revm::evm_inner::<_, true>(&mut env, &mut db, &mut tracer).transact();
+        assert!(matches!(result.exit_reason, revm::return_ok!()));
+
+        let arena = tracer.traces;
+        let storage = |states: &BTreeMap<Address, Option<AccountState>>| {
+            states[&contract].as_ref().unwrap().storage.clone()
+        };
+        let slot = |value: u64| BTreeMap::from([(U256::zero(), U256::from(value))]);
+        assert_eq!(storage(&arena.arena[0].trace.pre_state), slot(0));
+        assert_eq!(storage(&arena.arena[0].trace.post_state), slot(2));
+        // the library reads the value the contract stored before the call
+        assert_eq!(storage(&arena.arena[1].trace.pre_state), slot(1));
+        assert_eq!(storage(&arena.arena[1].trace.post_state), slot(2));
+
+        assert_eq!(
+            arena.parity_state_diff().0[&contract].storage,
+            BTreeMap::from([(
+                H256::zero(),
+                Diff::Changed(ChangedType { from: H256::zero(), to: H256::from_low_u64_be(2) })
+            )])
+        );
+    }
+
+    #[test]
+    fn diffs_state_of_nested_calls_with_their_outer_call() {
+        let contract = Address::random();
+        let state = |slot: Option<u64>| {
+            Some(AccountState {
+                storage: slot.map(|value| (U256::zero(), value.into())).into_iter().collect(),
+                ..Default::default()
+            })
+        };
+
+        // the outer call stores 5, the nested call 7, and the outer call resets the slot to 0
+        let mut arena = CallTraceArena::default();
+        arena.arena[0].trace.pre_state = BTreeMap::from([(contract, state(None))]);
+        arena.arena[0].trace.post_state = BTreeMap::from([(contract, state(None))]);
+        arena.push_trace(
+            0,
+            CallTrace {
+                depth: 1,
+                pre_state: BTreeMap::from([(contract, state(Some(5)))]),
+                post_state: BTreeMap::from([(contract, state(Some(7)))]),
+                ..Default::default()
+            },
+        );
+
+        assert!(arena.parity_state_diff().0.is_empty());
+    }
+
+    #[tokio::test]
+    async fn can_decode_with_signature_lookup() {
+        let transfer = get_func("transfer(address,uint256)").unwrap();
+        let calldata =
+            transfer.encode_input(&[Token::Address(Address::random()), Token::Uint(1u64.into())]);
+
+        let calldata = calldata.unwrap();
+        let mut arena = CallTraceArena::default();
+        arena.arena[0].trace.data = RawOrDecodedCall::Raw(calldata.clone());
+        let mut retained = arena.clone();
+
+        let decoder = CallTraceDecoderBuilder::new()
+            .with_signature_lookup(identifier::InMemorySignatureLookup::from_iter([
+                transfer.clone()
+            ]))
+            .build();
+        decoder.decode(&mut arena).await;
+
+        let trace = &arena.arena[0].trace;
+        assert!(matches!(&trace.data, RawOrDecodedCall::Decoded(name, _, _) if name == "transfer"));
+        assert!(trace.best_effort_signature);
+        assert_eq!(trace.raw, None);
+
+        let decoder = CallTraceDecoderBuilder::new()
+            .with_signature_lookup(identifier::InMemorySignatureLookup::from_iter([transfer]))
+            .with_raw_data(true)
+            .build();
+        decoder.decode(&mut retained).await;
+        assert_eq!(retained.arena[0].trace.raw_calldata(), Some(calldata.as_slice()));
+    }
+
+    #[test]
+    fn can_split_memory_into_words() {
+        let mut data = vec![1u8; 32];
+        data.extend([2u8; 4]);
+
+        let words = memory_words(&data).collect::<Vec<_>>();
+        let mut last = [0u8; 32];
+        last[..4].copy_from_slice(&[2u8; 4]);
+        assert_eq!(words, vec![(0, [1u8; 32]), (32, last)]);
+        assert_eq!(memory_words(&[]).count(), 0);
+    }
+
+    #[test]
+    fn can_read_stack_top() {
+        let mut stack = Stack::new();
+        for value in [1u64, 2, 3] {
+            stack.push(value.into()).unwrap();
+        }
+        let step = CallTraceStep { stack, ..step_of(revm::opcode::CALL) };
+
+        assert_eq!(step.stack_len(), 3);
+        assert_eq!(step.stack_top(0), Some(3u64.into()));
+        assert_eq!(step.stack_top(2), Some(1u64.into()));
+        assert_eq!(step.stack_top(3), None);
+    }
+
+    #[test]
+    fn can_annotate_source_locations() {
+        use crate::utils::build_pc_ic_map;
+        use revm::{opcode, SpecId};
+
+        let code = [opcode::PUSH1, 0x01, opcode::STOP];
+        let pc_ic_map = build_pc_ic_map(SpecId::LATEST, &code);
+        let source_map = ethers::solc::sourcemap::parse("0:10:0:i;12:3::o:1").unwrap();
+
+        let step = |pc| CallTraceStep { pc, ..step_of(code[pc]) };
+        let mut node = CallTraceNode::default();
+        node.trace.steps = vec![step(0), step(2)];
+        node.annotate_source_locations(&source_map, &pc_ic_map);
+
+        assert_eq!(
+            node.trace.steps[0].source_location,
+            Some(SourceLoc {
+                index: Some(0),
+                offset: 0,
+                length: 10,
+                jump: JumpType::In,
+                modifier_depth: 0
+            })
+        );
+        assert_eq!(
+            node.trace.steps[1].source_location,
+            Some(SourceLoc {
+                index: Some(0),
+                offset: 12,
+                length: 3,
+                jump: JumpType::Out,
+                modifier_depth: 1
+            })
+        );
+    }
+
+    #[test]
+    fn can_find_revert_step() {
+        let step = |pc, op| CallTraceStep { pc, ..step_of(op) };
+        let failed = |depth, output: &[u8], steps| CallTrace {
+            depth,
+            output: RawOrDecodedReturnData::Raw(output.to_vec()),
+            steps,
+            ..Default::default()
+        };
+
+        let mut arena = CallTraceArena::default();
+        arena.push_trace(
+            0,
+            failed(0, b"bubbled", vec![step(0, revm::opcode::CALL), step(1, revm::opcode::REVERT)]),
+        );
+        arena.push_trace(0, failed(1, b"bubbled", vec![step(7, revm::opcode::REVERT)]));
+        assert_eq!(arena.arena[0].revert_step(&arena.arena).map(|step| step.pc), Some(7));
+
+        // the revert data of the sub-call is not bubbled up
+        arena.arena[0].trace.output = RawOrDecodedReturnData::Raw(b"caught".to_vec());
+        assert_eq!(arena.arena[0].revert_step(&arena.arena).map(|step| step.pc), Some(1));
+
+        arena.arena[0].trace.success = true;
+        assert!(arena.arena[0].revert_step(&arena.arena).is_none());
+    }
 
     #[test]
-    fn can_convert_memory() {
-        let mut data = vec![0u8; 32];
+    fn can_summarize_token_transfers() {
+        let erc20 =
+            get_event("event Transfer(address indexed from, address indexed to, uint256 value)")
+                .unwrap();
+        let approval = get_event(
+            "event Approval(address indexed owner, address indexed spender, uint256 value)",
+        )
+        .unwrap();
+        let erc1155 = get_event(
+            "event TransferBatch(address indexed operator, address indexed from, address indexed to, uint256[] ids, uint256[] values)",
+        )
+        .unwrap();
+        let (token, proxy) = (Address::random(), Address::random());
+        let (alice, bob) = (Address::random(), Address::random());
+        let uints = |values: &[u64]| {
+            Token::Array(values.iter().map(|v| Token::Uint((*v).into())).collect())
+        };
+        let raw = |event: &ethers::abi::Event, topics: &[H256], data: &[Token]| {
+            RawOrDecodedLog::Raw(RawLog {
+                topics: std::iter::once(event.signature()).chain(topics.iter().copied()).collect(),
+                data: ethers::abi::encode(data),
+            })
+        };
+
+        let mut arena = CallTraceArena::default();
+        arena.arena[0].trace.address = token;
+        arena.arena[0].logs = vec![
+            raw(&erc20, &[alice.into(), bob.into()], &[Token::Uint(100u64.into())]),
+            raw(&approval, &[alice.into(), bob.into()], &[Token::Uint(100u64.into())]),
+        ];
+        let proxied =
+            arena.push_trace(0, CallTrace { depth: 1, address: proxy, ..Default::default() });
+        let implementation = arena.push_trace(
+            proxied,
+            CallTrace {
+                depth: 2,
+                kind: CallKind::DelegateCall,
+                address: Address::random(),
+                ..Default::default()
+            },
+        );
+        arena.arena[implementation].logs = vec![raw(
+            &erc1155,
+            &[Address::random().into(), alice.into(), bob.into()],
+            &[uints(&[1, 2]), uints(&[10, 20])],
+        )];
+        arena.arena[implementation].ordering = vec![LogCallOrder::Log(0)];
+        arena.arena[0]
+            .logs
+            .push(RawOrDecodedLog::Raw(RawLog { topics: vec![H256::random()], data: vec![] }));
+        arena.arena[0].ordering = vec![
+            LogCallOrder::Log(0),
+            LogCallOrder::Log(1),
+            LogCallOrder::Call(0),
+            LogCallOrder::Log(2),
+        ];
+        let events = [erc20, approval, erc1155];
+        for node in arena.arena.iter_mut() {
+            node.decode_logs(&events, &Default::default());
+        }
+
+        let labels = std::collections::HashMap::from([
+            (token, "Token".to_string()),
+            (alice, "alice".to_string()),
+            (bob, "bob".to_string()),
+        ]);
+        let transfer = |token: String, id: Option<&str>, amount: &str| TokenTransfer {
+            token,
+            from: format!("alice: [{}]", to_checksum(&alice, None)),
+            to: format!("bob: [{}]", to_checksum(&bob, None)),
+            id: id.map(str::to_string),
+            amount: amount.to_string(),
+        };
         assert_eq!(
-            convert_memory(&data),
-            vec!["0000000000000000000000000000000000000000000000000000000000000000".to_string()]
+            arena.token_transfers(&labels),
+            vec![
+                transfer(format!("Token: [{}]", to_checksum(&token, None)), None, "100"),
+                transfer(to_checksum(&proxy, None), Some("1"), "10"),
+                transfer(to_checksum(&proxy, None), Some("2"), "20"),
+            ]
         );
-        data.extend(data.clone());
+
+        // The decoded tokens are not serialized
+        let deserialized: CallTraceArena =
+            serde_json::from_str(&serde_json::to_string(&arena).unwrap()).unwrap();
+        assert!(deserialized.token_transfers(&labels).is_empty());
+    }
+
+    #[test]
+    fn can_export_chrome_trace() {
+        let mut arena = CallTraceArena::default();
+        arena.arena[0].trace = CallTrace {
+            label: Some("Test".to_string()),
+            data: RawOrDecodedCall::Decoded("run".to_string(), "run()".to_string(), vec![]),
+            gas_cost: 1000,
+            ..Default::default()
+        };
+        arena.push_trace(0, CallTrace { depth: 1, gas_cost: 300, ..Default::default() });
+        arena.push_trace(0, CallTrace { depth: 1, gas_cost: 200, ..Default::default() });
+
+        let trace = arena.chrome_trace();
+        let events = trace
+            .trace_events
+            .iter()
+            .map(|event| (event.name.as_str(), event.ts, event.dur))
+            .collect::<Vec<_>>();
+        let fallback = format!("{}::fallback", to_checksum(&Address::zero(), None));
         assert_eq!(
-            convert_memory(&data),
+            events,
             vec![
-                "0000000000000000000000000000000000000000000000000000000000000000".to_string(),
-                "0000000000000000000000000000000000000000000000000000000000000000".to_string()
+                ("Test::run", 0, 1000),
+                (fallback.as_str(), 0, 300),
+                (fallback.as_str(), 300, 200)
             ]
         );
+        assert_eq!(trace.trace_events[0].args["label"], "Test");
+
+        let json = serde_json::to_value(&trace).unwrap();
+        assert_eq!(json["traceEvents"][0]["ph"], "X");
+    }
+
+    #[test]
+    fn can_export_folded_stacks() {
+        let call = |depth, label: &str, gas_cost| CallTrace {
+            depth,
+            label: Some(label.to_string()),
+            data: RawOrDecodedCall::Decoded("run".to_string(), "run()".to_string(), vec![]),
+            gas_cost,
+            ..Default::default()
+        };
+
+        let mut arena = CallTraceArena::default();
+        arena.push_trace(0, call(0, "A", 1000));
+        let b = arena.push_trace(0, call(1, "B", 400));
+        arena.push_trace(b, call(2, "C", 100));
+
+        assert_eq!(
+            arena.folded_stacks(),
+            "A::run 600\nA::run;B::run 300\nA::run;B::run;C::run 100\n"
+        );
+    }
+
+    #[test]
+    fn can_detect_precompiles() {
+        let call = |address: u64| CallTrace {
+            address: Address::from_low_u64_be(address),
+            ..Default::default()
+        };
+        assert!(call(1).is_precompile());
+        assert!(call(9).is_precompile());
+        assert!(!call(0).is_precompile());
+        assert!(!call(10).is_precompile());
+        assert!(!call(0x100 + 1).is_precompile());
+    }
+
+    #[test]
+    fn labels_precompiles_by_name() {
+        let precompiles = CallTraceDecoder::new().precompiles;
+        let decode = |address: u64| {
+            let address = Address::from_low_u64_be(address);
+            let mut node = CallTraceNode {
+                trace: CallTrace {
+                    address,
+                    data: RawOrDecodedCall::Raw(vec![1, 2, 3]),
+                    success: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            node.decode_precompile(&precompiles[&address], &Default::default());
+            let name = match &node.trace.data {
+                RawOrDecodedCall::Decoded(name, _, _) => name.clone(),
+                data => panic!("not decoded: {data:?}"),
+            };
+            (node.trace.label, name)
+        };
+
+        assert_eq!(decode(1), (Some("ecrecover".to_string()), "ecrecover".to_string()));
+        assert_eq!(decode(2), (Some("sha256".to_string()), "sha256".to_string()));
+        assert_eq!(decode(4), (Some("identity".to_string()), "identity".to_string()));
+    }
+
+    #[test]
+    fn dispatches_decoding_by_destination() {
+        let warp = get_func("warp(uint256)").unwrap();
+        let calldata = warp.encode_input(&[Token::Uint(1u64.into())]).unwrap();
+        let call = |address| CallTraceNode {
+            trace: CallTrace {
+                address,
+                data: RawOrDecodedCall::Raw(calldata.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let decode = |node: &mut CallTraceNode| {
+            let labels = Default::default();
+            node.decode(&Default::default(), &BTreeMap::new(), &labels, &Default::default(), false)
+                .is_some()
+        };
+
+        // cheatcodes are decoded without being in the known functions
+        let mut cheatcode = call(CHEATCODE_ADDRESS);
+        assert!(decode(&mut cheatcode));
+        assert!(
+            matches!(cheatcode.trace.data, RawOrDecodedCall::Decoded(name, _, _) if name == "warp")
+        );
+
+        // unknown cheatcodes stay raw
+        let mut unknown = call(CHEATCODE_ADDRESS);
+        unknown.trace.data = RawOrDecodedCall::Raw(vec![0xff; 4]);
+        assert!(!decode(&mut unknown));
+
+        // the input of a precompile is not a function call
+        let mut precompile = call(Address::from_low_u64_be(4));
+        assert!(!decode(&mut precompile));
+        assert_eq!(precompile.trace.data, RawOrDecodedCall::Raw(calldata.clone()));
+    }
+
+    #[test]
+    fn can_decode_typed_data_in_cheatcode_inputs() {
+        let write_file = crate::abi::HEVM_ABI.function("writeFile").unwrap();
+        let input = |contents: &str| {
+            write_file
+                .encode_input(&[
+                    Token::String("mail.json".to_string()),
+                    Token::String(contents.to_string()),
+                ])
+                .unwrap()
+        };
+        let typed_data = r#"{"types":{"EIP712Domain":[{"name":"name","type":"string"},{"name":"chainId","type":"uint256"}],"Person":[{"name":"name","type":"string"}],"Mail":[{"name":"to","type":"Person"},{"name":"contents","type":"string"}]},"primaryType":"Mail","domain":{"chainId":1,"name":"Ether Mail"},"message":{"contents":"Hello, Bob!","to":{"name":"Bob"}}}"#;
+
+        assert_eq!(
+            utils::decode_cheatcode_inputs(write_file, &input(typed_data), &Default::default()),
+            Some(vec![
+                "mail.json".to_string(),
+                "EIP712Domain(chainId: 1, name: Ether Mail) Mail(contents: Hello, Bob!, to: (name: Bob))"
+                    .to_string()
+            ])
+        );
+        // other JSON falls back to the generic decoding
+        assert_eq!(
+            utils::decode_cheatcode_inputs(
+                write_file,
+                &input(r#"{"message":{}}"#),
+                &Default::default()
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn keeps_undecodable_cheatcode_calls_raw() {
+        let warp = crate::abi::HEVM_ABI.function("warp").unwrap();
+        let decode = |calldata: &[u8]| {
+            let mut node = CallTraceNode {
+                trace: CallTrace {
+                    address: CHEATCODE_ADDRESS,
+                    data: RawOrDecodedCall::Raw(calldata.to_vec()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let decoded = node.decode(
+                &Default::default(),
+                &BTreeMap::new(),
+                &Default::default(),
+                &Default::default(),
+                false,
+            );
+            (decoded.is_some(), node.trace.data)
+        };
+
+        let calldata = warp.encode_input(&[Token::Uint(1u64.into())]).unwrap();
+        assert_eq!(
+            decode(&calldata),
+            (
+                true,
+                RawOrDecodedCall::Decoded(
+                    "warp".to_string(),
+                    "warp(uint256)".to_string(),
+                    vec!["1".to_string()]
+                )
+            )
+        );
+
+        let malformed = [&calldata[..SELECTOR_LEN], &[0xff]].concat();
+        assert_eq!(decode(&malformed), (false, RawOrDecodedCall::Raw(malformed)));
+    }
+
+    #[test]
+    fn keeps_decoded_inputs_except_for_secrets() {
+        let transfer = get_func("transfer(address,uint256)").unwrap();
+        let tokens = vec![Token::Address(Address::from_low_u64_be(1)), Token::Uint(1u64.into())];
+        let mut node = CallTraceNode {
+            trace: CallTrace {
+                data: RawOrDecodedCall::Raw(transfer.encode_input(&tokens).unwrap()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        node.retain_raw();
+        node.decode_function(&[transfer], &Default::default(), &Default::default());
+        assert_eq!(node.trace.decoded_inputs, Some(tokens));
+
+        // the tokens are not serialized
+        let json = serde_json::to_string(&node).unwrap();
+        assert_eq!(
+            serde_json::from_str::<CallTraceNode>(&json).unwrap().trace.decoded_inputs,
+            None
+        );
+
+        node.restore_raw();
+        assert_eq!(node.trace.decoded_inputs, None);
+
+        // the private key passed to the cheatcode is redacted, and its token is not kept
+        let addr = crate::abi::HEVM_ABI.function("addr").unwrap();
+        let mut node = CallTraceNode {
+            trace: CallTrace {
+                address: CHEATCODE_ADDRESS,
+                data: RawOrDecodedCall::Raw(
+                    addr.encode_input(&[Token::Uint(1u64.into())]).unwrap(),
+                ),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        node.decode_function(&[addr.clone()], &Default::default(), &Default::default());
+        assert!(
+            matches!(&node.trace.data, RawOrDecodedCall::Decoded(_, _, inputs) if inputs == &["<pk>"])
+        );
+        assert_eq!(node.trace.decoded_inputs, None);
+    }
+
+    #[test]
+    fn can_decode_cheatcode_outputs() {
+        let ffi = crate::abi::HEVM_ABI.function("ffi").unwrap();
+        let output = |bytes: &[u8]| ethers::abi::encode(&[Token::Bytes(bytes.to_vec())]);
+
+        assert_eq!(
+            utils::decode_cheatcode_outputs(ffi, &output(b"hello\nworld")),
+            Some(r#""hello\nworld""#.to_string())
+        );
+        assert_eq!(utils::decode_cheatcode_outputs(ffi, &output(&[0x00, 0xff])), None);
+    }
+
+    #[test]
+    fn labels_nested_addresses() {
+        let alice = Address::from_low_u64_be(1);
+        let bob = Address::from_low_u64_be(2);
+        let labels = std::collections::HashMap::from([(alice, "alice".to_string())]);
+
+        let token = Token::Tuple(vec![
+            Token::Array(vec![Token::Address(alice), Token::Address(bob)]),
+            Token::FixedArray(vec![Token::Tuple(vec![Token::Address(alice), Token::Bool(true)])]),
+        ]);
+        assert_eq!(
+            utils::label(&token, &labels),
+            format!(
+                "([alice: [{alice}], {bob}], [(alice: [{alice}], true)])",
+                alice = to_checksum(&alice, None),
+                bob = to_checksum(&bob, None)
+            )
+        );
+
+        let address =
+            Address::from_slice(&hex::decode("5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").unwrap());
+        assert_eq!(
+            utils::label(&Token::Address(address), &labels),
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"
+        );
+        assert_eq!(
+            utils::label_with(&Token::Array(vec![Token::Address(address), Token::Address(alice)]), &labels, false),
+            "[0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed, alice: [0x0000000000000000000000000000000000000001]]"
+        );
+    }
+
+    #[test]
+    fn can_label_eip1967_proxies() {
+        let implementation = Address::random();
+        let slot = U256::from(keccak256("eip1967.proxy.implementation")) - 1;
+        let step = |op, stack_top: U256| {
+            let mut stack = Stack::new();
+            stack.push(stack_top).unwrap();
+            CallTraceStep { stack, ..step_of(op) }
+        };
+
+        let mut arena = CallTraceArena::default();
+        arena.arena[0].trace.label = Some("Proxy".to_string());
+        arena.arena[0].trace.steps = vec![
+            step(opcode::SLOAD, slot),
+            step(opcode::GAS, U256::from_big_endian(implementation.as_bytes())),
+            step(opcode::DELEGATECALL, U256::zero()),
+        ];
+        arena.push_trace(
+            0,
+            CallTrace {
+                depth: 1,
+                kind: CallKind::DelegateCall,
+                address: implementation,
+                parent_step: Some(2),
+                ..Default::default()
+            },
+        );
+
+        arena.label_eip1967_proxies();
+        assert_eq!(
+            arena.arena[1].trace.label,
+            Some(format!("Proxy -> {}", to_checksum(&implementation, None)))
+        );
+    }
+
+    #[test]
+    fn reparents_calls_at_the_step_of_pruned_callers() {
+        let implementation = Address::random();
+        let slot = U256::from(keccak256("eip1967.proxy.implementation")) - 1;
+        let step = |pc, op, depth, stack_top: U256| {
+            let mut stack = Stack::new();
+            stack.push(stack_top).unwrap();
+            CallTraceStep { pc, depth, stack, ..step_of(op) }
+        };
+
+        let mut arena = CallTraceNodeBuilder::new()
+            .child(
+                CallTraceNodeBuilder::new().gas_cost(50).child(
+                    CallTraceNodeBuilder::new()
+                        .kind(CallKind::DelegateCall)
+                        .address(implementation)
+                        .gas_cost(10_000),
+                ),
+            )
+            .build_arena();
+        arena.arena[0].trace.label = Some("Proxy".to_string());
+        arena.arena[0].trace.steps = vec![
+            step(0, opcode::SLOAD, 1, slot),
+            step(1, opcode::GAS, 1, U256::from_big_endian(implementation.as_bytes())),
+            step(2, opcode::CALL, 1, U256::zero()),
+            step(3, opcode::STOP, 1, U256::zero()),
+        ];
+        // the removed call makes the delegate call at a step beyond the steps of the proxy
+        arena.arena[1].trace.parent_step = Some(2);
+        arena.arena[1].trace.steps =
+            (0..8).map(|pc| step(pc, opcode::JUMPDEST, 2, U256::zero())).collect();
+        arena.arena[2].trace.parent_step = Some(7);
+        arena.arena[2].trace.steps = vec![step(0, opcode::ADD, 3, U256::zero())];
+
+        let mut pruned = arena.prune_below_gas(1_000);
+        assert_eq!(pruned.arena[1].trace.parent_step, Some(2));
+
+        let trace = pruned.geth_trace_with(U256::zero(), GethTraceOptions::default());
+        assert_eq!(
+            trace.struct_logs.iter().map(|log| log.op.as_str()).collect::<Vec<_>>(),
+            ["SLOAD", "GAS", "CALL", "ADD", "STOP"]
+        );
+
+        pruned.label_eip1967_proxies();
+        assert_eq!(
+            pruned.arena[1].trace.label,
+            Some(format!("Proxy -> {}", to_checksum(&implementation, None)))
+        );
+    }
+
+    #[test]
+    fn can_find_logs_by_topic0() {
+        let (transfer, approval) = (H256::random(), H256::random());
+        let raw = |topic0| RawOrDecodedLog::Raw(RawLog { topics: vec![topic0], data: vec![] });
+
+        let mut arena = CallTraceArena::default();
+        arena.arena[0].logs = vec![raw(transfer), raw(approval)];
+        let child = arena.push_trace(0, CallTrace { depth: 1, ..Default::default() });
+        arena.arena[child].logs = vec![RawOrDecodedLog::Decoded("Transfer".to_string(), vec![])];
+        arena.arena[child].first_log_topics = vec![Some(transfer)];
+
+        assert_eq!(
+            arena.find_logs_by_topic0(transfer),
+            vec![(0, &arena.arena[0].logs[0]), (1, &arena.arena[1].logs[0])]
+        );
+        assert!(arena.find_logs_by_topic0(H256::random()).is_empty());
+    }
+
+    #[test]
+    fn derives_parity_actions_of_delegatecall_chain() {
+        use ethers::types::Action;
+
+        let [sender, proxy, implementation, library, token] =
+            [1u64, 2, 3, 4, 5].map(Address::from_low_u64_be);
+        // A proxy delegating to an implementation that delegates to a library, which reads a
+        // token balance, as recorded by the tracer
+        let mut arena = CallTraceArena::default();
+        arena.arena[0].trace =
+            CallTrace { caller: sender, address: proxy, gas_limit: 100_000, ..Default::default() };
+        for (depth, kind, caller, address, gas_limit) in [
+            (1, CallKind::DelegateCall, proxy, implementation, 90_000),
+            (2, CallKind::DelegateCall, proxy, library, 80_000),
+            (3, CallKind::StaticCall, proxy, token, 70_000),
+        ] {
+            arena.push_trace(
+                0,
+                CallTrace {
+                    depth,
+                    kind,
+                    caller,
+                    address,
+                    gas_limit,
+                    gas_cost: 1,
+                    ..Default::default()
+                },
+            );
+        }
+
+        // as returned by OpenEthereum and Erigon for such a chain
+        let expected: Vec<Action> = serde_json::from_value(serde_json::json!([
+            {
+                "callType": "call",
+                "from": "0x0000000000000000000000000000000000000001",
+                "gas": "0x186a0",
+                "input": "0x",
+                "to": "0x0000000000000000000000000000000000000002",
+                "value": "0x0"
+            },
+            {
+                "callType": "delegatecall",
+                "from": "0x0000000000000000000000000000000000000002",
+                "gas": "0x15f90",
+                "input": "0x",
+                "to": "0x0000000000000000000000000000000000000003",
+                "value": "0x0"
+            },
+            {
+                "callType": "delegatecall",
+                "from": "0x0000000000000000000000000000000000000002",
+                "gas": "0x13880",
+                "input": "0x",
+                "to": "0x0000000000000000000000000000000000000004",
+                "value": "0x0"
+            },
+            {
+                "callType": "staticcall",
+                "from": "0x0000000000000000000000000000000000000002",
+                "gas": "0x11170",
+                "input": "0x",
+                "to": "0x0000000000000000000000000000000000000005",
+                "value": "0x0"
+            }
+        ]))
+        .unwrap();
+        assert_eq!(
+            arena.arena.iter().map(|node| node.parity_action()).collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    #[test]
+    fn trace_hash_is_stable_across_decoding() {
+        let transfer = get_func("transfer(address,uint256)").unwrap();
+        let calldata = transfer
+            .encode_input(&[Token::Address(Address::zero()), Token::Uint(1u64.into())])
+            .unwrap();
+        let build = |output: Vec<u8>| {
+            CallTraceNodeBuilder::new()
+                .child(CallTraceNodeBuilder::new().calldata(calldata.clone()).output(output))
+                .child(CallTraceNodeBuilder::new().kind(CallKind::StaticCall))
+                .build_arena()
+        };
+
+        let mut arena = build(ethers::abi::encode(&[Token::Bool(true)]));
+        let hash = arena.trace_hash();
+        assert_eq!(build(ethers::abi::encode(&[Token::Bool(true)])).trace_hash(), hash);
+
+        arena.arena[1].retain_raw();
+        arena.arena[1].decode_function_with(
+            &[transfer.clone()],
+            &Default::default(),
+            &Default::default(),
+            false,
+        );
+        arena.arena[1].trace.label = Some("Token".to_string());
+        assert!(matches!(arena.arena[1].trace.data, RawOrDecodedCall::Decoded(..)));
+        assert_eq!(arena.trace_hash(), hash);
+
+        assert_ne!(build(ethers::abi::encode(&[Token::Bool(false)])).trace_hash(), hash);
+
+        // calls decoded without their raw data are hashed by their decoded data
+        let mut unretained = build(ethers::abi::encode(&[Token::Bool(true)]));
+        unretained.arena[1].decode_function_with(
+            &[transfer],
+            &Default::default(),
+            &Default::default(),
+            false,
+        );
+        assert_eq!(unretained.arena[1].trace.raw, None);
+        assert_ne!(unretained.trace_hash(), hash);
+        let mut relabeled = unretained.clone();
+        relabeled.arena[1].trace.label = Some("Token".to_string());
+        assert_eq!(relabeled.trace_hash(), unretained.trace_hash());
+
+        // the order of the sub-calls matters
+        arena.arena[0].children.reverse();
+        assert_ne!(arena.trace_hash(), hash);
+    }
+
+    #[test]
+    fn warns_about_selector_collisions() {
+        // both have the selector 0x42966c68
+        let burn = get_func("burn(uint256)").unwrap();
+        let collision = get_func("collate_propagate_storage(bytes16)").unwrap();
+        let calldata = burn.encode_input(&[Token::Uint(1u64.into())]).unwrap();
+
+        let mut node = CallTraceNodeBuilder::new().calldata(calldata.clone()).build();
+        let warnings = node.decode_function_with(
+            &[burn.clone(), collision.clone()],
+            &Default::default(),
+            &Default::default(),
+            false,
+        );
+        assert_eq!(
+            warnings,
+            vec![node::DecodeWarning::SelectorCollision {
+                selector: burn.short_signature(),
+                signatures: vec![
+                    "burn(uint256)".to_string(),
+                    "collate_propagate_storage(bytes16)".to_string()
+                ],
+                used: "burn(uint256)".to_string(),
+            }]
+        );
+        assert!(
+            matches!(&node.trace.data, RawOrDecodedCall::Decoded(name, _, _) if name == "burn")
+        );
+
+        // the same function twice is not a collision
+        let mut node = CallTraceNodeBuilder::new().calldata(calldata).build();
+        assert!(node
+            .decode_function_with(
+                &[burn.clone(), burn],
+                &Default::default(),
+                &Default::default(),
+                false
+            )
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn collects_decode_warnings_of_all_calls() {
+        // both have the selector 0x42966c68
+        let burn = get_func("burn(uint256)").unwrap();
+        let collision = get_func("collate_propagate_storage(bytes16)").unwrap();
+        let calldata = burn.encode_input(&[Token::Uint(1u64.into())]).unwrap();
+        let mut arena = CallTraceNodeBuilder::new()
+            .calldata([0; 4])
+            .child(CallTraceNodeBuilder::new().calldata(calldata))
+            .build_arena();
+
+        let mut decoder = CallTraceDecoder::new();
+        decoder.functions.insert(burn.short_signature(), vec![burn, collision]);
+        let warnings = decoder.decode(&mut arena).await;
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].0, 1);
+        assert!(matches!(
+            &warnings[0].1,
+            node::DecodeWarning::SelectorCollision { used, .. } if used == "burn(uint256)"
+        ));
+    }
+
+    #[test]
+    fn decodes_with_first_function_that_matches() {
+        let burn = get_func("burn(uint256)").unwrap();
+        // can't decode a single word, since the offset of its bytes is out of bounds
+        let burn_bytes = get_func("burn(bytes)").unwrap();
+        let calldata = burn.encode_input(&[Token::Uint(1u64.into())]).unwrap();
+        let node_of = |data| CallTraceNode {
+            trace: CallTrace { data: RawOrDecodedCall::Raw(data), ..Default::default() },
+            ..Default::default()
+        };
+
+        let mut node = node_of(calldata.clone());
+        node.decode_function(&[burn_bytes, burn.clone()], &Default::default(), &Default::default());
+        assert_eq!(
+            node.trace.data,
+            RawOrDecodedCall::Decoded(
+                "burn".to_string(),
+                "burn(uint256)".to_string(),
+                vec!["1".to_string()]
+            )
+        );
+
+        // none of the functions can decode the input
+        let malformed = [&calldata[..SELECTOR_LEN], &[0xff]].concat();
+        let mut node = node_of(malformed.clone());
+        node.decode_function(&[burn], &Default::default(), &Default::default());
+        assert_eq!(node.trace.data, RawOrDecodedCall::Raw(malformed));
+    }
+
+    #[test]
+    fn can_snapshot_function_gas() {
+        let token = Address::from_low_u64_be(1);
+        let transfer = get_func("transfer(address,uint256)").unwrap();
+        let call = |gas_cost| {
+            CallTraceNodeBuilder::new()
+                .address(token)
+                .calldata(
+                    transfer
+                        .encode_input(&[Token::Address(Address::zero()), Token::Uint(1u64.into())])
+                        .unwrap(),
+                )
+                .gas_cost(gas_cost)
+        };
+        let mut arena = CallTraceNodeBuilder::new()
+            .gas_cost(100_000)
+            .child(call(30_000))
+            .child(call(10_000))
+            .child(call(5_000))
+            .child(CallTraceNodeBuilder::new().address(token).gas_cost(50))
+            .build_arena();
+        for node in &mut arena.arena[1..3] {
+            node.decode_function_with(
+                &[transfer.clone()],
+                &Default::default(),
+                &Default::default(),
+                false,
+            );
+        }
+
+        let snapshot = arena.gas_snapshot();
+        assert_eq!(
+            snapshot[&token]["transfer(address,uint256)"],
+            FunctionGas { calls: 2, total: 40_000, min: 10_000, max: 30_000 }
+        );
+        assert_eq!(snapshot[&token]["transfer(address,uint256)"].mean(), 20_000);
+        assert_eq!(snapshot[&token]["0xa9059cbb"].total, 5_000);
+        assert_eq!(snapshot[&token]["fallback"].calls, 1);
+        assert_eq!(snapshot[&Address::zero()]["fallback"].total, 100_000);
+    }
+
+    #[test]
+    fn can_build_nested_calls() {
+        let [outer, inner, other] = [1u64, 2, 3].map(Address::from_low_u64_be);
+        let arena = CallTraceNodeBuilder::new()
+            .address(outer)
+            .child(CallTraceNodeBuilder::new().address(inner).child(CallTraceNodeBuilder::new()))
+            .child(CallTraceNodeBuilder::new().address(other).success(false).output([1, 2]))
+            .build_arena();
+
+        assert_eq!(arena.arena.len(), 4);
+        assert_eq!(arena.arena[0].children, vec![1, 3]);
+        assert_eq!(arena.arena[1].children, vec![2]);
+        assert_eq!(arena.arena[2].trace.depth, 2);
+        assert_eq!((arena.arena[3].trace.address, arena.arena[3].trace.depth), (other, 1));
+        assert_eq!(arena.arena[3].status(), Return::Revert);
+        assert_eq!(arena.arena[3].trace.output, RawOrDecodedReturnData::Raw(vec![1, 2]));
+        assert_eq!(arena.arena[0].trace.address, outer);
+        assert_eq!(arena.arena[1].trace.address, inner);
+    }
+
+    #[test]
+    fn can_summarize_node() {
+        use node::TraceSummary;
+
+        let mut arena = CallTraceNodeBuilder::new()
+            .gas_cost(21_000)
+            .child(
+                CallTraceNodeBuilder::new()
+                    .kind(CallKind::Create)
+                    .calldata([0x60, 0x80, 0x60, 0x40])
+                    .success(false),
+            )
+            .build_arena();
+        arena.arena[0].trace.data = RawOrDecodedCall::Decoded(
+            "transfer".to_string(),
+            "transfer(address,uint256)".to_string(),
+            vec![],
+        );
+
+        assert_eq!(
+            arena.arena[0].summary(),
+            TraceSummary {
+                address: Address::zero(),
+                kind: CallKind::Call,
+                success: true,
+                gas_cost: 21_000,
+                selector: Some([0xa9, 0x05, 0x9c, 0xbb]),
+                decoded_name: Some("transfer".to_string()),
+            }
+        );
+        let summary = arena.arena[1].summary();
+        assert_eq!(
+            (summary.kind, summary.selector, summary.decoded_name),
+            (CallKind::Create, None, None)
+        );
+    }
+
+    #[test]
+    fn can_build_access_list() {
+        use ethers::types::transaction::eip2930::AccessListItem;
+
+        let step = |contract, op, stack: &[U256]| {
+            let mut step_stack = Stack::new();
+            for value in stack {
+                step_stack.push(*value).unwrap();
+            }
+            CallTraceStep { contract, stack: step_stack, ..step_of(op) }
+        };
+        let word = |address: Address| U256::from_big_endian(address.as_bytes());
+        let (caller, callee, other) = (Address::random(), Address::random(), Address::random());
+
+        let mut arena = CallTraceArena::default();
+        arena.arena[0].trace.steps = vec![
+            step(caller, opcode::SLOAD, &[U256::one()]),
+            step(caller, opcode::SSTORE, &[U256::from(2u64), U256::one()]),
+            step(caller, opcode::BALANCE, &[word(other)]),
+            step(caller, opcode::CALL, &[word(callee), U256::zero()]),
+        ];
+        let child = arena.push_trace(0, CallTrace { depth: 1, ..Default::default() });
+        arena.arena[child].trace.steps = vec![step(callee, opcode::SLOAD, &[U256::one()])];
+
+        let access_list = arena.arena[0].access_list(&arena.arena);
+        let mut expected = vec![
+            AccessListItem { address: caller, storage_keys: vec![H256::from_low_u64_be(1)] },
+            AccessListItem { address: callee, storage_keys: vec![H256::from_low_u64_be(1)] },
+            AccessListItem { address: other, storage_keys: vec![] },
+        ];
+        expected.sort_by_key(|item| item.address);
+        assert_eq!(access_list.0, expected);
+    }
+
+    #[test]
+    fn can_build_vm_trace() {
+        let step = |pc, op, stack: &[u64], gas| {
+            let mut step_stack = Stack::new();
+            for value in stack {
+                step_stack.push((*value).into()).unwrap();
+            }
+            CallTraceStep { pc, stack: step_stack, gas, gas_cost: 3, ..step_of(op) }
+        };
+
+        let mut arena = CallTraceArena::default();
+        arena.arena[0].trace.steps = vec![
+            step(0, opcode::PUSH1, &[], 100),
+            step(2, opcode::CALL, &[1], 97),
+            step(3, opcode::STOP, &[1], 50),
+        ];
+        arena.push_trace(0, CallTrace { depth: 1, parent_step: Some(1), ..Default::default() });
+
+        let trace = arena.vm_trace();
+        assert_eq!(trace.ops.len(), 3);
+        let ex = trace.ops[0].ex.as_ref().unwrap();
+        assert_eq!((ex.used, ex.push.clone()), (97, vec![U256::one()]));
+        assert!(trace.ops[0].sub.is_none());
+        assert!(trace.ops[1].sub.as_ref().unwrap().ops.is_empty());
+        assert!(trace.ops[2].ex.as_ref().unwrap().push.is_empty());
+    }
+
+    #[test]
+    fn can_diff_memory() {
+        assert!(memory_diff(&[1, 2, 3], &[1, 2, 3]).is_none());
+        let diff = memory_diff(&[1, 2, 3], &[1, 5, 6, 0]).unwrap();
+        assert_eq!((diff.off, diff.data), (1, vec![5, 6, 0].into()));
+    }
+
+    #[test]
+    fn can_compute_precompile_gas_costs() {
+        let cost = |precompile, input: &[u8]| {
+            utils::precompile_gas_cost(&Address::from_low_u64_be(precompile), input)
+        };
+        let word = |value: u64| H256::from_low_u64_be(value).as_bytes().to_vec();
+
+        assert_eq!(cost(1, &[0; 128]), Some(3000));
+        assert_eq!(cost(2, &[0; 33]), Some(60 + 12 * 2));
+        assert_eq!(cost(3, &[0; 64]), Some(600 + 120 * 2));
+        assert_eq!(cost(4, &[]), Some(15));
+        assert_eq!(cost(6, &[0; 128]), Some(150));
+        assert_eq!(cost(7, &[0; 96]), Some(6000));
+        assert_eq!(cost(8, &[0; 384]), Some(45_000 + 2 * 34_000));
+        assert_eq!(cost(9, &12u32.to_be_bytes()), Some(12));
+        assert_eq!(cost(10, &[]), None);
+        assert_eq!(utils::precompile_gas_cost(&Address::random(), &[]), None);
+
+        // 32 byte base and exponent with a 64 byte modulus: 8 words squared times 15 iterations
+        let modexp = [word(32), word(32), word(64), word(3), word(0xffff)].concat();
+        assert_eq!(cost(5, &modexp), Some(64 * 15 / 3));
+        // a 64 byte exponent adds 8 iterations per byte after the first 32 bytes
+        let modexp = [word(1), word(64), word(64), vec![3], word(1)].concat();
+        assert_eq!(cost(5, &modexp), Some(64 * 256 / 3));
+        // the cost is at least 200
+        assert_eq!(cost(5, &[word(1), word(1), word(1)].concat()), Some(200));
+    }
+
+    #[test]
+    fn can_decode_revert_data() {
+        let quote = get_func("function quote() returns (uint256, address)").unwrap();
+        let decode = |err: &[u8]| {
+            utils::decode_revert(
+                err,
+                &[quote.clone()],
+                &Default::default(),
+                &Default::default(),
+                false,
+                None,
+            )
+        };
+
+        let reason = [
+            [8, 195, 121, 160].as_slice(),
+            &ethers::abi::encode(&[Token::String("not owner".to_string())]),
+        ]
+        .concat();
+        assert_eq!(decode(&reason), Some(r#""not owner""#.to_string()));
+
+        // only data with the selector of a reason is decoded as one
+        let cheatcode_reason = [
+            crate::error::ERROR_PREFIX.as_slice(),
+            &ethers::abi::encode(&[Token::String("not owner".to_string())]),
+        ]
+        .concat();
+        assert_eq!(decode(&cheatcode_reason), Some(r#""not owner""#.to_string()));
+        let unprefixed = ethers::abi::encode(&[Token::String("not owner".to_string())]);
+        assert_ne!(
+            utils::decode_revert(
+                &unprefixed,
+                &[],
+                &Default::default(),
+                &Default::default(),
+                false,
+                None
+            ),
+            Some(r#""not owner""#.to_string())
+        );
+
+        // plain return data is decoded with the return types of the function
+        let data =
+            ethers::abi::encode(&[Token::Uint(1u64.into()), Token::Address(Address::zero())]);
+        assert_eq!(decode(&data), Some(format!("1, {}", to_checksum(&Address::zero(), None))));
+
+        // panics are not quoted
+        let panic =
+            [[78, 72, 123, 113].as_slice(), &ethers::abi::encode(&[Token::Uint(1u64.into())])]
+                .concat();
+        assert_eq!(decode(&panic), Some("assertion failed (0x1)".to_string()));
+
+        // reverts without data are told apart from empty reasons
+        assert_eq!(decode(&[]), Some("<empty revert data>".to_string()));
+        let empty_reason =
+            [[8, 195, 121, 160].as_slice(), &ethers::abi::encode(&[Token::String(String::new())])]
+                .concat();
+        assert_eq!(decode(&empty_reason), Some(r#""""#.to_string()));
+    }
+
+    #[test]
+    fn labels_custom_error_params() {
+        let abi: ethers::abi::Abi = serde_json::from_str(
+            r#"[{"type":"error","name":"Unauthorized","inputs":[{"name":"caller","type":"address"},{"name":"","type":"uint256"}]}]"#,
+        )
+        .unwrap();
+        let error = abi.error("Unauthorized").unwrap();
+        let alice = Address::from_low_u64_be(1);
+        let err = [
+            &error.signature()[..SELECTOR_LEN],
+            &ethers::abi::encode(&[Token::Address(alice), Token::Uint(1u64.into())]),
+        ]
+        .concat();
+
+        let labels = std::collections::HashMap::from([(alice, "alice".to_string())]);
+        let errors = crate::decode::errors_by_selector(&abi);
+        assert_eq!(
+            utils::decode_revert(&err, &[], &errors, &labels, false, None),
+            Some(format!("Unauthorized(caller: alice: [{}], 1)", to_checksum(&alice, None)))
+        );
+    }
+
+    #[test]
+    fn formats_amounts_in_ether() {
+        let transfer = get_func("function transfer(uint256 id, uint256 amount)").unwrap();
+        let calldata = transfer
+            .encode_input(&[Token::Uint(U256::exp10(18)), Token::Uint(U256::exp10(17) * 15u64)])
+            .unwrap();
+        let decode = |format_amounts| {
+            let mut node = CallTraceNode {
+                trace: CallTrace {
+                    data: RawOrDecodedCall::Raw(calldata.clone()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            node.decode_function_with(
+                &[transfer.clone()],
+                &Default::default(),
+                &Default::default(),
+                format_amounts,
+            );
+            match node.trace.data {
+                RawOrDecodedCall::Decoded(_, _, inputs) => inputs,
+                data => panic!("not decoded: {data:?}"),
+            }
+        };
+
+        // only params named like amounts are formatted
+        assert_eq!(decode(true), vec!["1000000000000000000", "1.5 ether"]);
+        assert_eq!(decode(false), vec!["1000000000000000000", "1500000000000000000"]);
+
+        let amount = ethers::abi::Param {
+            name: "wad".to_string(),
+            kind: ethers::abi::ParamType::Uint(256),
+            internal_type: None,
+        };
+        let label = |amount_wei: U256| {
+            utils::label_param(&Token::Uint(amount_wei), &amount, &Default::default(), true)
+        };
+        assert_eq!(label(U256::exp10(18) * 2u64), "2 ether");
+        assert_eq!(label(U256::exp10(9)), "1 gwei");
+        assert_eq!(label(U256::zero()), "0 gwei");
+    }
+
+    #[test]
+    fn can_decode_restored_call_again() {
+        let transfer = get_func("transfer(address,uint256)").unwrap();
+        let mut renamed = transfer.clone();
+        renamed.name = "send".to_string();
+        let calldata = transfer
+            .encode_input(&[Token::Address(Address::zero()), Token::Uint(1u64.into())])
+            .unwrap();
+
+        let mut node = CallTraceNodeBuilder::new().calldata(calldata.clone()).build();
+        let decode = |node: &mut CallTraceNode, func: &ethers::abi::Function| {
+            node.decode_function_with(
+                &[func.clone()],
+                &Default::default(),
+                &Default::default(),
+                false,
+            );
+            match &node.trace.data {
+                RawOrDecodedCall::Decoded(name, _, _) => name.clone(),
+                RawOrDecodedCall::Raw(_) => panic!("call was not decoded"),
+            }
+        };
+
+        // the raw data is only kept if requested
+        let mut unretained = node.clone();
+        assert_eq!(decode(&mut unretained, &transfer), "transfer");
+        assert_eq!(unretained.trace.raw, None);
+        unretained.restore_raw();
+        assert_eq!(decode(&mut unretained, &renamed), "transfer");
+
+        node.retain_raw();
+        assert_eq!(decode(&mut node, &transfer), "transfer");
+        // already decoded calls are left as they are
+        assert_eq!(decode(&mut node, &renamed), "transfer");
+
+        node.restore_raw();
+        assert_eq!(node.trace.data, RawOrDecodedCall::Raw(calldata));
+        assert_eq!(decode(&mut node, &renamed), "send");
+    }
+
+    #[test]
+    fn decodes_constructor_args_at_end_of_init_code() {
+        let abi: ethers::abi::Abi = serde_json::from_str(
+            r#"[{"type":"constructor","inputs":[{"name":"supply","type":"uint256"},{"name":"owner","type":"address"}]}]"#,
+        )
+        .unwrap();
+        let owner = Address::from_low_u64_be(1);
+        let labels = std::collections::HashMap::from([(owner, "owner".to_string())]);
+        let args = [Token::Uint(100u64.into()), Token::Address(owner)];
+        let init_code = [&[0x60, 0x80, 0x60, 0x40][..], &ethers::abi::encode(&args)].concat();
+        let node_of = |kind, data| CallTraceNode {
+            trace: CallTrace { kind, data: RawOrDecodedCall::Raw(data), ..Default::default() },
+            ..Default::default()
+        };
+
+        let mut node = node_of(CallKind::Create, init_code.clone());
+        assert_eq!(
+            node.decode_constructor(&abi, &labels),
+            Some(vec!["100".to_string(), format!("owner: [{}]", to_checksum(&owner, None))])
+        );
+        assert_eq!(node.trace.decoded_inputs, Some(args.to_vec()));
+        assert_eq!(node.trace.data, RawOrDecodedCall::Raw(init_code.clone()));
+
+        // the init code is too short to hold the arguments
+        let mut node = node_of(CallKind::Create, vec![0x60, 0x80]);
+        assert_eq!(node.decode_constructor(&abi, &labels), None);
+        assert_eq!(node.trace.decoded_inputs, None);
+
+        // calls have no constructor arguments
+        let mut node = node_of(CallKind::Call, init_code);
+        assert_eq!(node.decode_constructor(&abi, &labels), None);
     }
 }
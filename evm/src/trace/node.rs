@@ -1,20 +1,36 @@
 use crate::{
-    decode,
-    executor::CHEATCODE_ADDRESS,
+    abi::HEVM_FUNCTIONS_BY_SELECTOR,
+    debug::Instruction,
+    decode::{self, ErrorsBySelector},
+    executor::{
+        fork::{BlockingProvider, BlockingProviderResult, StateOverride},
+        CHEATCODE_ADDRESS,
+    },
     trace::{
-        utils, utils::decode_cheatcode_outputs, CallTrace, LogCallOrder, RawOrDecodedCall,
-        RawOrDecodedLog, RawOrDecodedReturnData,
+        utils, utils::decode_cheatcode_outputs, CallFrame, CallTrace, CallTraceStep, LogCallOrder,
+        RawOrDecodedCall, RawOrDecodedLog, RawOrDecodedReturnData, SourceLoc,
     },
+    utils::PCICMap,
     CallKind,
 };
 use ethers::{
-    abi::{Abi, Function},
-    types::{Action, Address, Call, CallResult, Create, CreateResult, Res, Suicide},
+    abi::{ethereum_types::BigEndianHash, Abi, Event, Function, Token},
+    providers::Middleware,
+    solc::{artifacts::Offsets, sourcemap::SourceMap},
+    types::{
+        transaction::eip2930::{AccessList, AccessListItem},
+        Action, Address, BlockId, Bytes, Call, CallResult, Create, CreateResult, Res, Suicide,
+        TransactionRequest, H256, U256,
+    },
+    utils::{get_create2_address, keccak256},
 };
 use foundry_common::SELECTOR_LEN;
-use revm::Return;
+use revm::{opcode, Return};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+};
 
 /// A node in the arena
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -28,13 +44,129 @@ pub struct CallTraceNode {
     /// The call trace
     pub trace: CallTrace,
     /// Logs
-    #[serde(skip)]
     pub logs: Vec<RawOrDecodedLog>,
+    /// The first topic of each log, which is kept when the logs are decoded, `None` for logs
+    /// without topics
+    #[serde(default)]
+    pub first_log_topics: Vec<Option<H256>>,
+    /// The tokens of each decoded log, before labels are applied, `None` for logs that are not
+    /// decoded. These are not serialized.
+    #[serde(skip)]
+    pub decoded_log_params: Vec<Option<Vec<Token>>>,
+    /// Notes attached to the node, e.g. from external analyses, which are displayed under it
+    #[serde(default)]
+    pub annotations: Vec<String>,
     /// Ordering of child calls and logs
     pub ordering: Vec<LogCallOrder>,
 }
 
+/// A problem encountered while decoding a [CallTraceNode] that did not prevent decoding it, see
+/// [CallTraceNode::decode_function_with] and
+/// [CallTraceDecoder::decode](crate::trace::CallTraceDecoder::decode)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeWarning {
+    /// Multiple distinct functions share the selector of the call, so the call may be mislabeled
+    SelectorCollision {
+        /// The selector of the call
+        selector: [u8; SELECTOR_LEN],
+        /// The signatures of all functions with the selector
+        signatures: Vec<String>,
+        /// The signature of the function the call was decoded with
+        used: String,
+    },
+}
+
+impl fmt::Display for DecodeWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeWarning::SelectorCollision { selector, signatures, used } => write!(
+                f,
+                "selector 0x{} is shared by {}, decoded as {used}",
+                hex::encode(selector),
+                signatures.join(", ")
+            ),
+        }
+    }
+}
+
+/// How the price of the gas used by a transaction is determined, see
+/// [CallTraceNode::gas_cost_in_wei]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GasPricing {
+    /// A legacy transaction, which pays a fixed price
+    Legacy {
+        /// The gas price of the transaction
+        gas_price: U256,
+    },
+    /// An EIP-1559 transaction, which pays the base fee of the block and a priority fee
+    Eip1559 {
+        /// The base fee of the block
+        base_fee: U256,
+        /// The effective priority fee, i.e. the max priority fee capped to the max fee minus the
+        /// base fee
+        priority_fee: U256,
+    },
+}
+
+impl GasPricing {
+    /// Returns the effective price of a unit of gas
+    pub fn effective_gas_price(&self) -> U256 {
+        match self {
+            GasPricing::Legacy { gas_price } => *gas_price,
+            GasPricing::Eip1559 { base_fee, priority_fee } => {
+                base_fee.saturating_add(*priority_fee)
+            }
+        }
+    }
+}
+
+/// A one-line summary of a [CallTraceNode], see [CallTraceNode::summary]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceSummary {
+    /// The destination address of the call or the address of the created contract
+    pub address: Address,
+    /// The kind of the call
+    pub kind: CallKind,
+    /// Whether the call was successful
+    pub success: bool,
+    /// The gas cost of the call
+    pub gas_cost: u64,
+    /// The function selector of the call, `None` for contract creations and calls without one
+    pub selector: Option<[u8; SELECTOR_LEN]>,
+    /// The name of the called function if the call is decoded
+    pub decoded_name: Option<String>,
+}
+
 impl CallTraceNode {
+    /// Returns a summary of the call, which does not borrow the node
+    pub fn summary(&self) -> TraceSummary {
+        TraceSummary {
+            address: self.trace.address,
+            kind: self.trace.kind,
+            success: self.trace.success,
+            gas_cost: self.trace.gas_cost,
+            selector: (!self.trace.kind.is_create()).then(|| self.trace.data.selector()).flatten(),
+            decoded_name: match &self.trace.data {
+                RawOrDecodedCall::Decoded(name, _, _) => Some(name.clone()),
+                RawOrDecodedCall::Raw(_) => None,
+            },
+        }
+    }
+
+    /// Attaches a note to the node, see [CallTraceNode::annotations]
+    pub fn annotate(&mut self, annotation: impl Into<String>) {
+        self.annotations.push(annotation.into());
+    }
+
+    /// Returns the first topic of the log at `idx`, i.e. the signature of its event unless it is
+    /// anonymous
+    pub fn log_topic0(&self, idx: usize) -> Option<H256> {
+        match &self.logs[idx] {
+            RawOrDecodedLog::Raw(log) => log.topics.first().copied(),
+            RawOrDecodedLog::Decoded(_, _) => self.first_log_topics.get(idx).copied().flatten(),
+        }
+    }
+
     /// Returns the kind of call the trace belongs to
     pub fn kind(&self) -> CallKind {
         self.trace.kind
@@ -45,83 +177,584 @@ impl CallTraceNode {
         self.trace.status
     }
 
-    /// Returns the `Res` for a parity trace
-    pub fn parity_result(&self) -> Res {
-        match self.kind() {
-            CallKind::Call | CallKind::StaticCall | CallKind::CallCode | CallKind::DelegateCall => {
-                Res::Call(CallResult {
-                    gas_used: self.trace.gas_cost.into(),
-                    output: self.trace.output.to_raw().into(),
+    /// Returns a readable description of the status of the call, e.g. `"reverted"`
+    pub fn status_string(&self) -> &'static str {
+        utils::status_description(self.status())
+    }
+
+    /// Returns the recorded steps of the node that execute the given opcode, e.g. `"SSTORE"`.
+    ///
+    /// The opcode name is matched case-insensitively.
+    pub fn steps_with_op<'a>(&'a self, op: &'a str) -> impl Iterator<Item = &'a CallTraceStep> {
+        self.trace.steps.iter().filter(move |step| step.op_name().eq_ignore_ascii_case(op))
+    }
+
+    /// Returns the cost of the gas used by the call in wei at the given pricing, e.g. to see the
+    /// cost of each call in ether.
+    ///
+    /// Like the gas cost, this includes the cost of the sub-calls.
+    pub fn gas_cost_in_wei(&self, pricing: GasPricing) -> U256 {
+        pricing.effective_gas_price().saturating_mul(self.trace.gas_cost.into())
+    }
+
+    /// Returns the values of the immutables of the contract created by the node, keyed by the AST
+    /// id of the immutable variable, e.g. to check that a contract was deployed with the expected
+    /// immutables.
+    ///
+    /// The values are read from the deployed runtime bytecode in the output of the node at the
+    /// `references` of each immutable, i.e. the `immutableReferences` of the deployed bytecode of
+    /// the contract's artifact. References past the end of the bytecode are skipped.
+    ///
+    /// Returns `None` if the node is not a contract creation, or if there is no metadata or
+    /// deployed bytecode, e.g. because the creation failed.
+    pub fn immutables(
+        &self,
+        references: Option<&BTreeMap<String, Vec<Offsets>>>,
+    ) -> Option<BTreeMap<String, Bytes>> {
+        let references = references.filter(|references| !references.is_empty())?;
+        if !self.kind().is_create() {
+            return None
+        }
+        let code = match &self.trace.output {
+            RawOrDecodedReturnData::Raw(code) if !code.is_empty() => code,
+            _ => return None,
+        };
+
+        Some(
+            references
+                .iter()
+                .filter_map(|(id, offsets)| {
+                    // Every reference of an immutable has the same value, so the first suffices
+                    let offset = offsets.first()?;
+                    let start = offset.start as usize;
+                    let value = code.get(start..start + offset.length as usize)?;
+                    Some((id.clone(), value.to_vec().into()))
                 })
+                .collect(),
+        )
+    }
+
+    /// Returns the gas used by the node's own execution, i.e. its gas cost without the gas cost of
+    /// its sub-calls
+    pub fn self_gas(&self, arena: &[CallTraceNode]) -> u64 {
+        let children_gas: u64 =
+            self.children.iter().map(|child| arena[*child].trace.gas_cost).sum();
+        self.trace.gas_cost.saturating_sub(children_gas)
+    }
+
+    /// Returns the gas used by this node and all of its descendants.
+    ///
+    /// The gas cost of a call already includes the gas used by its sub-calls, so this sums the gas
+    /// of each node's own execution to not count the gas of a sub-call twice. This also holds for
+    /// `DELEGATECALL`s, which execute in the context of the caller but in their own call frame.
+    pub fn cumulative_gas(&self, arena: &[CallTraceNode]) -> u64 {
+        self.self_gas(arena) +
+            self.children.iter().map(|child| arena[*child].cumulative_gas(arena)).sum::<u64>()
+    }
+
+    /// Returns whether the nodes make the same call, comparing only the raw calldata and return
+    /// data, the address, the kind and the number of sub-calls, so that the labels and decoding of
+    /// the calls are ignored.
+    ///
+    /// Calls that were decoded without keeping their raw data are compared by their decoded data.
+    /// The sub-calls themselves are compared with
+    /// [CallTraceArena::eq_structural](crate::trace::CallTraceArena::eq_structural).
+    pub fn eq_structural(&self, other: &CallTraceNode) -> bool {
+        let (trace, other_trace) = (&self.trace, &other.trace);
+        let same_data = match (trace.raw_calldata(), other_trace.raw_calldata()) {
+            (Some(data), Some(other)) => data == other,
+            _ => trace.data == other_trace.data,
+        };
+        let same_output = match (trace.raw_output(), other_trace.raw_output()) {
+            (Some(output), Some(other)) => output == other,
+            _ => trace.output == other_trace.output,
+        };
+        trace.address == other_trace.address &&
+            trace.kind == other_trace.kind &&
+            same_data &&
+            same_output &&
+            self.children.len() == other.children.len()
+    }
+
+    /// Returns the indices of the calls to `address` in the call tree of this node, including
+    /// itself, in execution order.
+    ///
+    /// Calls that run the code of `address` in the context of their caller, e.g. `DELEGATECALL`s
+    /// of a proxy to its implementation, are included. The caller of each call is its
+    /// [CallTrace::caller].
+    pub fn callers_of(&self, arena: &[CallTraceNode], address: Address) -> Vec<usize> {
+        let mut calls = Vec::new();
+        let mut stack = vec![self.idx];
+        while let Some(idx) = stack.pop() {
+            let node = &arena[idx];
+            if !node.kind().is_create() && node.trace.address == address {
+                calls.push(idx);
             }
-            CallKind::Create | CallKind::Create2 => Res::Create(CreateResult {
+            stack.extend(node.children.iter().rev());
+        }
+        calls
+    }
+
+    /// Returns the step at which the call reverted, `None` if it succeeded or steps are not
+    /// recorded.
+    ///
+    /// This is the last step of the call, unless it bubbled up the revert of its last sub-call, in
+    /// which case it is the step at which the sub-call reverted, recursively.
+    pub fn revert_step<'a>(&'a self, arena: &'a [CallTraceNode]) -> Option<&'a CallTraceStep> {
+        if self.trace.success {
+            return None
+        }
+        if let Some(child) = self.children.last().map(|child| &arena[*child]) {
+            if !child.trace.success && child.trace.output.to_raw() == self.trace.output.to_raw() {
+                if let Some(step) = child.revert_step(arena) {
+                    return Some(step)
+                }
+            }
+        }
+        self.trace.steps.last()
+    }
+
+    /// Returns the EIP-2930 access list of the call and its sub-calls.
+    ///
+    /// It contains the storage slots of every `SLOAD`/`SSTORE` and the addresses accessed by
+    /// calls, `BALANCE`, `EXTCODE*` and `SELFDESTRUCT`, which requires the steps to be recorded.
+    pub fn access_list(&self, arena: &[CallTraceNode]) -> AccessList {
+        let mut access_list = BTreeMap::new();
+        self.add_accesses(arena, &mut access_list);
+        AccessList::from(
+            access_list
+                .into_iter()
+                .map(|(address, slots)| AccessListItem {
+                    address,
+                    storage_keys: slots.into_iter().collect(),
+                })
+                .collect::<Vec<AccessListItem>>(),
+        )
+    }
+
+    fn add_accesses(
+        &self,
+        arena: &[CallTraceNode],
+        access_list: &mut BTreeMap<Address, BTreeSet<H256>>,
+    ) {
+        for step in &self.trace.steps {
+            let op = match step.op {
+                Instruction::OpCode(op) => op,
+                Instruction::Cheatcode(_) => continue,
+            };
+            let address = |n| step.stack_top(n).map(|value| Address::from(H256::from_uint(&value)));
+            match op {
+                opcode::SLOAD | opcode::SSTORE => {
+                    if let Some(slot) = step.stack_top(0) {
+                        access_list
+                            .entry(step.contract)
+                            .or_default()
+                            .insert(H256::from_uint(&slot));
+                    }
+                }
+                opcode::EXTCODECOPY |
+                opcode::EXTCODEHASH |
+                opcode::EXTCODESIZE |
+                opcode::BALANCE |
+                opcode::SELFDESTRUCT => {
+                    if let Some(address) = address(0) {
+                        access_list.entry(address).or_default();
+                    }
+                }
+                opcode::DELEGATECALL | opcode::CALL | opcode::STATICCALL | opcode::CALLCODE => {
+                    if let Some(address) = address(1) {
+                        access_list.entry(address).or_default();
+                    }
+                }
+                _ => {}
+            }
+        }
+        for child in &self.children {
+            arena[*child].add_accesses(arena, access_list);
+        }
+    }
+
+    /// Annotates the steps of the node with the locations of their instructions in the sources, see
+    /// [CallTraceStep::source_location].
+    ///
+    /// The `source_map` and the `pc_ic_map` must be of the code executed by the call, i.e. the
+    /// creation code for contract creations and the deployed code of the implementation for
+    /// `DELEGATECALL`s. Steps without an instruction in the source map are not annotated.
+    pub fn annotate_source_locations(&mut self, source_map: &SourceMap, pc_ic_map: &PCICMap) {
+        for step in &mut self.trace.steps {
+            step.source_location =
+                pc_ic_map.get(&step.pc).and_then(|ic| source_map.get(*ic)).map(SourceLoc::from);
+        }
+    }
+
+    /// Returns the `Res` for a parity trace
+    pub fn parity_result(&self) -> Res {
+        if self.kind().is_create() {
+            Res::Create(CreateResult {
                 gas_used: self.trace.gas_cost.into(),
                 code: self.trace.output.to_raw().into(),
                 address: self.trace.address,
-            }),
+            })
+        } else {
+            Res::Call(CallResult {
+                gas_used: self.trace.gas_cost.into(),
+                output: self.trace.output.to_raw().into(),
+            })
         }
     }
 
-    /// Returns the `Action` for a parity trace
+    /// Returns the `Action` for a parity trace.
+    ///
+    /// Like in OpenEthereum and Erigon, `from` is the account the call is made in the context of
+    /// and `to` the account whose code is executed, which for a `DELEGATECALL` or `CALLCODE` is the
+    /// calling contract (e.g. the proxy, also in a chain of `DELEGATECALL`s) and the called
+    /// contract (e.g. the implementation) respectively. `gas` is the gas available to the call.
+    ///
+    /// `value` is the value transferred by the call, so unlike a `CALLCODE`, a `DELEGATECALL` never
+    /// has a value, even though it keeps the `msg.value` of its caller.
     pub fn parity_action(&self) -> Action {
         if self.status() == Return::SelfDestruct {
             return Action::Suicide(Suicide {
                 address: self.trace.address,
-                // TODO deserialize from calldata here?
-                refund_address: Default::default(),
+                refund_address: self.selfdestruct_refund_address().unwrap_or_default(),
                 balance: self.trace.value,
             })
         }
-        match self.kind() {
-            CallKind::Call | CallKind::StaticCall | CallKind::CallCode | CallKind::DelegateCall => {
-                Action::Call(Call {
-                    from: self.trace.caller,
-                    to: self.trace.address,
-                    value: self.trace.value,
-                    gas: self.trace.gas_cost.into(),
-                    input: self.trace.data.to_raw().into(),
-                    call_type: self.kind().into(),
-                })
-            }
-            CallKind::Create | CallKind::Create2 => Action::Create(Create {
+        if self.kind().is_create() {
+            Action::Create(Create {
                 from: self.trace.caller,
                 value: self.trace.value,
-                gas: self.trace.gas_cost.into(),
+                gas: self.trace.gas_limit.into(),
                 init: self.trace.data.to_raw().into(),
-            }),
+            })
+        } else {
+            Action::Call(Call {
+                // The tracer records the calling contract as the caller of a `DELEGATECALL` or
+                // `CALLCODE` and the code address as the address, rather than the sender and the
+                // address of the context. The caller of an `AUTHCALL` is the authorized account
+                from: self.trace.caller,
+                to: self.trace.address,
+                value: if self.kind().is_delegate() { U256::zero() } else { self.trace.value },
+                gas: self.trace.gas_limit.into(),
+                input: self.trace.data.to_raw().into(),
+                call_type: self.kind().into(),
+            })
+        }
+    }
+
+    /// Executes the call of the node again at the given block with `eth_call`, e.g. to check
+    /// whether a historical sub-call still succeeds against the current state, and returns its
+    /// output.
+    ///
+    /// The call is made with the parameters of [Self::parity_action]. Note that it runs as a
+    /// regular call, so a `DELEGATECALL` or `CALLCODE` runs in the context of the called contract
+    /// instead of its caller. The recorded gas limit of the call is not used, since the replayed
+    /// call is a transaction of its own that also has to pay the intrinsic gas, so the gas limit is
+    /// left to the RPC node.
+    pub fn replay_call<M: Middleware>(
+        &self,
+        provider: &BlockingProvider<M>,
+        block: Option<BlockId>,
+    ) -> BlockingProviderResult<Bytes, M>
+    where
+        M::Error: 'static,
+    {
+        self.replay_call_with(provider, block, None, &StateOverride::new())
+    }
+
+    /// Same as [Self::replay_call], but with the gas limit replaced by `gas` if set and the state
+    /// of the accounts in `overrides` replaced, e.g. to check whether a call that ran out of gas
+    /// succeeds with more, or what a call returns with another balance or code.
+    ///
+    /// The recorded gas limit of a sub-call is what its caller forwarded to it, which is at most
+    /// all but one 64th of the caller's remaining gas (EIP-150). So if a sub-call succeeds with
+    /// more gas, its caller needs to have had 64/63 of it left at the call, and so on for every
+    /// level of nesting, to actually forward that much. Also note that the replayed call is a
+    /// transaction of its own, so the intrinsic gas of the transaction is deducted from `gas`
+    /// before the call is executed.
+    ///
+    /// The call is made with [BlockingProvider::call_with_overrides] if there are overrides,
+    /// which not every node supports.
+    pub fn replay_call_with<M: Middleware>(
+        &self,
+        provider: &BlockingProvider<M>,
+        block: Option<BlockId>,
+        gas: Option<u64>,
+        overrides: &StateOverride,
+    ) -> BlockingProviderResult<Bytes, M>
+    where
+        M::Error: 'static,
+    {
+        let (from, to, value, data) = match self.parity_action() {
+            Action::Call(call) => (call.from, Some(call.to), call.value, call.input),
+            Action::Create(create) => (create.from, None, create.value, create.init),
+            // The call self-destructed, which the action does not describe
+            _ => (
+                self.trace.caller,
+                Some(self.trace.address),
+                self.trace.value,
+                self.trace.data.to_raw().into(),
+            ),
+        };
+        let tx = TransactionRequest {
+            from: Some(from),
+            to: to.map(Into::into),
+            value: Some(value),
+            gas: gas.map(Into::into),
+            data: Some(data),
+            ..Default::default()
+        }
+        .into();
+        if overrides.is_empty() {
+            provider.call(&tx, block)
+        } else {
+            provider.call_with_overrides(&tx, block, overrides)
+        }
+    }
+
+    /// Returns the beneficiary of the `SELFDESTRUCT` that ended the call.
+    ///
+    /// This is the top stack item of the last step, so it's only available if steps were recorded.
+    fn selfdestruct_refund_address(&self) -> Option<Address> {
+        let step = self
+            .trace
+            .steps
+            .last()
+            .filter(|step| step.op == Instruction::OpCode(opcode::SELFDESTRUCT))?;
+        step.stack.peek(0).ok().map(|value| H256::from_uint(&value).into())
+    }
+
+    /// Returns the call frame for a geth `callTracer` trace, without any sub-calls
+    pub fn geth_call_frame(&self) -> CallFrame {
+        let (from, to, value, input) = match self.parity_action() {
+            Action::Call(call) => (call.from, call.to, call.value, call.input),
+            Action::Create(create) => (create.from, self.trace.address, create.value, create.init),
+            Action::Suicide(suicide) => {
+                (suicide.address, suicide.refund_address, suicide.balance, Default::default())
+            }
+            Action::Reward(_) => unreachable!("rewards are not traced"),
+        };
+        let (gas_used, output) = match self.parity_result() {
+            Res::Call(call) => (call.gas_used, call.output),
+            Res::Create(create) => (create.gas_used, create.code),
+            Res::None => (U256::zero(), Default::default()),
+        };
+
+        CallFrame {
+            kind: self.kind(),
+            from,
+            to,
+            value: (!self.kind().is_static() && !self.kind().is_delegate()).then_some(value),
+            gas: self.trace.gas_limit.into(),
+            gas_used,
+            input,
+            output: (!output.is_empty()).then_some(output),
+            error: (!self.trace.success)
+                .then(|| utils::geth_error(self.status(), self.trace.steps.last().map(|s| s.op))),
+            calls: Vec::new(),
+        }
+    }
+
+    /// Keeps the raw calldata and return data of the call before it is decoded, so it can be
+    /// restored with [Self::restore_raw].
+    ///
+    /// The data is not kept by default, since this copies it for every decoded call.
+    pub fn retain_raw(&mut self) {
+        if self.trace.raw.is_some() {
+            return
         }
+        if let (RawOrDecodedCall::Raw(data), RawOrDecodedReturnData::Raw(output)) =
+            (&self.trace.data, &self.trace.output)
+        {
+            self.trace.raw = Some((data.clone(), output.clone()));
+        }
+    }
+
+    /// Returns a hash of the call and its sub-calls that is stable across runs, e.g. to cache
+    /// decoded traces.
+    ///
+    /// It covers the kind, the caller, the address, the value, the calldata, the return data and
+    /// the success of the calls, the first topics of their logs and the order of the sub-calls.
+    /// Decoding and labeling calls does not change the hash, as long as their raw data is kept,
+    /// see [Self::retain_raw]. Otherwise the decoded data is hashed instead.
+    pub fn trace_hash(&self, arena: &[CallTraceNode]) -> H256 {
+        // the discriminants are part of the hash, so they must not change with the enum
+        let kind: u8 = match self.kind() {
+            CallKind::Call => 0,
+            CallKind::StaticCall => 1,
+            CallKind::CallCode => 2,
+            CallKind::DelegateCall => 3,
+            CallKind::Create => 4,
+            CallKind::Create2 => 5,
+            CallKind::AuthCall => 6,
+        };
+        let data = match self.trace.raw_calldata() {
+            Some(data) => data.to_vec(),
+            None => serde_json::to_vec(&self.trace.data).expect("decoded calls are serializable"),
+        };
+        let output = match self.trace.raw_output() {
+            Some(output) => output.to_vec(),
+            None => serde_json::to_vec(&self.trace.output).expect("decoded output is serializable"),
+        };
+
+        let mut preimage = vec![kind, self.trace.success as u8];
+        // whether the data is raw, so decoded data can't be mistaken for raw data
+        preimage.extend([
+            self.trace.raw_calldata().is_some() as u8,
+            self.trace.raw_output().is_some() as u8,
+        ]);
+        preimage.extend_from_slice(self.trace.caller.as_bytes());
+        preimage.extend_from_slice(self.trace.address.as_bytes());
+        preimage.extend_from_slice(H256::from_uint(&self.trace.value).as_bytes());
+        for bytes in [&data, &output] {
+            preimage.extend_from_slice(&(bytes.len() as u64).to_be_bytes());
+            preimage.extend_from_slice(bytes);
+        }
+        preimage.extend_from_slice(&(self.logs.len() as u64).to_be_bytes());
+        for idx in 0..self.logs.len() {
+            preimage.extend_from_slice(self.log_topic0(idx).unwrap_or_default().as_bytes());
+        }
+        for child in &self.children {
+            preimage.extend_from_slice(arena[*child].trace_hash(arena).as_bytes());
+        }
+        H256(keccak256(preimage))
+    }
+
+    /// Restores the raw calldata and return data of a decoded call, so it can be decoded again,
+    /// e.g. with an updated ABI.
+    ///
+    /// Decoding is idempotent: the decoding functions leave already decoded calls as they are, even
+    /// when given other functions, so a call has to be restored first to be decoded again.
+    pub fn restore_raw(&mut self) {
+        if let Some((data, output)) = self.trace.raw.take() {
+            self.trace.data = RawOrDecodedCall::Raw(data);
+            self.trace.output = RawOrDecodedReturnData::Raw(output);
+            self.trace.decoded_inputs = None;
+            self.trace.best_effort_signature = false;
+        }
+    }
+
+    /// Decodes the call with the decoder for its destination, returning the problems encountered
+    /// if it was decoded, `None` otherwise.
+    ///
+    /// Calls to precompiles are decoded with their function in `precompiles`, and are left as is if
+    /// it is unknown. Calls to the cheatcode address are decoded with the cheatcodes only, and all
+    /// other calls with the `functions` by selector.
+    pub fn decode(
+        &mut self,
+        precompiles: &HashMap<Address, Function>,
+        functions: &BTreeMap<[u8; 4], Vec<Function>>,
+        labels: &HashMap<Address, String>,
+        errors: &ErrorsBySelector,
+        format_amounts: bool,
+    ) -> Option<Vec<DecodeWarning>> {
+        if let Some(precompile_fn) = precompiles.get(&self.trace.address) {
+            self.decode_precompile(precompile_fn, labels);
+            return Some(Vec::new())
+        }
+        if self.trace.is_precompile() {
+            return None
+        }
+
+        let selector: [u8; SELECTOR_LEN] = match &self.trace.data {
+            RawOrDecodedCall::Raw(bytes) if bytes.len() >= SELECTOR_LEN => {
+                bytes[..SELECTOR_LEN].try_into().unwrap()
+            }
+            _ => return None,
+        };
+        let funcs = if self.trace.address == CHEATCODE_ADDRESS {
+            std::slice::from_ref(HEVM_FUNCTIONS_BY_SELECTOR.get(&selector)?)
+        } else {
+            functions.get(&selector)?.as_slice()
+        };
+        if funcs.is_empty() {
+            return None
+        }
+
+        let warnings = self.decode_function_with(funcs, labels, errors, format_amounts);
+        matches!(self.trace.data, RawOrDecodedCall::Decoded(..)).then_some(warnings)
     }
 
-    /// Decode a regular function
+    /// Decode a regular function, returning the problems encountered, see
+    /// [Self::decode_function_with]
+    ///
+    /// When decoding many nodes with the same errors, prefer [Self::decode_function_with] with an
+    /// index of the errors built once with [decode::errors_by_selector].
     pub fn decode_function(
         &mut self,
         funcs: &[Function],
         labels: &HashMap<Address, String>,
         errors: &Abi,
-    ) {
+    ) -> Vec<DecodeWarning> {
+        self.decode_function_with(funcs, labels, &decode::errors_by_selector(errors), false)
+    }
+
+    /// Decode a regular function, looking up custom errors by their selector in `errors`
+    ///
+    /// If `format_amounts` is set, amounts of wei in the inputs and outputs are shown in ether, see
+    /// [utils::label_param]
+    ///
+    /// Decoding never fails because of the returned warnings, e.g. if `funcs` contains multiple
+    /// distinct functions, the call is decoded with the first one that can decode its input, but
+    /// a [DecodeWarning::SelectorCollision] is returned so the ABI can be disambiguated. The call
+    /// stays raw if none of the functions can decode its input.
+    pub fn decode_function_with(
+        &mut self,
+        funcs: &[Function],
+        labels: &HashMap<Address, String>,
+        errors: &ErrorsBySelector,
+        format_amounts: bool,
+    ) -> Vec<DecodeWarning> {
         debug_assert!(!funcs.is_empty(), "requires at least 1 func");
-        // This is safe because (1) we would not have an entry for the given
-        // selector if no functions with that selector were added and (2) the
-        // same selector implies the function has
-        // the same name and inputs.
-        let func = &funcs[0];
 
+        let mut warnings = Vec::new();
         if let RawOrDecodedCall::Raw(ref bytes) = self.trace.data {
+            // The same selector does not imply the same function, since selectors of different
+            // functions can collide, so we use the first function that can decode the input.
+            // This is safe because we would not have an entry for the given selector if no
+            // functions with that selector were added.
+            let decoded = bytes.get(SELECTOR_LEN..).and_then(|input| {
+                funcs
+                    .iter()
+                    .find_map(|func| func.decode_input(input).ok().map(|tokens| (func, tokens)))
+            });
+            let func = decoded.as_ref().map_or(&funcs[0], |(func, _)| *func);
+
+            let signatures = funcs.iter().map(|func| func.signature()).collect::<BTreeSet<_>>();
+            if signatures.len() > 1 {
+                warnings.push(DecodeWarning::SelectorCollision {
+                    selector: func.short_signature(),
+                    signatures: signatures.into_iter().collect(),
+                    used: func.signature(),
+                });
+            }
+
             let inputs = if bytes.len() >= SELECTOR_LEN {
-                if self.trace.address == CHEATCODE_ADDRESS {
+                let cheatcode_inputs = if self.trace.address == CHEATCODE_ADDRESS {
                     // Try to decode cheatcode inputs in a more custom way
-                    utils::decode_cheatcode_inputs(func, bytes, errors).unwrap_or_else(|| {
-                        func.decode_input(&bytes[SELECTOR_LEN..])
-                            .expect("bad function input decode")
-                            .iter()
-                            .map(|token| utils::label(token, labels))
-                            .collect()
-                    })
+                    utils::decode_cheatcode_inputs(func, bytes, errors)
                 } else {
-                    match func.decode_input(&bytes[SELECTOR_LEN..]) {
-                        Ok(v) => v.iter().map(|token| utils::label(token, labels)).collect(),
-                        Err(_) => Vec::new(),
+                    None
+                };
+
+                match (cheatcode_inputs, decoded) {
+                    // Cheatcode inputs are decoded in a custom way to redact secrets like private
+                    // keys, so their tokens are not kept either
+                    (Some(inputs), _) => inputs,
+                    (None, Some((func, tokens))) => {
+                        let inputs = tokens
+                            .iter()
+                            .zip(func.inputs.iter())
+                            .map(|(token, param)| {
+                                utils::label_param(token, param, labels, format_amounts)
+                            })
+                            .collect();
+                        self.trace.decoded_inputs = Some(tokens);
+                        inputs
                     }
+                    // Keep the call raw if none of the functions can decode its input, e.g. for
+                    // malformed cheatcode calldata
+                    (None, None) => return warnings,
                 }
             } else {
                 Vec::new()
@@ -138,48 +771,171 @@ impl CallTraceNode {
                             funcs.iter().find_map(|func| decode_cheatcode_outputs(func, bytes))
                         {
                             self.trace.output = RawOrDecodedReturnData::Decoded(decoded);
-                            return
+                            return warnings
                         }
                     }
 
-                    if let Some(tokens) =
-                        funcs.iter().find_map(|func| func.decode_output(bytes).ok())
+                    if let Some(decoded) = funcs
+                        .iter()
+                        .find_map(|func| utils::decode_output(func, bytes, labels, format_amounts))
                     {
-                        // Functions coming from an external database do not have any outputs
-                        // specified, and will lead to returning an empty list of tokens.
-                        if !tokens.is_empty() {
-                            self.trace.output = RawOrDecodedReturnData::Decoded(
-                                tokens
-                                    .iter()
-                                    .map(|token| utils::label(token, labels))
-                                    .collect::<Vec<_>>()
-                                    .join(", "),
-                            );
+                        self.trace.output = RawOrDecodedReturnData::Decoded(decoded);
+                    }
+                } else if let Some(decoded_error) = utils::decode_revert(
+                    bytes,
+                    funcs,
+                    errors,
+                    labels,
+                    format_amounts,
+                    Some(self.trace.status),
+                ) {
+                    self.trace.output = RawOrDecodedReturnData::Decoded(decoded_error);
+                }
+            }
+        }
+        warnings
+    }
+
+    /// Decodes the raw logs of the node with the given events.
+    ///
+    /// Anonymous events are tried for every log. A log stays raw if none of the events decode it,
+    /// or if it's decoded by multiple different events, e.g. if their `topic0` collides.
+    pub fn decode_logs(&mut self, events: &[Event], labels: &HashMap<Address, String>) {
+        for idx in 0..self.logs.len() {
+            if let RawOrDecodedLog::Raw(raw_log) = &self.logs[idx] {
+                let mut decoded = Vec::new();
+                for event in events.iter().filter(|event| {
+                    event.anonymous || raw_log.topics.first() == Some(&event.signature())
+                }) {
+                    if let Some(decoded_log) = utils::decode_event(event, raw_log, labels) {
+                        if !decoded.contains(&decoded_log) {
+                            decoded.push(decoded_log);
                         }
                     }
-                } else if let Ok(decoded_error) =
-                    decode::decode_revert(bytes, Some(errors), Some(self.trace.status))
-                {
-                    self.trace.output =
-                        RawOrDecodedReturnData::Decoded(format!(r#""{}""#, decoded_error));
+                }
+
+                if decoded.len() == 1 {
+                    let (log, tokens) = decoded.remove(0);
+                    self.set_decoded_log(idx, log, tokens);
                 }
             }
         }
     }
 
-    /// Decode the node's tracing data for the given precompile function
+    /// Replaces the log at `idx` with its decoded version and keeps its `tokens` in
+    /// [Self::decoded_log_params]
+    pub(crate) fn set_decoded_log(&mut self, idx: usize, log: RawOrDecodedLog, tokens: Vec<Token>) {
+        self.logs[idx] = log;
+        if self.decoded_log_params.len() < self.logs.len() {
+            self.decoded_log_params.resize(self.logs.len(), None);
+        }
+        self.decoded_log_params[idx] = Some(tokens);
+    }
+
+    /// Decodes the constructor arguments of a contract creation with the `abi` of the created
+    /// contract, returning the labeled arguments. The tokens are kept in
+    /// [CallTrace::decoded_inputs].
+    ///
+    /// The arguments are ABI encoded at the end of the init code. Since the length of the creation
+    /// bytecode is not known, the arguments are the shortest suffix of the init code that decodes
+    /// to the constructor parameters and encodes back to the same bytes.
+    ///
+    /// The init code itself is not changed, since it's needed to identify the created contract.
+    /// Returns `None` if this is not a contract creation or the arguments can't be decoded.
+    pub fn decode_constructor(
+        &mut self,
+        abi: &Abi,
+        labels: &HashMap<Address, String>,
+    ) -> Option<Vec<String>> {
+        if !self.kind().is_create() {
+            return None
+        }
+        let init_code = match &self.trace.data {
+            RawOrDecodedCall::Raw(init_code) => init_code,
+            RawOrDecodedCall::Decoded(..) => return None,
+        };
+
+        let inputs =
+            abi.constructor().map(|constructor| constructor.inputs.as_slice()).unwrap_or_default();
+        let tokens = if inputs.is_empty() {
+            Vec::new()
+        } else {
+            let types = inputs.iter().map(|input| input.kind.clone()).collect::<Vec<_>>();
+            // every parameter takes at least one word in the head of the encoding
+            let min_len = types.len() * 32;
+            (min_len..=init_code.len()).step_by(32).find_map(|len| {
+                let args = &init_code[init_code.len() - len..];
+                ethers::abi::decode(&types, args)
+                    .ok()
+                    .filter(|tokens| ethers::abi::encode(tokens) == args)
+            })?
+        };
+
+        let args = tokens
+            .iter()
+            .zip(inputs)
+            .map(|(token, param)| utils::label_param(token, param, labels, false))
+            .collect();
+        self.trace.decoded_inputs = Some(tokens);
+        Some(args)
+    }
+
+    /// Whether the address created by this `CREATE2` is the address of the given `deployer`,
+    /// `salt` and `init_code`, i.e. `keccak256(0xff ++ deployer ++ salt ++ keccak256(init_code))`
+    /// as specified in [EIP-1014](https://eips.ethereum.org/EIPS/eip-1014).
+    ///
+    /// Always `false` if this is not a `CREATE2`.
+    pub fn verify_create2_address(&self, deployer: Address, salt: H256, init_code: &[u8]) -> bool {
+        self.kind() == CallKind::Create2 &&
+            self.trace.address ==
+                get_create2_address(deployer, salt.to_fixed_bytes(), init_code.to_vec())
+    }
+
+    /// Decodes the sub-calls bundled in the calldata of a multicall with the `functions` by
+    /// selector, see [utils::multicall_calls].
+    ///
+    /// The sub-calls replace the raw calldata in the decoded inputs, this does nothing if the call
+    /// was not decoded as a multicall.
+    pub fn decode_multicall(
+        &mut self,
+        functions: &BTreeMap<[u8; 4], Vec<Function>>,
+        labels: &HashMap<Address, String>,
+        format_amounts: bool,
+    ) {
+        if let (RawOrDecodedCall::Decoded(name, _, inputs), Some(tokens)) =
+            (&mut self.trace.data, &self.trace.decoded_inputs)
+        {
+            if let (Some(calls), Some(input)) =
+                (utils::multicall_calls(name, tokens), inputs.last_mut())
+            {
+                *input = utils::format_multicall_calls(&calls, functions, labels, format_amounts);
+            }
+        }
+    }
+
+    /// Decode the node's tracing data for the given precompile function, labeling the node with
+    /// the name of the precompile.
+    ///
+    /// The gas cost of a successful call is set to the cost the precompile charges for its input,
+    /// see [utils::precompile_gas_cost].
     pub fn decode_precompile(
         &mut self,
         precompile_fn: &Function,
         labels: &HashMap<Address, String>,
     ) {
         if let RawOrDecodedCall::Raw(ref bytes) = self.trace.data {
-            self.trace.label = Some("PRECOMPILE".to_string());
+            if self.trace.success {
+                if let Some(gas_cost) = utils::precompile_gas_cost(&self.trace.address, bytes) {
+                    self.trace.gas_cost = gas_cost;
+                }
+            }
+            self.trace.label = Some(precompile_fn.name.clone());
+            self.trace.decoded_inputs = precompile_fn.decode_input(bytes).ok();
             self.trace.data = RawOrDecodedCall::Decoded(
                 precompile_fn.name.clone(),
                 precompile_fn.signature(),
-                precompile_fn.decode_input(bytes).map_or_else(
-                    |_| vec![hex::encode(bytes)],
+                self.trace.decoded_inputs.as_ref().map_or_else(
+                    || vec![hex::encode(bytes)],
                     |tokens| tokens.iter().map(|token| utils::label(token, labels)).collect(),
                 ),
             );
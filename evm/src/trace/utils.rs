@@ -1,39 +1,142 @@
 //! utilities used within tracing
 
-use crate::decode;
+use crate::{
+    debug::Instruction,
+    decode::{self, ErrorsBySelector, RevertData},
+    trace::RawOrDecodedLog,
+};
 use ethers::{
-    abi::{Abi, Address, Function, ParamType, Token},
-    core::utils::to_checksum,
+    abi::{Address, Event, Function, Param, ParamType, RawLog, Token},
+    core::utils::{format_units, to_checksum},
+    types::U256,
 };
 use foundry_common::{abi::format_token, SELECTOR_LEN};
-use std::collections::HashMap;
+use revm::{opcode, return_revert, Return};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 /// Returns the label for the given `token`
 ///
 /// If the `token` is an `Address` then we look abel the label map.
 /// by default the token is formatted using standard formatting
+///
+/// Arrays and tuples are labeled recursively, so the addresses within them, e.g. in struct
+/// arguments, are labeled too. Addresses are rendered EIP-55 checksummed, see [label_with].
 pub fn label(token: &Token, labels: &HashMap<Address, String>) -> String {
+    label_with(token, labels, true)
+}
+
+/// Same as [label], but addresses are rendered in lowercase if `checksum` is not set, e.g. for
+/// deterministic output in tests
+pub fn label_with(token: &Token, labels: &HashMap<Address, String>, checksum: bool) -> String {
     match token {
         Token::Address(addr) => {
-            if let Some(label) = labels.get(addr) {
-                format!("{}: [{}]", label, to_checksum(addr, None))
-            } else {
-                format_token(token)
+            let address = if checksum { to_checksum(addr, None) } else { format!("{addr:?}") };
+            match labels.get(addr) {
+                Some(label) => format!("{label}: [{address}]"),
+                None => address,
             }
         }
+        Token::Array(tokens) | Token::FixedArray(tokens) => {
+            format!("[{}]", label_all(tokens, labels, checksum))
+        }
+        Token::Tuple(tokens) => format!("({})", label_all(tokens, labels, checksum)),
         _ => format_token(token),
     }
 }
 
+/// Labels the `tokens` with [label_with] and joins them with `, `
+fn label_all(tokens: &[Token], labels: &HashMap<Address, String>, checksum: bool) -> String {
+    tokens.iter().map(|token| label_with(token, labels, checksum)).collect::<Vec<_>>().join(", ")
+}
+
+/// Returns the label for the given `token` of the `param`
+///
+/// Same as [label], but if `format_amounts` is set, `uint` values of params whose name suggests an
+/// amount of wei (`amount`, `value`, `wad`) are formatted in ether, or gwei for small amounts.
+pub fn label_param(
+    token: &Token,
+    param: &Param,
+    labels: &HashMap<Address, String>,
+    format_amounts: bool,
+) -> String {
+    match token {
+        Token::Uint(amount) if format_amounts && is_amount_param(param) => format_amount(*amount),
+        _ => label(token, labels),
+    }
+}
+
+/// Whether the name of the `param` suggests that it's an amount of wei
+fn is_amount_param(param: &Param) -> bool {
+    let name = param.name.to_lowercase();
+    ["amount", "value", "wad"].iter().any(|hint| name.contains(hint))
+}
+
+/// Formats the amount of wei in ether, or in gwei if it's less than 0.001 ether
+fn format_amount(amount: U256) -> String {
+    let (decimals, unit) =
+        if amount >= U256::exp10(15) { (18u32, "ether") } else { (9u32, "gwei") };
+    match format_units(amount, decimals) {
+        Ok(formatted) => {
+            format!("{} {}", formatted.trim_end_matches('0').trim_end_matches('.'), unit)
+        }
+        Err(_) => amount.to_string(),
+    }
+}
+
+/// Decodes the `log` with the given `event`, labeling the decoded parameters.
+///
+/// Also returns the decoded tokens, before labels are applied.
+pub(crate) fn decode_event(
+    event: &Event,
+    log: &RawLog,
+    labels: &HashMap<Address, String>,
+) -> Option<(RawOrDecodedLog, Vec<Token>)> {
+    let mut event = event.clone();
+    // ensure all params are named, otherwise this will cause issues with decoding: See also <https://github.com/rust-ethereum/ethabi/issues/206>
+    let empty_params = patch_nameless_params(&mut event);
+    let decoded = event.parse_log(log.clone()).ok()?;
+
+    let tokens = decoded.params.iter().map(|param| param.value.clone()).collect();
+    let log = RawOrDecodedLog::Decoded(
+        event.name,
+        decoded
+            .params
+            .into_iter()
+            .map(|param| {
+                // undo patched names
+                let name =
+                    if empty_params.contains(&param.name) { "".to_string() } else { param.name };
+                (name, label(&param.value, labels))
+            })
+            .collect(),
+    );
+    Some((log, tokens))
+}
+
+/// This is a bit horrible but due to <https://github.com/rust-ethereum/ethabi/issues/206> we need to patch nameless (valid) params before decoding a logs, otherwise [`Event::parse_log()`] will result in wrong results since they're identified by name.
+///
+/// Returns a set of patched param names, that originally were empty.
+fn patch_nameless_params(event: &mut Event) -> HashSet<String> {
+    let mut patches = HashSet::new();
+    if event.inputs.iter().filter(|input| input.name.is_empty()).count() > 1 {
+        for (idx, param) in event.inputs.iter_mut().enumerate() {
+            // this is an illegal arg name, which ensures patched identifiers are unique
+            param.name = format!("<patched {}>", idx);
+            patches.insert(param.name.clone());
+        }
+    }
+    patches
+}
+
 /// Custom decoding of cheatcode calls
 pub(crate) fn decode_cheatcode_inputs(
     func: &Function,
     data: &[u8],
-    errors: &Abi,
+    errors: &ErrorsBySelector,
 ) -> Option<Vec<String>> {
     match func.name.as_str() {
         "expectRevert" => {
-            decode::decode_revert(data, Some(errors), None).ok().map(|decoded| vec![decoded])
+            decode::decode_revert_with(data, Some(errors), None).ok().map(|decoded| vec![decoded])
         }
         "rememberKey" | "addr" | "startBroadcast" | "broadcast" => {
             // these functions accept a private key as uint256, which should not be
@@ -55,19 +158,389 @@ pub(crate) fn decode_cheatcode_inputs(
         }
         "deriveKey" => Some(vec!["<pk>".to_string()]),
 
-        _ => None,
+        // EIP-712 typed data is passed to cheatcodes as JSON, e.g. to `parseJson` or `writeFile`
+        _ => {
+            let decoded = func.decode_input(data.get(SELECTOR_LEN..)?).ok()?;
+            let mut has_typed_data = false;
+            let inputs = decoded
+                .iter()
+                .map(|token| {
+                    let typed_data = match token {
+                        Token::String(s) => format_typed_data(s),
+                        Token::Bytes(bytes) => {
+                            std::str::from_utf8(bytes).ok().and_then(format_typed_data)
+                        }
+                        _ => None,
+                    };
+                    has_typed_data |= typed_data.is_some();
+                    typed_data.unwrap_or_else(|| format_token(token))
+                })
+                .collect();
+            has_typed_data.then_some(inputs)
+        }
     }
 }
 
+/// Formats EIP-712 typed data in its JSON representation as its domain and its message, e.g.
+/// `EIP712Domain(name: Ether Mail, chainId: 1) Mail(contents: Hello, Bob!)`, `None` if `json` is
+/// not typed data
+fn format_typed_data(json: &str) -> Option<String> {
+    let typed_data: serde_json::Value = serde_json::from_str(json).ok()?;
+    let primary_type = typed_data.get("primaryType")?.as_str()?;
+    typed_data.get("types")?.get(primary_type)?;
+    let domain = typed_data.get("domain")?.as_object()?;
+    let message = typed_data.get("message")?.as_object()?;
+    Some(format!(
+        "EIP712Domain({}) {}({})",
+        format_typed_data_fields(domain),
+        primary_type,
+        format_typed_data_fields(message)
+    ))
+}
+
+/// Formats the fields of a typed data struct as `name: value`, recursively for nested structs
+fn format_typed_data_fields(fields: &serde_json::Map<String, serde_json::Value>) -> String {
+    fields
+        .iter()
+        .map(|(name, value)| match value {
+            serde_json::Value::String(value) => format!("{}: {}", name, value),
+            serde_json::Value::Object(fields) => {
+                format!("{}: ({})", name, format_typed_data_fields(fields))
+            }
+            value => format!("{}: {}", name, value),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 /// Custom decoding of cheatcode return values
-pub(crate) fn decode_cheatcode_outputs(func: &Function, _data: &[u8]) -> Option<String> {
+pub(crate) fn decode_cheatcode_outputs(func: &Function, data: &[u8]) -> Option<String> {
     if func.name.starts_with("env") {
         // redacts the value stored in the env var
         return Some("<env var value>".to_string())
     }
-    if func.name == "deriveKey" {
+    match func.name.as_str() {
         // redacts derived private key
-        return Some("<pk>".to_string())
+        "deriveKey" => Some("<pk>".to_string()),
+        // redacts the RPC URLs, which often contain API keys
+        "rpcUrl" | "rpcUrls" => Some("<rpc url>".to_string()),
+        // shows the output of commands and binary files as text if possible
+        "ffi" | "readFileBinary" => match func.decode_output(data).ok()?.as_slice() {
+            [Token::Bytes(bytes)] => {
+                let text = std::str::from_utf8(bytes).ok()?;
+                // `{:?}` quotes the text and escapes newlines
+                (!text.chars().any(|c| c.is_control() && !c.is_whitespace()))
+                    .then(|| format!("{:?}", text))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Functions of common multicall contracts, whose last parameter bundles the sub-calls
+const MULTICALL_FUNCTIONS: &[&str] = &[
+    "multicall",
+    "aggregate",
+    "tryAggregate",
+    "blockAndAggregate",
+    "tryBlockAndAggregate",
+    "aggregate3",
+    "aggregate3Value",
+];
+
+/// Returns the target, if any, and the calldata of each sub-call bundled in the `inputs` of the
+/// multicall function `func`.
+///
+/// Supports the `multicall(bytes[])` and `multicall(uint256,bytes[])` functions of contracts that
+/// call themselves, like Uniswap's periphery, and the `aggregate` functions of Multicall2 and
+/// Multicall3 with their `(address,..,bytes)[]` calls.
+pub(crate) fn multicall_calls<'a>(
+    func: &str,
+    inputs: &'a [Token],
+) -> Option<Vec<(Option<Address>, &'a [u8])>> {
+    if !MULTICALL_FUNCTIONS.contains(&func) {
+        return None
+    }
+    match inputs.last() {
+        Some(Token::Array(calls)) => calls
+            .iter()
+            .map(|call| match call {
+                Token::Bytes(data) => Some((None, &data[..])),
+                Token::Tuple(fields) => match (fields.first(), fields.last()) {
+                    (Some(Token::Address(target)), Some(Token::Bytes(data))) => {
+                        Some((Some(*target), &data[..]))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+/// Formats the sub-calls of a multicall, see [multicall_calls], decoding each of them with the
+/// `functions` by selector.
+///
+/// Calls are shown as `target::name(args)`, or `name(args)` if they have no target, and nested
+/// multicalls are decoded as well. Calls that can't be decoded are shown as raw calldata.
+pub(crate) fn format_multicall_calls(
+    calls: &[(Option<Address>, &[u8])],
+    functions: &BTreeMap<[u8; 4], Vec<Function>>,
+    labels: &HashMap<Address, String>,
+    format_amounts: bool,
+) -> String {
+    let calls = calls
+        .iter()
+        .map(|(target, data)| {
+            let decoded = data
+                .get(..SELECTOR_LEN)
+                .and_then(|selector| functions.get(selector))
+                .and_then(|funcs| {
+                    funcs.iter().find_map(|func| {
+                        func.decode_input(&data[SELECTOR_LEN..]).ok().map(|tokens| (func, tokens))
+                    })
+                });
+            let call = match decoded {
+                Some((func, tokens)) => {
+                    let mut args = tokens
+                        .iter()
+                        .zip(func.inputs.iter())
+                        .map(|(token, param)| label_param(token, param, labels, format_amounts))
+                        .collect::<Vec<_>>();
+                    if let (Some(calls), Some(arg)) =
+                        (multicall_calls(&func.name, &tokens), args.last_mut())
+                    {
+                        *arg = format_multicall_calls(&calls, functions, labels, format_amounts);
+                    }
+                    format!("{}({})", func.name, args.join(", "))
+                }
+                None => format!("0x{}", hex::encode(data)),
+            };
+            match target {
+                Some(target) => format!(
+                    "{}::{}",
+                    labels.get(target).cloned().unwrap_or_else(|| to_checksum(target, None)),
+                    call
+                ),
+                None => call,
+            }
+        })
+        .collect::<Vec<_>>();
+    format!("[{}]", calls.join(", "))
+}
+
+/// Decodes the revert data of a call, which is tried to be decoded as, in order:
+/// - one of the custom `errors`, with the parameters shown with their names and labels applied,
+///   e.g. `Unauthorized(caller: alice: [0x...])`
+/// - a revert reason string, also of a failed cheatcode, which is quoted, e.g. `"not owner"`, or no
+///   data at all for reverts, which is shown as `<empty revert data>` to tell it apart from
+///   `revert("")`, see [RevertData]
+/// - the return values of one of the `funcs`, for calls that revert with plain return data, which
+///   unlike errors is not prefixed with a selector, so its length is a multiple of 32 bytes
+/// - a panic or any other error [decode::decode_revert_with] recognizes
+pub(crate) fn decode_revert(
+    err: &[u8],
+    funcs: &[Function],
+    errors: &ErrorsBySelector,
+    labels: &HashMap<Address, String>,
+    format_amounts: bool,
+    status: Option<Return>,
+) -> Option<String> {
+    let revert = RevertData::new(err);
+    let reverted = status.map_or(true, |status| matches!(status, return_revert!()));
+    decode_custom_error(err, errors, labels)
+        .or_else(|| match &revert {
+            // halts without data, e.g. running out of gas, are shown with their status below
+            RevertData::Empty if !reverted => None,
+            RevertData::Empty | RevertData::Reason(_) => Some(revert.to_string()),
+            _ => None,
+        })
+        .or_else(|| {
+            (err.len() % 32 == 0)
+                .then(|| {
+                    funcs.iter().find_map(|func| decode_output(func, err, labels, format_amounts))
+                })
+                .flatten()
+        })
+        .or_else(|| decode::decode_revert_with(err, Some(errors), status).ok())
+}
+
+/// Decodes the return data of a call to `func`, `None` if it can't be decoded or `func` has no
+/// outputs, e.g. if it comes from a signature database.
+///
+/// If `format_amounts` is set, amounts of wei are shown in ether, see [label_param]
+pub(crate) fn decode_output(
+    func: &Function,
+    data: &[u8],
+    labels: &HashMap<Address, String>,
+    format_amounts: bool,
+) -> Option<String> {
+    let tokens = func.decode_output(data).ok().filter(|tokens| !tokens.is_empty())?;
+    Some(
+        tokens
+            .iter()
+            .zip(func.outputs.iter())
+            .map(|(token, param)| label_param(token, param, labels, format_amounts))
+            .collect::<Vec<_>>()
+            .join(", "),
+    )
+}
+
+/// Decodes `err` as one of the custom `errors`, labeling the decoded parameters
+fn decode_custom_error(
+    err: &[u8],
+    errors: &ErrorsBySelector,
+    labels: &HashMap<Address, String>,
+) -> Option<String> {
+    let (error, tokens) = decode::decode_abi_error(err, errors)?;
+    let params = error
+        .inputs
+        .iter()
+        .zip(tokens.iter())
+        .map(|(param, token)| {
+            let value = label(token, labels);
+            if param.name.is_empty() {
+                value
+            } else {
+                format!("{}: {}", param.name, value)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    Some(format!("{}({})", error.name, params))
+}
+
+/// Returns the gas cost of a successful call to the precompile at `address` with `input`, as of
+/// the Berlin hardfork, `None` if `address` is not a precompile.
+///
+/// The costs of the hash functions, `modexp` (EIP-2565) and `ecPairing` (EIP-1108) depend on the
+/// input, while the costs of `ecrecover`, `ecAdd` and `ecMul` (EIP-1108) are fixed.
+pub(crate) fn precompile_gas_cost(address: &Address, input: &[u8]) -> Option<u64> {
+    let (prefix, last) = address.as_bytes().split_at(19);
+    if prefix.iter().any(|byte| *byte != 0) {
+        return None
+    }
+    let words = (input.len() as u64 + 31) / 32;
+    let cost = match last[0] {
+        0x01 => 3000,
+        0x02 => 60 + 12 * words,
+        0x03 => 600 + 120 * words,
+        0x04 => 15 + 3 * words,
+        0x05 => modexp_gas_cost(input),
+        0x06 => 150,
+        0x07 => 6000,
+        0x08 => 45_000 + 34_000 * (input.len() as u64 / 192),
+        // the number of rounds
+        0x09 => u32::from_be_bytes(padded(input, 0, 4).try_into().expect("4 bytes")) as u64,
+        _ => return None,
+    };
+    Some(cost)
+}
+
+/// Returns the gas cost of the `modexp` precompile, see EIP-2565
+fn modexp_gas_cost(input: &[u8]) -> u64 {
+    let length = |offset| U256::from_big_endian(&padded(input, offset, 32));
+    let (base_len, exp_len, mod_len) = (length(0), length(32), length(64));
+
+    // Lengths that don't fit into 32 bits make the call run out of gas anyway
+    let limit = U256::from(u32::MAX);
+    if base_len > limit || exp_len > limit || mod_len > limit {
+        return u64::MAX
+    }
+    let (base_len, exp_len, mod_len) = (base_len.as_u64(), exp_len.as_u64(), mod_len.as_u64());
+
+    let words = U256::from((base_len.max(mod_len) + 7) / 8);
+    let multiplication_complexity = words * words;
+
+    // The bit length of the first 32 bytes of the exponent, plus 8 bits per byte of the remainder
+    let exp_head = U256::from_big_endian(&padded(
+        input,
+        96usize.saturating_add(base_len as usize),
+        exp_len.min(32) as usize,
+    ));
+    let exp_head_bits = exp_head.bits().saturating_sub(1) as u64;
+    let iteration_count =
+        if exp_len <= 32 { exp_head_bits } else { 8 * (exp_len - 32) + exp_head_bits };
+
+    let cost = multiplication_complexity * U256::from(iteration_count.max(1)) / 3;
+    if cost > U256::from(u64::MAX) {
+        u64::MAX
+    } else {
+        cost.as_u64().max(200)
+    }
+}
+
+/// Returns `len` bytes of `input` at `offset`, padded with zeros where `input` is too short
+fn padded(input: &[u8], offset: usize, len: usize) -> Vec<u8> {
+    let mut bytes = vec![0; len];
+    if let Some(available) = input.get(offset..) {
+        let n = available.len().min(len);
+        bytes[..n].copy_from_slice(&available[..n]);
+    }
+    bytes
+}
+
+/// Returns a readable description of the status of a call or step, e.g. `"out of gas"`
+#[allow(unreachable_patterns)]
+pub(crate) fn status_description(status: Return) -> &'static str {
+    match status {
+        Return::Continue => "running",
+        Return::Stop => "stopped",
+        Return::Return => "returned",
+        Return::SelfDestruct => "self-destructed",
+        Return::Revert => "reverted",
+        Return::CallTooDeep => "call too deep",
+        Return::OutOfFund => "insufficient balance",
+        Return::OutOfGas => "out of gas",
+        Return::OpcodeNotFound => "opcode not found",
+        Return::CallNotAllowedInsideStatic => "state change in static call",
+        // the designated INVALID opcode, e.g. failed `assert`s before solidity 0.8
+        Return::InvalidOpcode => "invalid opcode: INVALID",
+        Return::InvalidJump => "invalid jump destination",
+        Return::InvalidMemoryRange => "invalid memory range",
+        Return::NotActivated => "opcode not activated",
+        Return::StackUnderflow => "stack underflow",
+        Return::StackOverflow => "stack overflow",
+        Return::OutOfOffset => "return data out of bounds",
+        Return::FatalExternalError => "fatal external error",
+        Return::GasMaxFeeGreaterThanPriorityFee => "max priority fee greater than max fee",
+        Return::GasPriceLessThenBasefee => "gas price less than base fee",
+        Return::CallerGasLimitMoreThenBlock => "gas limit greater than block gas limit",
+        Return::RejectCallerWithCode => "sender has code",
+        Return::LackOfFundForGasLimit => "insufficient funds for gas",
+        Return::CreateCollision => "contract address collision",
+        Return::OverflowPayment => "payment overflow",
+        Return::PrecompileError => "precompile error",
+        Return::CreateContractLimit => "contract size limit exceeded",
+        // e.g. variants added by later revm versions
+        _ => "failed",
+    }
+}
+
+/// Returns the error message geth emits for a step or call that failed with the given status.
+///
+/// `op` is the opcode the step or call failed at, if known, which tells the designated INVALID
+/// opcode apart from undefined opcodes.
+pub(crate) fn geth_error(status: Return, op: Option<Instruction>) -> String {
+    match status {
+        Return::OpcodeNotFound | Return::InvalidOpcode => match op {
+            Some(Instruction::OpCode(op)) if op != opcode::INVALID => {
+                format!("invalid opcode: opcode {op:#x} not defined")
+            }
+            // revm halts with `InvalidOpcode` on the INVALID opcode
+            _ if matches!(status, Return::InvalidOpcode) || op.is_some() => {
+                "invalid opcode: INVALID".to_string()
+            }
+            _ => "invalid opcode".to_string(),
+        },
+        Return::OutOfGas => "out of gas".to_string(),
+        Return::Revert => "execution reverted".to_string(),
+        Return::CallTooDeep => "max call depth exceeded".to_string(),
+        Return::OutOfFund => "insufficient balance for transfer".to_string(),
+        Return::StackOverflow => "stack limit reached".to_string(),
+        Return::CallNotAllowedInsideStatic => "write protection".to_string(),
+        _ => status_description(status).to_string(),
     }
-    None
 }
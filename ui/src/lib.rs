@@ -375,6 +375,7 @@ impl Tui {
                 CallKind::StaticCall => "Contract staticcall",
                 CallKind::CallCode => "Contract callcode",
                 CallKind::DelegateCall => "Contract delegatecall",
+                CallKind::AuthCall => "Contract authcall",
             })
             .borders(Borders::ALL);
 
@@ -386,19 +387,17 @@ impl Tui {
             {
                 let pc_ic_map = pc_ic_maps.get(contract_name);
                 // grab either the creation source map or runtime sourcemap
-                if let Some((sourcemap, ic)) =
-                    if matches!(call_kind, CallKind::Create | CallKind::Create2) {
-                        known.bytecode.source_map().zip(pc_ic_map.and_then(|(c, _)| c.get(&pc)))
-                    } else {
-                        known
-                            .deployed_bytecode
-                            .bytecode
-                            .as_ref()
-                            .expect("no bytecode")
-                            .source_map()
-                            .zip(pc_ic_map.and_then(|(_, r)| r.get(&pc)))
-                    }
-                {
+                if let Some((sourcemap, ic)) = if call_kind.is_create() {
+                    known.bytecode.source_map().zip(pc_ic_map.and_then(|(c, _)| c.get(&pc)))
+                } else {
+                    known
+                        .deployed_bytecode
+                        .bytecode
+                        .as_ref()
+                        .expect("no bytecode")
+                        .source_map()
+                        .zip(pc_ic_map.and_then(|(_, r)| r.get(&pc)))
+                } {
                     match sourcemap {
                         Ok(sourcemap) => {
                             // we are handed a vector of SourceElements that give